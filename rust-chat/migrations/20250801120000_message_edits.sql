@@ -0,0 +1,15 @@
+-- migrations/{timestamp}_message_edits.sql
+
+-- Set whenever the author edits a message; NULL means never edited.
+ALTER TABLE messages ADD COLUMN edited_at TIMESTAMPTZ;
+
+-- Message Revisions Table
+-- Every edit stores the text the message had *before* the edit.
+CREATE TABLE message_revisions (
+    id BIGSERIAL PRIMARY KEY,
+    message_id BIGINT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    content TEXT NOT NULL,
+    edited_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_message_revisions_message_id ON message_revisions(message_id);
@@ -0,0 +1,15 @@
+-- migrations/{timestamp}_message_deletion.sql
+
+-- Soft-delete columns. A deleted message keeps its row as a tombstone so
+-- history stays consistent; `deleted_by` may be the author or a moderator.
+ALTER TABLE messages ADD COLUMN deleted_at TIMESTAMPTZ;
+ALTER TABLE messages ADD COLUMN deleted_by UUID REFERENCES users(id) ON DELETE SET NULL;
+
+-- Room Moderators Table
+-- Moderators may delete other users' messages in their room.
+CREATE TABLE room_moderators (
+    room_id UUID NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (room_id, user_id)
+);
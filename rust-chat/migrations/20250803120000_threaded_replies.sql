@@ -0,0 +1,7 @@
+-- migrations/{timestamp}_threaded_replies.sql
+
+-- Replies point at the top-level message that started their thread.
+-- Top-level messages have a NULL parent.
+ALTER TABLE messages ADD COLUMN parent_id BIGINT REFERENCES messages(id) ON DELETE CASCADE;
+
+CREATE INDEX idx_messages_parent_id ON messages(parent_id);
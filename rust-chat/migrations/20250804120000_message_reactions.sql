@@ -0,0 +1,12 @@
+-- migrations/{timestamp}_message_reactions.sql
+
+-- Message Reactions Table
+-- A user can react to a message with any number of distinct emoji,
+-- but only once per emoji.
+CREATE TABLE message_reactions (
+    message_id BIGINT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    emoji VARCHAR(64) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (message_id, user_id, emoji)
+);
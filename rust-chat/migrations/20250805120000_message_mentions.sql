@@ -0,0 +1,15 @@
+-- migrations/{timestamp}_message_mentions.sql
+
+-- Message Mentions Table
+-- One row per `@username` mention; `acknowledged_at` stays NULL until the
+-- mentioned user has seen it.
+CREATE TABLE message_mentions (
+    message_id BIGINT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    acknowledged_at TIMESTAMPTZ,
+    PRIMARY KEY (message_id, user_id)
+);
+
+CREATE INDEX idx_message_mentions_unacknowledged
+    ON message_mentions(user_id) WHERE acknowledged_at IS NULL;
@@ -0,0 +1,16 @@
+-- migrations/{timestamp}_direct_messages.sql
+
+-- Direct-message conversations are rooms flagged as direct. Their name is
+-- derived from the two participant ids, so the UNIQUE constraint on
+-- `rooms.name` guarantees one conversation per pair of users.
+ALTER TABLE rooms ADD COLUMN is_direct BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Direct Participants Table
+-- The only users allowed to join or read a direct room.
+CREATE TABLE direct_participants (
+    room_id UUID NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    PRIMARY KEY (room_id, user_id)
+);
+
+CREATE INDEX idx_direct_participants_user_id ON direct_participants(user_id);
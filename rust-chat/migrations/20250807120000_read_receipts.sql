@@ -0,0 +1,14 @@
+-- migrations/{timestamp}_read_receipts.sql
+
+-- Room Reads Table
+-- The newest message each user has read in each room. Messages with a
+-- higher id are unread.
+CREATE TABLE room_reads (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    room_id UUID NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    last_read_message_id BIGINT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, room_id)
+);
+
+CREATE INDEX idx_messages_room_id_id ON messages(room_id, id);
@@ -0,0 +1,12 @@
+-- migrations/{timestamp}_pinned_messages.sql
+
+-- Pinned Messages Table
+-- A message is pinned at most once; only room moderators may pin or unpin.
+CREATE TABLE pinned_messages (
+    message_id BIGINT PRIMARY KEY REFERENCES messages(id) ON DELETE CASCADE,
+    room_id UUID NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    pinned_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    pinned_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_pinned_messages_room_id ON pinned_messages(room_id);
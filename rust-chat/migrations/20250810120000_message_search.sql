@@ -0,0 +1,8 @@
+-- migrations/{timestamp}_message_search.sql
+
+-- Full-text search vector, kept in sync with `content` by Postgres itself.
+ALTER TABLE messages
+    ADD COLUMN content_tsv TSVECTOR
+    GENERATED ALWAYS AS (to_tsvector('english', content)) STORED;
+
+CREATE INDEX idx_messages_content_tsv ON messages USING GIN (content_tsv);
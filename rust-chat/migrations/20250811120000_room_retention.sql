@@ -0,0 +1,7 @@
+-- migrations/{timestamp}_room_retention.sql
+
+-- Messages older than this many days are purged by the retention task.
+-- NULL keeps history forever.
+ALTER TABLE rooms ADD COLUMN retention_days INTEGER CHECK (retention_days > 0);
+
+CREATE INDEX idx_messages_created_at ON messages(created_at);
@@ -0,0 +1,16 @@
+-- migrations/{timestamp}_room_memberships.sql
+
+-- Room Memberships Table
+-- Users who joined a room, kept across reconnects and restarts.
+CREATE TABLE room_memberships (
+    room_id UUID NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    joined_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (room_id, user_id)
+);
+
+CREATE INDEX idx_room_memberships_user_id ON room_memberships(user_id);
+
+-- Participants of existing direct conversations are members of them.
+INSERT INTO room_memberships (room_id, user_id)
+SELECT room_id, user_id FROM direct_participants;
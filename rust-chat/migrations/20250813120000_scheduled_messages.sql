@@ -0,0 +1,20 @@
+-- migrations/{timestamp}_scheduled_messages.sql
+
+-- Scheduled Messages Table
+-- Messages waiting for their `send_at`. The scheduler sets `claimed_at` while
+-- it delivers a row and deletes the row once it is delivered, so everything
+-- here is still pending.
+CREATE TABLE scheduled_messages (
+    id BIGSERIAL PRIMARY KEY,
+    room_id UUID NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    content TEXT NOT NULL,
+    parent_id BIGINT REFERENCES messages(id) ON DELETE CASCADE,
+    attachment_ids UUID[] NOT NULL DEFAULT '{}',
+    send_at TIMESTAMPTZ NOT NULL,
+    claimed_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_scheduled_messages_send_at ON scheduled_messages(send_at);
+CREATE INDEX idx_scheduled_messages_user_id ON scheduled_messages(user_id);
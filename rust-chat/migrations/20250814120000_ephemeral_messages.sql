@@ -0,0 +1,12 @@
+-- migrations/{timestamp}_ephemeral_messages.sql
+
+-- Messages past `expires_at` are hard-deleted by the expiry task.
+ALTER TABLE messages ADD COLUMN expires_at TIMESTAMPTZ;
+
+CREATE INDEX idx_messages_expires_at ON messages(expires_at) WHERE expires_at IS NOT NULL;
+
+-- Default lifetime of new messages in a room, in seconds; NULL keeps them.
+ALTER TABLE rooms ADD COLUMN message_ttl_seconds INTEGER CHECK (message_ttl_seconds > 0);
+
+-- Per-message lifetime requested with a scheduled message, counted from delivery.
+ALTER TABLE scheduled_messages ADD COLUMN ttl_seconds INTEGER CHECK (ttl_seconds > 0);
@@ -0,0 +1,11 @@
+-- migrations/{timestamp}_drafts.sql
+
+-- Drafts Table
+-- A user's unsent message in a room, shared between their devices.
+CREATE TABLE drafts (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    room_id UUID NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    content TEXT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, room_id)
+);
@@ -0,0 +1,17 @@
+-- migrations/{timestamp}_bookmarks.sql
+
+-- Bookmarks Table
+-- Messages a user saved for later. A bookmark of a deleted message is kept as
+-- a tombstone until the message row itself is purged.
+CREATE TABLE bookmarks (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    message_id BIGINT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    note TEXT,
+    remind_at TIMESTAMPTZ,
+    reminded_at TIMESTAMPTZ, -- Set once the reminder has been sent.
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, message_id)
+);
+
+CREATE INDEX idx_bookmarks_message_id ON bookmarks(message_id);
+CREATE INDEX idx_bookmarks_remind_at ON bookmarks(remind_at) WHERE reminded_at IS NULL;
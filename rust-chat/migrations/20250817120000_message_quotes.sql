@@ -0,0 +1,9 @@
+-- migrations/{timestamp}_message_quotes.sql
+
+-- A forwarded or quoted message, copied when the message is sent so it is
+-- shown the same way to readers who cannot see the original's room.
+ALTER TABLE messages ADD COLUMN quote JSONB;
+
+-- Quoted message of a scheduled message, resolved again at delivery. If the
+-- quoted message is purged first, the message is delivered without a quote.
+ALTER TABLE scheduled_messages ADD COLUMN quote_id BIGINT REFERENCES messages(id) ON DELETE SET NULL;
@@ -0,0 +1,6 @@
+-- migrations/{timestamp}_imported_users.sql
+
+-- Slack user id of accounts created by `import-slack`. Imports match users
+-- by this id, never by username, so a Slack user is not merged into a local
+-- account that happens to share their name.
+ALTER TABLE users ADD COLUMN slack_id VARCHAR(255) UNIQUE;
@@ -0,0 +1,36 @@
+-- migrations/{timestamp}_message_summaries.sql
+
+-- Reactions of a message as sent to clients: one entry per emoji, in the
+-- order each was first used, flagging the ones `viewer_id` reacted with.
+CREATE FUNCTION message_reaction_summary(msg_id BIGINT, viewer_id UUID) RETURNS JSONB
+LANGUAGE sql STABLE AS $$
+    SELECT COALESCE(
+               jsonb_agg(
+                   jsonb_build_object('emoji', g.emoji, 'count', g.count, 'reacted', g.reacted)
+                   ORDER BY g.first_at
+               ),
+               '[]'::jsonb
+           )
+    FROM (
+        SELECT emoji, COUNT(*) AS count, bool_or(user_id = viewer_id) AS reacted,
+               MIN(created_at) AS first_at
+        FROM message_reactions
+        WHERE message_id = msg_id
+        GROUP BY emoji
+    ) g
+$$;
+
+-- Attachments of a message as sent to clients, in upload order.
+CREATE FUNCTION message_attachment_summary(msg_id BIGINT) RETURNS JSONB
+LANGUAGE sql STABLE AS $$
+    SELECT COALESCE(
+               jsonb_agg(
+                   jsonb_build_object('id', a.id, 'file_name', a.file_name,
+                                      'content_type', a.content_type, 'size_bytes', a.size_bytes)
+                   ORDER BY a.created_at
+               ),
+               '[]'::jsonb
+           )
+    FROM attachments a
+    WHERE a.message_id = msg_id
+$$;
@@ -0,0 +1,31 @@
+-- migrations_sqlite/{timestamp}_create_chat_tables.sql
+
+-- SQLite counterpart of migrations/20250721142638_create_chat_tables.sql.
+-- There is no pgcrypto here: UUIDs are generated by the server and stored as
+-- 16-byte BLOBs, and timestamps are RFC 3339 TEXT in UTC.
+
+-- Users Table
+CREATE TABLE users (
+    id BLOB PRIMARY KEY NOT NULL,
+    username VARCHAR(255) UNIQUE NOT NULL,
+    password_hash VARCHAR(255) NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+-- Rooms Table
+CREATE TABLE rooms (
+    id BLOB PRIMARY KEY NOT NULL,
+    name VARCHAR(255) UNIQUE NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+-- Messages Table
+-- AUTOINCREMENT keeps ids from being reused, like a Postgres sequence.
+CREATE TABLE messages (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    content TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    -- Foreign keys linking to users and rooms
+    user_id BLOB NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    room_id BLOB NOT NULL REFERENCES rooms(id) ON DELETE CASCADE
+);
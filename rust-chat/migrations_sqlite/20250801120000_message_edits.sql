@@ -0,0 +1,12 @@
+-- migrations_sqlite/{timestamp}_message_edits.sql
+
+ALTER TABLE messages ADD COLUMN edited_at TEXT;
+
+CREATE TABLE message_revisions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    message_id INTEGER NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    content TEXT NOT NULL,
+    edited_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE INDEX idx_message_revisions_message_id ON message_revisions(message_id);
@@ -0,0 +1,11 @@
+-- migrations_sqlite/{timestamp}_message_deletion.sql
+
+ALTER TABLE messages ADD COLUMN deleted_at TEXT;
+ALTER TABLE messages ADD COLUMN deleted_by BLOB REFERENCES users(id) ON DELETE SET NULL;
+
+CREATE TABLE room_moderators (
+    room_id BLOB NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    user_id BLOB NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    PRIMARY KEY (room_id, user_id)
+);
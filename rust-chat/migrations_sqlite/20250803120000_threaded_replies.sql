@@ -0,0 +1,5 @@
+-- migrations_sqlite/{timestamp}_threaded_replies.sql
+
+ALTER TABLE messages ADD COLUMN parent_id INTEGER REFERENCES messages(id) ON DELETE CASCADE;
+
+CREATE INDEX idx_messages_parent_id ON messages(parent_id);
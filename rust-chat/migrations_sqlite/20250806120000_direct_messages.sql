@@ -0,0 +1,11 @@
+-- migrations_sqlite/{timestamp}_direct_messages.sql
+
+ALTER TABLE rooms ADD COLUMN is_direct BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE direct_participants (
+    room_id BLOB NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    user_id BLOB NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    PRIMARY KEY (room_id, user_id)
+);
+
+CREATE INDEX idx_direct_participants_user_id ON direct_participants(user_id);
@@ -0,0 +1,11 @@
+-- migrations_sqlite/{timestamp}_read_receipts.sql
+
+CREATE TABLE room_reads (
+    user_id BLOB NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    room_id BLOB NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    last_read_message_id INTEGER NOT NULL,
+    updated_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    PRIMARY KEY (user_id, room_id)
+);
+
+CREATE INDEX idx_messages_room_id_id ON messages(room_id, id);
@@ -0,0 +1,10 @@
+-- migrations_sqlite/{timestamp}_pinned_messages.sql
+
+CREATE TABLE pinned_messages (
+    message_id INTEGER PRIMARY KEY REFERENCES messages(id) ON DELETE CASCADE,
+    room_id BLOB NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    pinned_by BLOB REFERENCES users(id) ON DELETE SET NULL,
+    pinned_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE INDEX idx_pinned_messages_room_id ON pinned_messages(room_id);
@@ -0,0 +1,14 @@
+-- migrations_sqlite/{timestamp}_attachments.sql
+
+CREATE TABLE attachments (
+    id BLOB PRIMARY KEY NOT NULL,
+    uploader_id BLOB NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    room_id BLOB NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    message_id INTEGER REFERENCES messages(id) ON DELETE SET NULL,
+    file_name VARCHAR(255) NOT NULL,
+    content_type VARCHAR(255) NOT NULL,
+    size_bytes INTEGER NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE INDEX idx_attachments_message_id ON attachments(message_id);
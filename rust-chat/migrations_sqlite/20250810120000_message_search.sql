@@ -0,0 +1,27 @@
+-- migrations_sqlite/{timestamp}_message_search.sql
+
+-- SQLite counterpart of the Postgres `content_tsv` column: an FTS5 index over
+-- `messages.content`, kept in sync by triggers. Words are split and compared
+-- like `storage/text_search.rs` does, without stemming, so the snippets built
+-- there mark exactly the words that matched.
+CREATE VIRTUAL TABLE message_search USING fts5(
+    content,
+    content = 'messages',
+    content_rowid = 'id',
+    tokenize = 'unicode61 remove_diacritics 0'
+);
+
+CREATE TRIGGER messages_search_insert AFTER INSERT ON messages BEGIN
+    INSERT INTO message_search (rowid, content) VALUES (new.id, new.content);
+END;
+
+CREATE TRIGGER messages_search_delete AFTER DELETE ON messages BEGIN
+    INSERT INTO message_search (message_search, rowid, content) VALUES ('delete', old.id, old.content);
+END;
+
+CREATE TRIGGER messages_search_update AFTER UPDATE OF content ON messages BEGIN
+    INSERT INTO message_search (message_search, rowid, content) VALUES ('delete', old.id, old.content);
+    INSERT INTO message_search (rowid, content) VALUES (new.id, new.content);
+END;
+
+INSERT INTO message_search (message_search) VALUES ('rebuild');
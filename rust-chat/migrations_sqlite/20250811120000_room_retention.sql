@@ -0,0 +1,5 @@
+-- migrations_sqlite/{timestamp}_room_retention.sql
+
+ALTER TABLE rooms ADD COLUMN retention_days INTEGER CHECK (retention_days > 0);
+
+CREATE INDEX idx_messages_created_at ON messages(created_at);
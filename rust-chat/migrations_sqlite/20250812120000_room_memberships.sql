@@ -0,0 +1,13 @@
+-- migrations_sqlite/{timestamp}_room_memberships.sql
+
+CREATE TABLE room_memberships (
+    room_id BLOB NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    user_id BLOB NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    joined_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    PRIMARY KEY (room_id, user_id)
+);
+
+CREATE INDEX idx_room_memberships_user_id ON room_memberships(user_id);
+
+INSERT INTO room_memberships (room_id, user_id)
+SELECT room_id, user_id FROM direct_participants;
@@ -0,0 +1,17 @@
+-- migrations_sqlite/{timestamp}_scheduled_messages.sql
+
+-- `attachment_ids` holds a JSON array, as SQLite has no array type.
+CREATE TABLE scheduled_messages (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    room_id BLOB NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    user_id BLOB NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    content TEXT NOT NULL,
+    parent_id INTEGER REFERENCES messages(id) ON DELETE CASCADE,
+    attachment_ids TEXT NOT NULL DEFAULT '[]',
+    send_at TEXT NOT NULL,
+    claimed_at TEXT,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE INDEX idx_scheduled_messages_send_at ON scheduled_messages(send_at);
+CREATE INDEX idx_scheduled_messages_user_id ON scheduled_messages(user_id);
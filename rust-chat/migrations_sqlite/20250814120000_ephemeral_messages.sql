@@ -0,0 +1,9 @@
+-- migrations_sqlite/{timestamp}_ephemeral_messages.sql
+
+ALTER TABLE messages ADD COLUMN expires_at TEXT;
+
+CREATE INDEX idx_messages_expires_at ON messages(expires_at) WHERE expires_at IS NOT NULL;
+
+ALTER TABLE rooms ADD COLUMN message_ttl_seconds INTEGER CHECK (message_ttl_seconds > 0);
+
+ALTER TABLE scheduled_messages ADD COLUMN ttl_seconds INTEGER CHECK (ttl_seconds > 0);
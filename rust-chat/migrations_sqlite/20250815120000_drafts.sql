@@ -0,0 +1,9 @@
+-- migrations_sqlite/{timestamp}_drafts.sql
+
+CREATE TABLE drafts (
+    user_id BLOB NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    room_id BLOB NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    content TEXT NOT NULL,
+    updated_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    PRIMARY KEY (user_id, room_id)
+);
@@ -0,0 +1,14 @@
+-- migrations_sqlite/{timestamp}_bookmarks.sql
+
+CREATE TABLE bookmarks (
+    user_id BLOB NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    message_id INTEGER NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    note TEXT,
+    remind_at TEXT,
+    reminded_at TEXT,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    PRIMARY KEY (user_id, message_id)
+);
+
+CREATE INDEX idx_bookmarks_message_id ON bookmarks(message_id);
+CREATE INDEX idx_bookmarks_remind_at ON bookmarks(remind_at) WHERE reminded_at IS NULL;
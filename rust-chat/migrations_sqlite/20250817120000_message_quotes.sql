@@ -0,0 +1,5 @@
+-- migrations_sqlite/{timestamp}_message_quotes.sql
+
+ALTER TABLE messages ADD COLUMN quote TEXT;
+
+ALTER TABLE scheduled_messages ADD COLUMN quote_id INTEGER REFERENCES messages(id) ON DELETE SET NULL;
@@ -0,0 +1,5 @@
+-- migrations_sqlite/{timestamp}_imported_users.sql
+
+ALTER TABLE users ADD COLUMN slack_id TEXT;
+
+CREATE UNIQUE INDEX idx_users_slack_id ON users(slack_id);
@@ -0,0 +1,49 @@
+// src/access.rs
+
+// Room visibility rules of the Postgres backend, behind `RoomStore::can_access`
+// and `RoomStore::is_moderator`.
+
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+/// Returns `true` if `user_id` may join and read `room_id`. Public rooms are
+/// open to every authenticated user; direct rooms only to their two
+/// participants. Unknown rooms are never accessible.
+pub async fn can_access_room(
+    pool: &PgPool,
+    room_id: Uuid,
+    user_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT (NOT r.is_direct OR EXISTS (
+                   SELECT 1 FROM direct_participants p
+                   WHERE p.room_id = r.id AND p.user_id = $2
+               )) AS "allowed!"
+        FROM rooms r
+        WHERE r.id = $1
+        "#,
+        room_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.is_some_and(|r| r.allowed))
+}
+
+/// Returns `true` if `user_id` moderates `room_id`. Takes any executor so it
+/// can run inside a caller's transaction.
+pub async fn is_room_moderator<'e, E: PgExecutor<'e>>(
+    executor: E,
+    room_id: Uuid,
+    user_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT EXISTS(SELECT 1 FROM room_moderators WHERE room_id = $1 AND user_id = $2) AS \"is_moderator!\"",
+        room_id,
+        user_id
+    )
+    .fetch_one(executor)
+    .await?;
+    Ok(row.is_moderator)
+}
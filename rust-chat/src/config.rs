@@ -1,12 +1,100 @@
 // src/config.rs
 
-use serde::Deserialize;
+use std::num::{NonZeroU32, NonZeroU64};
+
+use serde::{Deserialize, Serialize};
 
 // This struct must match the structure of the `[default.app]` table
 // in `Rocket.toml`. Rocket will automatically deserialize the configuration
-// into this struct.
+// into this struct. Intervals and batch sizes are `NonZero`, so a 0 that would
+// stall or crash a background task is rejected when the config is loaded.
 #[derive(Deserialize)]
 pub struct AppConfig {
     pub database_url: String,
     pub jwt_secret: String,
-}
\ No newline at end of file
+    // Apply pending migrations from `migrations/` at startup. Turn off when
+    // migrations are run separately; a schema newer than the binary is still refused.
+    #[serde(default = "default_run_migrations")]
+    pub run_migrations: bool,
+    // Where users, rooms and messages are kept.
+    #[serde(default)]
+    pub storage: StorageBackend,
+    // Database file used by `storage = "sqlite"`; created if missing.
+    #[cfg(feature = "sqlite")]
+    #[serde(default = "default_sqlite_path")]
+    pub sqlite_path: String,
+    // Directory where uploaded attachments are stored.
+    #[serde(default = "default_upload_dir")]
+    pub upload_dir: String,
+    // Largest accepted attachment, in bytes. Rocket's own `limits.file` and
+    // `limits.data-form` must be at least this large.
+    #[serde(default = "default_max_upload_bytes")]
+    pub max_upload_bytes: u64,
+    // MIME types (`type/subtype`) accepted for attachments.
+    #[serde(default = "default_allowed_mime_types")]
+    pub allowed_mime_types: Vec<String>,
+    // How often the retention task looks for expired messages, in seconds.
+    #[serde(default = "default_retention_sweep_secs")]
+    pub retention_sweep_secs: NonZeroU64,
+    // Maximum number of messages deleted per retention transaction.
+    #[serde(default = "default_retention_batch_size")]
+    pub retention_batch_size: NonZeroU32,
+    // How often the scheduler looks for scheduled messages that are due, in seconds.
+    #[serde(default = "default_scheduler_poll_secs")]
+    pub scheduler_poll_secs: NonZeroU64,
+    // How often ephemeral messages past their `expires_at` are deleted, in seconds.
+    #[serde(default = "default_expiry_poll_secs")]
+    pub expiry_poll_secs: NonZeroU64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    Postgres,
+    // Keeps everything in process and never connects to `database_url`.
+    Memory,
+    // Keeps everything in `sqlite_path`. Requires the `sqlite` cargo feature.
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+}
+
+#[cfg(feature = "sqlite")]
+fn default_sqlite_path() -> String {
+    "chat.db".to_string()
+}
+
+fn default_run_migrations() -> bool {
+    true
+}
+
+fn default_upload_dir() -> String {
+    "uploads".to_string()
+}
+
+fn default_max_upload_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_allowed_mime_types() -> Vec<String> {
+    ["image/png", "image/jpeg", "image/gif", "image/webp", "application/pdf", "text/plain"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect()
+}
+
+fn default_retention_sweep_secs() -> NonZeroU64 {
+    NonZeroU64::new(3600).unwrap()
+}
+
+fn default_retention_batch_size() -> NonZeroU32 {
+    NonZeroU32::new(1000).unwrap()
+}
+
+fn default_scheduler_poll_secs() -> NonZeroU64 {
+    NonZeroU64::new(5).unwrap()
+}
+
+fn default_expiry_poll_secs() -> NonZeroU64 {
+    NonZeroU64::new(5).unwrap()
+}
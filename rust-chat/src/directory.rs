@@ -0,0 +1,82 @@
+// src/directory.rs
+
+// Cache of user profiles, so hot paths like `SendMessage` do not look up the
+// sender's username on every message. It holds the users who are online:
+// entries are filled when a user connects and dropped when their last
+// connection closes, so the cache never outgrows the set of connected users.
+// Usernames cannot change, so an entry is never stale. The hit rate is
+// reported by `GET /api/diagnostics`.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use dashmap::DashMap;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::models::{UserId, UserRecord};
+use crate::storage::{StorageResult, UserStore};
+
+#[derive(Default)]
+pub struct UserDirectory {
+    usernames: DashMap<UserId, String>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct DirectoryStats {
+    entries: usize,
+    hits: u64,
+    misses: u64,
+    hit_rate: Option<f64>, // `None` until the first lookup.
+}
+
+impl UserDirectory {
+    pub fn insert(&self, user_id: UserId, username: String) {
+        self.usernames.insert(user_id, username);
+    }
+
+    /// Drops the profile of a user who went offline.
+    pub fn remove(&self, user_id: &UserId) {
+        self.usernames.remove(user_id);
+    }
+
+    /// The username of `user_id`, or `None` if the user does not exist.
+    pub async fn username(&self, users: &dyn UserStore, user_id: UserId) -> StorageResult<Option<String>> {
+        Ok(self.get_users(users, &[user_id]).await?.pop().map(|u| u.username))
+    }
+
+    /// Like `UserStore::get_users`, but only asks storage for the users that
+    /// are not cached, in a single call. Users read from storage are not
+    /// cached; they are offline.
+    pub async fn get_users(&self, users: &dyn UserStore, ids: &[Uuid]) -> StorageResult<Vec<UserRecord>> {
+        let mut found = Vec::with_capacity(ids.len());
+        let mut missing = Vec::new();
+        for id in ids {
+            match self.usernames.get(id) {
+                Some(username) => found.push(UserRecord { id: *id, username: username.clone() }),
+                None => missing.push(*id),
+            }
+        }
+        self.hits.fetch_add(found.len() as u64, Ordering::Relaxed);
+        self.misses.fetch_add(missing.len() as u64, Ordering::Relaxed);
+
+        if !missing.is_empty() {
+            found.extend(users.get_users(&missing).await?);
+        }
+        found.sort_by(|a, b| a.username.cmp(&b.username));
+        Ok(found)
+    }
+
+    pub fn stats(&self) -> DirectoryStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let lookups = hits + misses;
+        DirectoryStats {
+            entries: self.usernames.len(),
+            hits,
+            misses,
+            hit_rate: (lookups > 0).then(|| hits as f64 / lookups as f64),
+        }
+    }
+}
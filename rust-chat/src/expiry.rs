@@ -0,0 +1,63 @@
+// src/expiry.rs
+
+// Background deletion of ephemeral messages once their `expires_at` passes.
+// Unlike retention, which works in days, expiry runs every few seconds and
+// tells online members about each deletion.
+
+use std::path::PathBuf;
+
+use log::{error, info};
+use tokio::time::{interval, Duration, MissedTickBehavior};
+
+use crate::state::ChatServerState;
+use crate::storage::Storage;
+use crate::websocket::commands::{broadcast_to_room, MessageExpired};
+
+/// Most messages deleted per transaction; a sweep keeps going until none are left.
+const BATCH_SIZE: i64 = 500;
+
+/// Starts the expiry loop. It runs once right away and then every `poll_every`.
+pub fn spawn_expiry_task(storage: Storage, state: ChatServerState, upload_dir: String, poll_every: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = interval(poll_every);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            expire_due(&storage, &state, &upload_dir).await;
+        }
+    });
+}
+
+async fn expire_due(storage: &Storage, state: &ChatServerState, upload_dir: &str) {
+    loop {
+        // Replies are deleted with their thread, whether or not they expired themselves.
+        let expired = match storage.messages.expire_messages(BATCH_SIZE).await {
+            Ok(purged) => {
+                for id in purged.attachment_ids {
+                    let path = PathBuf::from(upload_dir).join(id.to_string());
+                    if let Err(e) = tokio::fs::remove_file(&path).await {
+                        error!("Failed to remove expired attachment {}: {}", path.display(), e);
+                    }
+                }
+                purged.messages
+            }
+            Err(e) => {
+                error!("Message expiry failed: {}", e);
+                return;
+            }
+        };
+        let count = expired.len() as i64;
+
+        for (id, room_id) in expired {
+            let room_id = room_id.to_string();
+            broadcast_to_room(state, &room_id, &MessageExpired::new(id, room_id.clone()));
+        }
+        if count > 0 {
+            info!("Deleted {} expired messages", count);
+        }
+
+        if count < BATCH_SIZE {
+            return;
+        }
+    }
+}
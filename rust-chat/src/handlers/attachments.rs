@@ -0,0 +1,199 @@
+// src/handlers/attachments.rs
+
+use std::path::PathBuf;
+
+use rocket::{
+    data::Capped, form::{Form, FromForm}, fs::TempFile, get, http::{ContentType, Header},
+    post, serde::json::Json, Responder, State,
+};
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::handlers::chat::ApiError;
+use crate::handlers::guard::AuthenticatedUser;
+use crate::models::AttachmentSummary;
+use crate::storage::{NewAttachment, Storage};
+
+#[derive(FromForm)]
+pub struct UploadForm<'r> {
+    room_id: String,
+    file: Capped<TempFile<'r>>,
+}
+
+/// Leading bytes of an upload inspected to identify its type.
+const SNIFF_BYTES: u64 = 8192;
+
+#[derive(Responder)]
+pub struct AttachmentDownload {
+    file: tokio::fs::File,
+    content_type: ContentType,
+    disposition: Header<'static>,
+    nosniff: Header<'static>, // Browsers must trust `content_type`, not guess.
+}
+
+/// Location of an attachment's bytes on disk. Files are named by id only, so
+/// client-supplied names never reach the filesystem.
+fn attachment_path(config: &AppConfig, id: Uuid) -> PathBuf {
+    PathBuf::from(&config.upload_dir).join(id.to_string())
+}
+
+/// Keeps a display-safe version of the client's file name: no path
+/// separators, quotes or control characters.
+fn sanitize_file_name(raw: &str) -> String {
+    let cleaned: String = raw
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, '.' | '-' | '_' | ' '))
+        .take(255)
+        .collect();
+    let trimmed = cleaned.trim().trim_start_matches('.');
+    if trimmed.is_empty() { "attachment".to_string() } else { trimmed.to_string() }
+}
+
+// POST /api/attachments (multipart/form-data with `room_id` and `file`)
+// Stores the file for later use in a `send_message` command via `attachment_ids`.
+#[post("/attachments", data = "<form>")]
+pub async fn upload_attachment(
+    mut form: Form<UploadForm<'_>>,
+    user: AuthenticatedUser,
+    config: &State<AppConfig>,
+    storage: &State<Storage>,
+) -> Result<Json<AttachmentSummary>, ApiError> {
+    let room_uuid = Uuid::parse_str(&form.room_id)
+        .map_err(|e| ApiError::NotFound(format!("Invalid room_id: {}", e)))?;
+    let allowed = storage.rooms.can_access(room_uuid, user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if !allowed {
+        return Err(ApiError::Forbidden("You cannot post in this room.".to_string()));
+    }
+
+    // An incomplete file means Rocket's own limit cut the upload short.
+    let size = form.file.len();
+    if !form.file.is_complete() || size > config.max_upload_bytes {
+        return Err(ApiError::TooLarge(format!(
+            "Attachments may be at most {} bytes.",
+            config.max_upload_bytes
+        )));
+    }
+
+    let declared = form
+        .file
+        .content_type()
+        .map(|ct| format!("{}/{}", ct.top(), ct.sub()).to_lowercase())
+        .unwrap_or_default();
+
+    // The declared type must match the file's magic bytes. Types without a
+    // signature, such as plain text, are taken as declared.
+    let mut head = Vec::new();
+    form.file
+        .open()
+        .await
+        .map_err(|e| ApiError::Internal(format!("Failed to read upload: {}", e)))?
+        .take(SNIFF_BYTES)
+        .read_to_end(&mut head)
+        .await
+        .map_err(|e| ApiError::Internal(format!("Failed to read upload: {}", e)))?;
+    let content_type = match infer::get(&head) {
+        Some(kind) if kind.mime_type() == declared => declared,
+        None if !infer::is_mime_supported(&declared) => declared,
+        _ => {
+            return Err(ApiError::UnsupportedType(format!(
+                "The file's contents do not match its type '{}'.",
+                declared
+            )))
+        }
+    };
+
+    if !config.allowed_mime_types.iter().any(|allowed| allowed.eq_ignore_ascii_case(&content_type)) {
+        return Err(ApiError::UnsupportedType(format!(
+            "Attachments of type '{}' are not allowed.",
+            content_type
+        )));
+    }
+
+    let file_name = sanitize_file_name(
+        form.file
+            .raw_name()
+            .map(|name| name.dangerous_unsafe_unsanitized_raw().as_str())
+            .unwrap_or(""),
+    );
+
+    // Store the bytes first, so a recorded attachment always has its file.
+    let id = Uuid::new_v4();
+    let path = attachment_path(config.inner(), id);
+    tokio::fs::create_dir_all(&config.upload_dir)
+        .await
+        .map_err(|e| ApiError::Internal(format!("Failed to prepare upload dir: {}", e)))?;
+    form.file
+        .move_copy_to(&path)
+        .await
+        .map_err(|e| ApiError::Internal(format!("Failed to store attachment: {}", e)))?;
+
+    let attachment = NewAttachment {
+        id,
+        uploader_id: user.user_id,
+        room_id: room_uuid,
+        file_name: &file_name,
+        content_type: &content_type,
+        size_bytes: size as i64,
+    };
+    if let Err(e) = storage.messages.insert_attachment(attachment).await {
+        let _ = tokio::fs::remove_file(&path).await;
+        return Err(ApiError::DatabaseError(e.to_string()));
+    }
+
+    Ok(Json(AttachmentSummary {
+        id,
+        file_name,
+        content_type,
+        size_bytes: size as i64,
+    }))
+}
+
+// GET /api/attachments/<attachment_id>
+// The uploader can always fetch their file; anyone else needs read access to
+// the room and the attachment must belong to a message that was not deleted.
+#[get("/attachments/<attachment_id>")]
+pub async fn download_attachment(
+    attachment_id: String,
+    user: AuthenticatedUser,
+    config: &State<AppConfig>,
+    storage: &State<Storage>,
+) -> Result<AttachmentDownload, ApiError> {
+    let id = Uuid::parse_str(&attachment_id)
+        .map_err(|e| ApiError::NotFound(format!("Invalid attachment id: {}", e)))?;
+
+    let attachment = storage
+        .messages
+        .attachment(id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+        .ok_or_else(|| ApiError::NotFound("Attachment not found.".to_string()))?;
+
+    if attachment.uploader_id != user.user_id {
+        let allowed = attachment.visible
+            && storage.rooms.can_access(attachment.room_id, user.user_id)
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+        if !allowed {
+            // Don't reveal that the attachment exists.
+            return Err(ApiError::NotFound("Attachment not found.".to_string()));
+        }
+    }
+
+    let file = tokio::fs::File::open(attachment_path(config.inner(), id))
+        .await
+        .map_err(|_| ApiError::NotFound("Attachment data is missing.".to_string()))?;
+
+    Ok(AttachmentDownload {
+        file,
+        content_type: ContentType::parse_flexible(&attachment.content_type)
+            .unwrap_or(ContentType::Binary),
+        disposition: Header::new(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", attachment.file_name),
+        ),
+        nosniff: Header::new("X-Content-Type-Options", "nosniff"),
+    })
+}
@@ -12,10 +12,10 @@ use rocket::{
     http::Status, post, request::Request, response::{self, Responder, Response}, serde::json::Json, State
 };
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
 use uuid::Uuid;
 // Import our new config struct
 use crate::config::AppConfig;
+use crate::storage::{Storage, StorageError};
 
 // ... (AuthPayload, AuthResponse, ErrorResponse structs remain the same) ...
 
@@ -71,8 +71,8 @@ impl<'r> Responder<'r, 'static> for AuthError {
             .ok()
     }
 }
-impl From<sqlx::Error> for AuthError {
-    fn from(_: sqlx::Error) -> Self { AuthError::ServerError }
+impl From<StorageError> for AuthError {
+    fn from(_: StorageError) -> Self { AuthError::ServerError }
 }
 impl From<argon2::password_hash::Error> for AuthError {
     fn from(_: argon2::password_hash::Error) -> Self { AuthError::InvalidCredentials }
@@ -107,7 +107,7 @@ fn create_token(user_id: Uuid, config: &AppConfig) -> Result<String, AuthError>
 
 #[post("/register", format = "json", data = "<payload>")]
 pub async fn register(
-    pool: &State<PgPool>,
+    storage: &State<Storage>,
     payload: Json<AuthPayload>,
     // The AppConfig is now injected by Rocket as managed state.
     config: &State<AppConfig>,
@@ -117,15 +117,11 @@ pub async fn register(
         .hash_password(payload.password.as_bytes(), &salt)?
         .to_string();
 
-    let user_id = sqlx::query!(
-        "INSERT INTO users (username, password_hash) VALUES ($1, $2) ON CONFLICT (username) DO NOTHING RETURNING id",
-        payload.username,
-        password_hash
-    )
-    .fetch_optional(pool.inner())
-    .await?
-    .ok_or(AuthError::UsernameExists)?
-    .id;
+    let user_id = storage
+        .users
+        .create_user(&payload.username, &password_hash)
+        .await?
+        .ok_or(AuthError::UsernameExists)?;
 
     // We pass the config to the create_token function.
     let token = create_token(user_id, config.inner())?;
@@ -134,18 +130,16 @@ pub async fn register(
 
 #[post("/login", format = "json", data = "<payload>")]
 pub async fn login(
-    pool: &State<PgPool>,
+    storage: &State<Storage>,
     payload: Json<AuthPayload>,
     // The AppConfig is also injected here.
     config: &State<AppConfig>,
 ) -> Result<Json<AuthResponse>, AuthError> {
-    let user = sqlx::query!(
-        "SELECT id, password_hash FROM users WHERE username = $1",
-        payload.username
-    )
-    .fetch_optional(pool.inner())
-    .await?
-    .ok_or(AuthError::InvalidCredentials)?;
+    let user = storage
+        .users
+        .find_credentials(&payload.username)
+        .await?
+        .ok_or(AuthError::InvalidCredentials)?;
 
     let parsed_hash = PasswordHash::new(&user.password_hash)?;
     Argon2::default().verify_password(payload.password.as_bytes(), &parsed_hash)?;
@@ -0,0 +1,96 @@
+// src/handlers/bookmarks.rs
+
+use chrono::{DateTime, Utc};
+use rocket::{delete, get, put, serde::json::Json, State};
+use serde::Deserialize;
+
+use crate::handlers::chat::ApiError;
+use crate::handlers::guard::AuthenticatedUser;
+use crate::models::BookmarkRecord;
+use crate::storage::Storage;
+
+#[derive(Deserialize)]
+pub struct BookmarkPayload {
+    #[serde(default)]
+    note: Option<String>,
+    // The caller is sent a `bookmark reminder` event at this time.
+    #[serde(default)]
+    remind_at: Option<DateTime<Utc>>,
+}
+
+// GET /api/users/me/bookmarks
+// Lists the caller's saved messages. Deleted messages stay listed as
+// tombstones, with no content, until they are purged.
+#[get("/users/me/bookmarks")]
+pub async fn list_bookmarks(
+    user: AuthenticatedUser,
+    storage: &State<Storage>,
+) -> Result<Json<Vec<BookmarkRecord>>, ApiError> {
+    let bookmarks = storage
+        .messages
+        .bookmarks(user.user_id, None)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(bookmarks))
+}
+
+// PUT /api/users/me/bookmarks/<message_id>
+// Bookmarks a message the caller can read, or replaces the note and reminder
+// of an existing bookmark. Changing the reminder re-arms it.
+#[put("/users/me/bookmarks/<message_id>", data = "<payload>")]
+pub async fn save_bookmark(
+    message_id: i64,
+    payload: Json<BookmarkPayload>,
+    user: AuthenticatedUser,
+    storage: &State<Storage>,
+) -> Result<Json<BookmarkRecord>, ApiError> {
+    let room_id = storage
+        .messages
+        .live_message_room(message_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+        .ok_or_else(|| ApiError::NotFound("Message not found.".to_string()))?;
+
+    let allowed = storage.rooms.can_access(room_id, user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if !allowed {
+        return Err(ApiError::Forbidden("You cannot read this message.".to_string()));
+    }
+
+    storage
+        .messages
+        .save_bookmark(user.user_id, message_id, payload.note.as_deref(), payload.remind_at)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    let bookmark = storage
+        .messages
+        .bookmarks(user.user_id, Some(message_id))
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+        .pop()
+        .ok_or_else(|| ApiError::NotFound("Message not found.".to_string()))?;
+
+    Ok(Json(bookmark))
+}
+
+// DELETE /api/users/me/bookmarks/<message_id>
+#[delete("/users/me/bookmarks/<message_id>")]
+pub async fn delete_bookmark(
+    message_id: i64,
+    user: AuthenticatedUser,
+    storage: &State<Storage>,
+) -> Result<(), ApiError> {
+    let deleted = storage
+        .messages
+        .delete_bookmark(user.user_id, message_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    if !deleted {
+        return Err(ApiError::NotFound("No bookmark for this message.".to_string()));
+    }
+    Ok(())
+}
@@ -1,26 +1,19 @@
-use rocket::{get, post, response, serde::json::Json, Responder, State};
+use rocket::{get, post, put, serde::json::Json, Responder, State};
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
 
 use uuid::Uuid;
-use chrono::{DateTime, Utc};
-use crate::{handlers::guard::AuthenticatedUser, state::ChatServerState};
+use crate::{
+    handlers::guard::AuthenticatedUser,
+    models::{MessageRecord, PinRecord, RoomListing, RoomRecord, UserRecord},
+    state::ChatServerState,
+    storage::{HistoryQuery, Storage},
+};
 
-
-#[derive(Serialize, sqlx::FromRow)]
-pub struct MessageRecord {
-    id: i64,
-    user_id: Uuid,
-    username: String, // We'll get this with a JOIN
-    room_id: String,
-    content: String,
-    created_at: DateTime<Utc>,
-}
-
-#[derive(Serialize, sqlx::FromRow)]
-pub struct UserRecord {
+#[derive(Serialize)]
+pub struct RoomMemberRecord {
     id: Uuid,
     username: String,
+    online: bool, // Has an open WebSocket connection right now.
 }
 
 #[derive(Responder)]
@@ -32,90 +25,179 @@ pub struct RoomError(String);
 pub enum ApiError {
     #[response(status = 500, content_type = "json")]
     DatabaseError(String),
+    #[response(status = 500, content_type = "json")]
+    Internal(String), // Server-side failures outside the database, such as file I/O.
     #[response(status = 404, content_type = "json")]
     NotFound(String),
+    #[response(status = 403, content_type = "json")]
+    Forbidden(String),
+    #[response(status = 400, content_type = "json")]
+    BadRequest(String),
+    #[response(status = 413, content_type = "json")]
+    TooLarge(String),
+    #[response(status = 415, content_type = "json")]
+    UnsupportedType(String),
 }
 
+// GET /api/rooms
+// Each room carries the caller's unread and unacknowledged-mention counts.
 #[get("/rooms")]
 pub async fn list_rooms(
-    _user: AuthenticatedUser,
-    pool: &State<PgPool>,
+    user: AuthenticatedUser,
+    storage: &State<Storage>,
 
-)-> Result<Json<Vec<RoomRecord>>, RoomError>{
-    let rooms = sqlx::query_as!(
-        RoomRecord,
-        "SELECT id, name FROM rooms ORDER BY name"
-    ).fetch_all(pool.inner())
+)-> Result<Json<Vec<RoomListing>>, RoomError>{
+    let rooms = storage.rooms.list_rooms(user.user_id)
         .await
         .map_err(|e| RoomError(e.to_string()))?;
     Ok(Json(rooms))
     }
 
 
-#[derive(Serialize, sqlx::FromRow)]
-pub struct RoomRecord{
-    id: Uuid,
-    name:String,
-}
-
 #[derive(Responder)]
 pub enum HistoryError{
     #[response(status = 500)]
     InternalError(String),
+    #[response(status = 404)]
+    NotFound(String),
+    #[response(status = 403)]
+    Forbidden(String),
 }
 
 #[derive(Deserialize)]
 pub struct CreateRoomPayload {
     name: String,
+    #[serde(default)]
+    retention_days: Option<i32>,
+    #[serde(default)]
+    message_ttl_seconds: Option<i32>,
+}
+
+#[derive(Deserialize)]
+pub struct RetentionPayload {
+    retention_days: Option<i32>, // `null` disables retention
 }
-// GET /api/history/<room_id>
+
+#[derive(Deserialize)]
+pub struct MessageTtlPayload {
+    message_ttl_seconds: Option<i32>, // `null` makes new messages permanent
+}
+/// Page size used when the client does not pass `limit`.
+const DEFAULT_HISTORY_LIMIT: i64 = 50;
+/// Upper bound on `limit`, so one request cannot pull a whole room.
+const MAX_HISTORY_LIMIT: i64 = 200;
+
+#[derive(Serialize)]
+pub struct HistoryPage {
+    messages: Vec<MessageRecord>,
+    // Pass as `before` (or `after`, when paging forwards) to get the next page.
+    // `None` once there is nothing more in that direction.
+    next_cursor: Option<i64>,
+}
+
+// GET /api/history/<room_id>?before=<id>&after=<id>&limit=<n>
 // The <room_id> in the path is captured and passed as an argument.
-#[get("/history/<room_id>")]
+// Only top-level messages are returned; replies are summarised by
+// `reply_count` and `last_reply_at` and fetched through the thread endpoint.
+//
+// Pages are ordered by message id: newest first by default or with `before`,
+// oldest first when `after` is given (both together select the range between).
+#[get("/history/<room_id>?<before>&<after>&<limit>")]
 pub async fn get_history(
     room_id: String,
-    _user: AuthenticatedUser,
-    pool: &State<PgPool>,
+    before: Option<i64>,
+    after: Option<i64>,
+    limit: Option<i64>,
+    user: AuthenticatedUser,
+    storage: &State<Storage>,
+) -> Result<Json<HistoryPage>, HistoryError> {
+    let limit = limit.unwrap_or(DEFAULT_HISTORY_LIMIT).clamp(1, MAX_HISTORY_LIMIT);
+    let room_uuid = Uuid::parse_str(&room_id)
+        .map_err(|e| HistoryError::InternalError(format!("Invalid room_id: {}", e)))?;
+    let allowed = storage.rooms.can_access(room_uuid, user.user_id)
+        .await
+        .map_err(|e| HistoryError::InternalError(e.to_string()))?;
+    if !allowed {
+        return Err(HistoryError::Forbidden("You cannot read this room.".to_string()));
+    }
+    let mut messages = storage
+        .messages
+        .history(HistoryQuery {
+            room_id: room_uuid,
+            viewer: user.user_id,
+            before,
+            after,
+            limit: limit + 1, // One extra row tells us whether another page exists.
+        })
+        .await
+        .map_err(|e| HistoryError::InternalError(e.to_string()))?;
+
+    let next_cursor = if messages.len() as i64 > limit {
+        messages.truncate(limit as usize);
+        messages.last().map(|m| m.id)
+    } else {
+        None
+    };
+    Ok(Json(HistoryPage { messages, next_cursor }))
+}
+
+// GET /api/history/<room_id>/threads/<message_id>
+// Returns the thread's root message followed by all replies, oldest first.
+#[get("/history/<room_id>/threads/<message_id>")]
+pub async fn get_thread(
+    room_id: String,
+    message_id: i64,
+    user: AuthenticatedUser,
+    storage: &State<Storage>,
 ) -> Result<Json<Vec<MessageRecord>>, HistoryError> {
     let room_uuid = Uuid::parse_str(&room_id)
         .map_err(|e| HistoryError::InternalError(format!("Invalid room_id: {}", e)))?;
-    let messages = sqlx::query_as!(
-        MessageRecord,
-        r#"
-        SELECT m.id, m.user_id, u.username, m.room_id, m.content, m.created_at
-        FROM messages m
-        JOIN users u ON m.user_id = u.id
-        WHERE m.room_id = $1
-        ORDER BY m.created_at DESC
-        LIMIT 50
-        "#,
-        room_uuid
-    )
-    .fetch_all(pool.inner())
-    .await
-    .map_err(|e| HistoryError::InternalError(e.to_string()))?;
-    Ok(Json(messages))
+    let allowed = storage.rooms.can_access(room_uuid, user.user_id)
+        .await
+        .map_err(|e| HistoryError::InternalError(e.to_string()))?;
+    if !allowed {
+        return Err(HistoryError::Forbidden("You cannot read this room.".to_string()));
+    }
+    let messages = storage
+        .messages
+        .thread(room_uuid, message_id, user.user_id)
+        .await
+        .map_err(|e| HistoryError::InternalError(e.to_string()))?;
+
+    // The first row must be the root itself; otherwise the id was not a
+    // top-level message in this room.
+    match messages.first() {
+        Some(root) if root.id == message_id && root.parent_id.is_none() => Ok(Json(messages)),
+        _ => Err(HistoryError::NotFound("Thread not found.".to_string())),
+    }
 }
 
 #[post("/rooms", data = "<payload>")]
 pub async fn create_room(
     payload: Json<CreateRoomPayload>,
-    _user: AuthenticatedUser, // Guard ensures the user is logged in.
-    pool: &State<PgPool>,
+    user: AuthenticatedUser, // Guard ensures the user is logged in.
+    storage: &State<Storage>,
 ) -> Result<Json<RoomRecord>, RoomError> {
-    
-    // We use `query_as!` to execute the INSERT and immediately return the newly
-    // created row, which we then map directly into our `RoomRecord` struct.
-    // The `ON CONFLICT (name) DO NOTHING` clause prevents duplicate room names.
-    // If a conflict occurs, the query does nothing and returns no rows.
-    let new_room = sqlx::query_as!(
-        RoomRecord,
-        "INSERT INTO rooms (name) VALUES ($1) RETURNING id, name",
-        payload.name
-    )
-    .fetch_optional(pool.inner()) // Use `fetch_optional` because a conflict returns no row.
-    .await
-    .map_err(|e| RoomError(e.to_string()))?
-    .ok_or_else(|| RoomError("A room with this name already exists.".to_string()))?;
+    // Direct conversations are created through `/api/dm` and own this prefix.
+    if payload.name.starts_with("dm:") {
+        return Err(RoomError("Room names starting with 'dm:' are reserved.".to_string()));
+    }
+
+    if payload.retention_days.is_some_and(|days| days <= 0) {
+        return Err(RoomError("retention_days must be positive.".to_string()));
+    }
+    if payload.message_ttl_seconds.is_some_and(|secs| secs <= 0) {
+        return Err(RoomError("message_ttl_seconds must be positive.".to_string()));
+    }
+
+    // The store returns no room when the name is already taken, and makes the
+    // creator the room's first moderator.
+    let new_room = storage
+        .rooms
+        .create_room(&payload.name, payload.retention_days, payload.message_ttl_seconds, user.user_id)
+        .await
+        .map_err(|e| RoomError(e.to_string()))?
+        .ok_or_else(|| RoomError("A room with this name already exists.".to_string()))?;
 
     // On success, return a 200 OK with the JSON of the newly created room record.
     Ok(Json(new_room))
@@ -124,36 +206,175 @@ pub async fn create_room(
 #[get("/rooms/<room_id>/members")]
 pub async fn get_room_members(
     room_id: String,
-    _user: AuthenticatedUser,       // Ensures the requester is logged in.
+    user: AuthenticatedUser,       // Ensures the requester is logged in.
     chat_state: &State<ChatServerState>, // Access to in-memory state.
-    pool: &State<PgPool>,            // Access to the database.
-) -> Result<Json<Vec<UserRecord>>, ApiError> { // Updated error type
-    
-    // Get the list of active user IDs from the in-memory state.
+    storage: &State<Storage>,        // Access to the user directory.
+) -> Result<Json<Vec<RoomMemberRecord>>, ApiError> { // Updated error type
+    let room_uuid = Uuid::parse_str(&room_id)
+        .map_err(|e| ApiError::NotFound(format!("Invalid room_id: {}", e)))?;
+    let allowed = storage.rooms.can_access(room_uuid, user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if !allowed {
+        return Err(ApiError::Forbidden("You cannot view this room.".to_string()));
+    }
+
+    // Get the member IDs from the in-memory state, which mirrors `room_memberships`.
     let member_ids = match chat_state.room_members.get(&room_id) {
         Some(members) => {
             members.iter().map(|id_ref| *id_ref.key()).collect::<Vec<Uuid>>()
         }
-        None => {
-            // If the room doesn't exist in memory, it's effectively not found.
-            return Err(ApiError::NotFound("Room not found or is empty.".to_string()));
-        }
+        None => Vec::new(), // Nobody has joined yet.
     };
   // Both branches must return the same type: Result<Json<...>, ApiError>.
     if member_ids.is_empty() {
         // If the room is empty, return an empty JSON array.
         Ok(Json(vec![]))
     } else {
-        // If there are members, query the database for their details.
-        let members = sqlx::query_as!(
-            UserRecord,
-            "SELECT id, username FROM users WHERE id = ANY($1)",
-            &member_ids
-        )
-        .fetch_all(pool.inner())
+        // If there are members, look up their details, from the user directory where possible.
+        let members = chat_state
+            .users
+            .get_users(storage.users.as_ref(), &member_ids)
+            .await
+            .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+            .into_iter()
+            .map(|u| RoomMemberRecord {
+                online: chat_state.connections.contains_key(&u.id),
+                id: u.id,
+                username: u.username,
+            })
+            .collect();
+
+        Ok(Json(members))
+    }
+}
+
+// POST /api/rooms/<room_id>/moderators/<user_id>
+// Only an existing moderator of the room may promote another user.
+#[post("/rooms/<room_id>/moderators/<user_id>")]
+pub async fn add_moderator(
+    room_id: String,
+    user_id: String,
+    user: AuthenticatedUser,
+    storage: &State<Storage>,
+) -> Result<Json<UserRecord>, ApiError> {
+    let room_uuid = Uuid::parse_str(&room_id)
+        .map_err(|e| ApiError::NotFound(format!("Invalid room_id: {}", e)))?;
+    let target_uuid = Uuid::parse_str(&user_id)
+        .map_err(|e| ApiError::NotFound(format!("Invalid user_id: {}", e)))?;
+
+    let is_moderator = storage.rooms.is_moderator(room_uuid, user.user_id)
         .await
         .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
 
-        Ok(Json(members))
+    if !is_moderator {
+        return Err(ApiError::Forbidden("Only room moderators can add moderators.".to_string()));
+    }
+
+    let target = storage
+        .users
+        .get_users(&[target_uuid])
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+        .pop()
+        .ok_or_else(|| ApiError::NotFound("User not found.".to_string()))?;
+
+    storage
+        .rooms
+        .add_moderator(room_uuid, target.id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(target))
+}
+
+// GET /api/rooms/<room_id>/pins
+// Pinned messages of a room, most recently pinned first.
+#[get("/rooms/<room_id>/pins")]
+pub async fn list_pins(
+    room_id: String,
+    user: AuthenticatedUser,
+    storage: &State<Storage>,
+) -> Result<Json<Vec<PinRecord>>, ApiError> {
+    let room_uuid = Uuid::parse_str(&room_id)
+        .map_err(|e| ApiError::NotFound(format!("Invalid room_id: {}", e)))?;
+    let allowed = storage.rooms.can_access(room_uuid, user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if !allowed {
+        return Err(ApiError::Forbidden("You cannot view this room.".to_string()));
+    }
+
+    let pins = storage
+        .messages
+        .list_pins(room_uuid)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(pins))
+}
+
+// PUT /api/rooms/<room_id>/retention
+// Lets a room moderator change how long messages are kept.
+#[put("/rooms/<room_id>/retention", data = "<payload>")]
+pub async fn set_retention(
+    room_id: String,
+    payload: Json<RetentionPayload>,
+    user: AuthenticatedUser,
+    storage: &State<Storage>,
+) -> Result<Json<RoomRecord>, ApiError> {
+    let room_uuid = Uuid::parse_str(&room_id)
+        .map_err(|e| ApiError::NotFound(format!("Invalid room_id: {}", e)))?;
+    if payload.retention_days.is_some_and(|days| days <= 0) {
+        return Err(ApiError::BadRequest("retention_days must be positive.".to_string()));
+    }
+
+    let is_moderator = storage.rooms.is_moderator(room_uuid, user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if !is_moderator {
+        return Err(ApiError::Forbidden("Only room moderators can change retention.".to_string()));
+    }
+
+    let room = storage
+        .rooms
+        .set_retention(room_uuid, payload.retention_days)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+        .ok_or_else(|| ApiError::NotFound("Room not found.".to_string()))?;
+
+    Ok(Json(room))
+}
+
+// PUT /api/rooms/<room_id>/ttl
+// Lets a room moderator make new messages ephemeral. Messages already sent
+// keep the lifetime they were sent with.
+#[put("/rooms/<room_id>/ttl", data = "<payload>")]
+pub async fn set_message_ttl(
+    room_id: String,
+    payload: Json<MessageTtlPayload>,
+    user: AuthenticatedUser,
+    storage: &State<Storage>,
+) -> Result<Json<RoomRecord>, ApiError> {
+    let room_uuid = Uuid::parse_str(&room_id)
+        .map_err(|e| ApiError::NotFound(format!("Invalid room_id: {}", e)))?;
+    if payload.message_ttl_seconds.is_some_and(|secs| secs <= 0) {
+        return Err(ApiError::BadRequest("message_ttl_seconds must be positive.".to_string()));
+    }
+
+    let is_moderator = storage.rooms.is_moderator(room_uuid, user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if !is_moderator {
+        return Err(ApiError::Forbidden("Only room moderators can change the message TTL.".to_string()));
     }
-}
\ No newline at end of file
+
+    let room = storage
+        .rooms
+        .set_message_ttl(room_uuid, payload.message_ttl_seconds)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+        .ok_or_else(|| ApiError::NotFound("Room not found.".to_string()))?;
+
+    Ok(Json(room))
+}
@@ -0,0 +1,46 @@
+// src/handlers/diagnostics.rs
+
+use rocket::{get, serde::json::Json, State};
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::config::{AppConfig, StorageBackend};
+use crate::directory::DirectoryStats;
+use crate::handlers::chat::ApiError;
+use crate::handlers::guard::AuthenticatedUser;
+use crate::schema::{self, SchemaStatus};
+use crate::state::ChatServerState;
+
+#[derive(Serialize)]
+pub struct Diagnostics {
+    storage: StorageBackend,
+    schema: Option<SchemaStatus>, // `None` unless the storage backend is Postgres.
+    user_directory: DirectoryStats,
+}
+
+// GET /api/diagnostics
+// Reports which schema a server runs against, for any signed-in user; the
+// details are not for anonymous callers. Reads the schema version live, since
+// migrations may be applied by hand when `run_migrations` is off. Also reports
+// how well the user directory cache is doing.
+#[get("/diagnostics")]
+pub async fn get_diagnostics(
+    _user: AuthenticatedUser,
+    config: &State<AppConfig>,
+    pool: &State<Option<PgPool>>, // Only connected for Postgres storage.
+    chat_state: &State<ChatServerState>,
+) -> Result<Json<Diagnostics>, ApiError> {
+    let schema = match pool.inner() {
+        Some(pool) => Some(
+            schema::status(pool)
+                .await
+                .map_err(|e| ApiError::DatabaseError(e.to_string()))?,
+        ),
+        None => None,
+    };
+    Ok(Json(Diagnostics {
+        storage: config.storage,
+        schema,
+        user_directory: chat_state.users.stats(),
+    }))
+}
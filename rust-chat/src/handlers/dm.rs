@@ -0,0 +1,76 @@
+// src/handlers/dm.rs
+
+use rocket::{get, post, serde::json::Json, State};
+use uuid::Uuid;
+
+use crate::handlers::chat::ApiError;
+use crate::handlers::guard::AuthenticatedUser;
+use crate::models::DirectConversation;
+use crate::state::ChatServerState;
+use crate::storage::Storage;
+
+// POST /api/dm/<user_id>
+// Returns the direct conversation between the caller and <user_id>,
+// creating it on first use.
+#[post("/dm/<user_id>")]
+pub async fn open_direct_message(
+    user_id: String,
+    user: AuthenticatedUser,
+    chat_state: &State<ChatServerState>,
+    storage: &State<Storage>,
+) -> Result<Json<DirectConversation>, ApiError> {
+    let other_id = Uuid::parse_str(&user_id)
+        .map_err(|e| ApiError::NotFound(format!("Invalid user_id: {}", e)))?;
+    if other_id == user.user_id {
+        return Err(ApiError::BadRequest("Cannot open a direct conversation with yourself.".to_string()));
+    }
+
+    let exists = !storage
+        .users
+        .get_users(&[other_id])
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+        .is_empty();
+    if !exists {
+        return Err(ApiError::NotFound("User not found.".to_string()));
+    }
+
+    let room_id = storage
+        .rooms
+        .open_direct(user.user_id, other_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    // Both participants are members from the start, so messages reach them over
+    // their existing connections without an explicit `join_room`.
+    let members = chat_state.room_members.entry(room_id.to_string()).or_default();
+    members.insert(user.user_id);
+    members.insert(other_id);
+
+    let conversation = storage
+        .rooms
+        .direct_conversations(user.user_id, Some(room_id))
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+        .pop()
+        .ok_or_else(|| ApiError::NotFound("User not found.".to_string()))?;
+
+    Ok(Json(conversation))
+}
+
+// GET /api/dm
+// Lists the caller's direct conversations with their unread and
+// unacknowledged-mention counts and the caller's drafts.
+#[get("/dm")]
+pub async fn list_direct_messages(
+    user: AuthenticatedUser,
+    storage: &State<Storage>,
+) -> Result<Json<Vec<DirectConversation>>, ApiError> {
+    let conversations = storage
+        .rooms
+        .direct_conversations(user.user_id, None)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(conversations))
+}
@@ -0,0 +1,267 @@
+// src/handlers/export.rs
+
+use std::future::Future;
+
+use chrono::{DateTime, Utc};
+use log::error;
+use rocket::async_stream::stream;
+use rocket::futures::Stream;
+use rocket::{
+    get, http::{ContentType, Header}, response::stream::ByteStream, Responder, State,
+};
+use uuid::Uuid;
+
+use crate::handlers::chat::ApiError;
+use crate::handlers::guard::AuthenticatedUser;
+use crate::models::ExportRow;
+use crate::storage::{Storage, StorageResult};
+
+#[derive(Clone, Copy)]
+enum ExportFormat {
+    Json,
+    Txt,
+    Csv,
+}
+
+impl ExportFormat {
+    fn parse(value: Option<&str>) -> Option<Self> {
+        match value.unwrap_or("json") {
+            "json" => Some(ExportFormat::Json),
+            "txt" => Some(ExportFormat::Txt),
+            "csv" => Some(ExportFormat::Csv),
+            _ => None,
+        }
+    }
+
+    fn content_type(self) -> ContentType {
+        match self {
+            ExportFormat::Json => ContentType::JSON,
+            ExportFormat::Txt => ContentType::Plain,
+            ExportFormat::Csv => ContentType::CSV,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Txt => "txt",
+            ExportFormat::Csv => "csv",
+        }
+    }
+}
+
+/// Messages read from storage at a time.
+const EXPORT_PAGE_SIZE: i64 = 500;
+
+#[derive(Responder)]
+pub struct Export<R> {
+    inner: R,
+    disposition: Header<'static>,
+}
+
+/// Quotes a CSV field when it contains a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn format_time(time: Option<DateTime<Utc>>) -> String {
+    time.map(|t| t.to_rfc3339()).unwrap_or_default()
+}
+
+/// Renders a single row; `first` tells JSON whether a separator is needed.
+fn render_row(format: ExportFormat, row: &ExportRow, first: bool) -> Vec<u8> {
+    match format {
+        ExportFormat::Json => {
+            let json = serde_json::to_string(row).unwrap_or_else(|_| "null".to_string());
+            if first { json.into_bytes() } else { format!(",{}", json).into_bytes() }
+        }
+        ExportFormat::Txt => {
+            let content = row.content.as_deref().unwrap_or("<message deleted>");
+            let reply = row.parent_id.map(|p| format!(" (reply to #{})", p)).unwrap_or_default();
+            let edited = if row.edited_at.is_some() && row.deleted_at.is_none() { " (edited)" } else { "" };
+            format!(
+                "[{}] #{} {}{}: {}{}\n",
+                row.created_at.to_rfc3339(),
+                row.id,
+                row.username,
+                reply,
+                content,
+                edited
+            )
+            .into_bytes()
+        }
+        ExportFormat::Csv => format!(
+            "{},{},{},{},{},{},{},{}\n",
+            row.id,
+            row.parent_id.map(|p| p.to_string()).unwrap_or_default(),
+            row.created_at.to_rfc3339(),
+            row.user_id,
+            csv_field(&row.username),
+            format_time(row.edited_at),
+            format_time(row.deleted_at),
+            csv_field(row.content.as_deref().unwrap_or(""))
+        )
+        .into_bytes(),
+    }
+}
+
+/// Renders a whole export, asking `next_page` for the rows after a given id
+/// until a short page comes back.
+///
+/// The response headers are sent before the first page is read, so a storage
+/// error cannot become an error status. Instead the transcript is left visibly
+/// incomplete: a JSON array is never closed, and text and CSV exports end with
+/// a line saying the export was aborted.
+fn transcript<F, Fut>(format: ExportFormat, mut next_page: F) -> impl Stream<Item = Vec<u8>>
+where
+    F: FnMut(i64) -> Fut,
+    Fut: Future<Output = StorageResult<Vec<ExportRow>>>,
+{
+    stream! {
+        match format {
+            ExportFormat::Json => yield b"[".to_vec(),
+            ExportFormat::Csv => {
+                yield b"id,parent_id,created_at,user_id,username,edited_at,deleted_at,content\n".to_vec()
+            }
+            ExportFormat::Txt => {}
+        }
+
+        let mut after = 0;
+        let mut first = true;
+        loop {
+            let page = match next_page(after).await {
+                Ok(page) => page,
+                Err(e) => {
+                    error!("Export aborted after message {}: {}", after, e);
+                    match format {
+                        ExportFormat::Json => {}
+                        ExportFormat::Txt | ExportFormat::Csv => {
+                            yield b"# export aborted: the remaining messages could not be read\n".to_vec()
+                        }
+                    }
+                    return;
+                }
+            };
+            for row in &page {
+                yield render_row(format, row, first);
+                first = false;
+            }
+            match page.last() {
+                Some(last) if page.len() as i64 == EXPORT_PAGE_SIZE => after = last.id,
+                _ => break,
+            }
+        }
+
+        if let ExportFormat::Json = format {
+            yield b"]".to_vec();
+        }
+    }
+}
+
+// GET /api/rooms/<room_id>/export?format=json|txt|csv
+// Streams the complete history of a room, oldest first, with usernames
+// resolved. Rows are read from storage a page at a time and sent as they
+// arrive, so memory use does not grow with the size of the room.
+#[get("/rooms/<room_id>/export?<format>")]
+pub async fn export_room(
+    room_id: String,
+    format: Option<&str>,
+    user: AuthenticatedUser,
+    storage: &State<Storage>,
+) -> Result<Export<(ContentType, ByteStream<impl Stream<Item = Vec<u8>>>)>, ApiError> {
+    let format = ExportFormat::parse(format)
+        .ok_or_else(|| ApiError::BadRequest("format must be one of json, txt, csv.".to_string()))?;
+    let room_uuid = Uuid::parse_str(&room_id)
+        .map_err(|e| ApiError::NotFound(format!("Invalid room_id: {}", e)))?;
+    let allowed = storage.rooms.can_access(room_uuid, user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+    if !allowed {
+        return Err(ApiError::Forbidden("You cannot read this room.".to_string()));
+    }
+
+    let room_name = storage
+        .rooms
+        .get_room(room_uuid)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?
+        .ok_or_else(|| ApiError::NotFound("Room not found.".to_string()))?
+        .name;
+
+    let storage = storage.inner().clone();
+    let stream = ByteStream::from(transcript(format, move |after| {
+        let storage = storage.clone();
+        async move { storage.messages.export_page(room_uuid, after, EXPORT_PAGE_SIZE).await }
+    }));
+
+    let file_name: String = room_name
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    Ok(Export {
+        inner: (format.content_type(), stream),
+        disposition: Header::new(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}.{}\"", file_name, format.extension()),
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::StorageError;
+    use rocket::futures::StreamExt;
+
+    fn row(id: i64) -> ExportRow {
+        ExportRow {
+            id,
+            parent_id: None,
+            user_id: Uuid::nil(),
+            username: "alice".to_string(),
+            content: Some(format!("message {}", id)),
+            created_at: Utc::now(),
+            edited_at: None,
+            deleted_at: None,
+        }
+    }
+
+    /// Serves one full page, then fails the next one.
+    async fn export_failing_second_page(format: ExportFormat) -> String {
+        let pages = transcript(format, |after| async move {
+            if after == 0 {
+                Ok((1..=EXPORT_PAGE_SIZE).map(row).collect())
+            } else {
+                Err(StorageError::Database(sqlx::Error::PoolClosed))
+            }
+        });
+        let bytes: Vec<u8> = pages.collect::<Vec<_>>().await.concat();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn complete_json_export_is_a_closed_array() {
+        let pages = transcript(ExportFormat::Json, |_| async { Ok(vec![row(1), row(2)]) });
+        let bytes: Vec<u8> = pages.collect::<Vec<_>>().await.concat();
+        let rows: Vec<serde_json::Value> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(rows.len(), 2);
+    }
+
+    #[rocket::async_test]
+    async fn failed_page_leaves_the_export_visibly_incomplete() {
+        let json = export_failing_second_page(ExportFormat::Json).await;
+        assert!(!json.ends_with(']'));
+        assert!(serde_json::from_str::<Vec<serde_json::Value>>(&json).is_err());
+
+        let txt = export_failing_second_page(ExportFormat::Txt).await;
+        assert!(txt.ends_with("# export aborted: the remaining messages could not be read\n"));
+
+        let csv = export_failing_second_page(ExportFormat::Csv).await;
+        assert_eq!(csv.lines().count(), 1 + EXPORT_PAGE_SIZE as usize + 1);
+        assert!(csv.lines().last().unwrap().starts_with("# export aborted"));
+    }
+}
@@ -0,0 +1,45 @@
+// src/handlers/mentions.rs
+
+use rocket::{get, post, serde::json::Json, State};
+
+use crate::handlers::chat::ApiError;
+use crate::handlers::guard::AuthenticatedUser;
+use crate::models::MentionRecord;
+use crate::storage::Storage;
+
+// GET /api/mentions
+// Lists the caller's unacknowledged mentions, newest first, so users who were
+// offline when they were mentioned can catch up.
+#[get("/mentions")]
+pub async fn list_mentions(
+    user: AuthenticatedUser,
+    storage: &State<Storage>,
+) -> Result<Json<Vec<MentionRecord>>, ApiError> {
+    let mentions = storage
+        .messages
+        .list_mentions(user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(mentions))
+}
+
+// POST /api/mentions/<message_id>/ack
+// Marks a single mention of the caller as seen.
+#[post("/mentions/<message_id>/ack")]
+pub async fn acknowledge_mention(
+    message_id: i64,
+    user: AuthenticatedUser,
+    storage: &State<Storage>,
+) -> Result<(), ApiError> {
+    let acknowledged = storage
+        .messages
+        .acknowledge_mention(message_id, user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    if !acknowledged {
+        return Err(ApiError::NotFound("No pending mention for this message.".to_string()));
+    }
+    Ok(())
+}
@@ -1,5 +1,13 @@
 // HTTP route handlers will go here later
 
+pub mod attachments;
 pub mod auth;
+pub mod bookmarks;
 pub mod guard;
-pub mod chat;
\ No newline at end of file
+pub mod chat;
+pub mod diagnostics;
+pub mod dm;
+pub mod export;
+pub mod mentions;
+pub mod scheduled;
+pub mod search;
\ No newline at end of file
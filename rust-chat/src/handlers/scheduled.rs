@@ -0,0 +1,46 @@
+// src/handlers/scheduled.rs
+
+use rocket::{delete, get, serde::json::Json, State};
+
+use crate::handlers::chat::ApiError;
+use crate::handlers::guard::AuthenticatedUser;
+use crate::models::ScheduledRecord;
+use crate::storage::Storage;
+
+// GET /api/scheduled
+// The caller's messages that have not been delivered yet, soonest first.
+#[get("/scheduled")]
+pub async fn list_scheduled(
+    user: AuthenticatedUser,
+    storage: &State<Storage>,
+) -> Result<Json<Vec<ScheduledRecord>>, ApiError> {
+    let scheduled = storage
+        .messages
+        .list_scheduled(user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    Ok(Json(scheduled))
+}
+
+// DELETE /api/scheduled/<id>
+// Cancels one of the caller's pending messages. Messages already delivered
+// are gone from the schedule, and ones being delivered can no longer be
+// stopped, so cancelling either is a 404.
+#[delete("/scheduled/<id>")]
+pub async fn cancel_scheduled(
+    id: i64,
+    user: AuthenticatedUser,
+    storage: &State<Storage>,
+) -> Result<(), ApiError> {
+    let cancelled = storage
+        .messages
+        .cancel_scheduled(id, user.user_id)
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    if !cancelled {
+        return Err(ApiError::NotFound("No pending scheduled message with this id.".to_string()));
+    }
+    Ok(())
+}
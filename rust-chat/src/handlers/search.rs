@@ -0,0 +1,90 @@
+// src/handlers/search.rs
+
+use chrono::{DateTime, Utc};
+use rocket::{get, serde::json::Json, State};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::handlers::chat::ApiError;
+use crate::handlers::guard::AuthenticatedUser;
+use crate::models::SearchResult;
+use crate::storage::{SearchQuery, Storage};
+
+/// Results per page when the client does not pass `limit`.
+const DEFAULT_SEARCH_LIMIT: i64 = 20;
+/// Upper bound on `limit`.
+const MAX_SEARCH_LIMIT: i64 = 100;
+
+#[derive(Serialize)]
+pub struct SearchPage {
+    results: Vec<SearchResult>,
+    // Pass as `offset` to fetch the next page; `None` on the last page.
+    next_offset: Option<i64>,
+}
+
+fn parse_optional_uuid(value: Option<&str>, field: &str) -> Result<Option<Uuid>, ApiError> {
+    value
+        .map(|v| Uuid::parse_str(v).map_err(|e| ApiError::BadRequest(format!("Invalid {}: {}", field, e))))
+        .transpose()
+}
+
+fn parse_optional_time(value: Option<&str>, field: &str) -> Result<Option<DateTime<Utc>>, ApiError> {
+    value
+        .map(|v| {
+            DateTime::parse_from_rfc3339(v)
+                .map(|t| t.with_timezone(&Utc))
+                .map_err(|e| ApiError::BadRequest(format!("Invalid {} (expected RFC 3339): {}", field, e)))
+        })
+        .transpose()
+}
+
+// GET /api/search?q=<text>&room_id=<uuid>&author_id=<uuid>&from=<rfc3339>&to=<rfc3339>&limit=<n>&offset=<n>
+// Searches message content in every room the caller may read, best match first.
+// `q` accepts web-search syntax: quoted phrases, `or`, and `-excluded` words.
+#[allow(clippy::too_many_arguments)]
+#[get("/search?<q>&<room_id>&<author_id>&<from>&<to>&<limit>&<offset>")]
+pub async fn search_messages(
+    q: &str,
+    room_id: Option<&str>,
+    author_id: Option<&str>,
+    from: Option<&str>,
+    to: Option<&str>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    user: AuthenticatedUser,
+    storage: &State<Storage>,
+) -> Result<Json<SearchPage>, ApiError> {
+    if q.trim().is_empty() {
+        return Err(ApiError::BadRequest("Search query must not be empty.".to_string()));
+    }
+    let room_id = parse_optional_uuid(room_id, "room_id")?;
+    let author_id = parse_optional_uuid(author_id, "author_id")?;
+    let from = parse_optional_time(from, "from")?;
+    let to = parse_optional_time(to, "to")?;
+    let limit = limit.unwrap_or(DEFAULT_SEARCH_LIMIT).clamp(1, MAX_SEARCH_LIMIT);
+    let offset = offset.unwrap_or(0).max(0);
+
+    let mut results = storage
+        .messages
+        .search(SearchQuery {
+            text: q,
+            viewer: user.user_id,
+            room_id,
+            author_id,
+            from,
+            to,
+            limit: limit + 1, // One extra row tells us whether another page exists.
+            offset,
+        })
+        .await
+        .map_err(|e| ApiError::DatabaseError(e.to_string()))?;
+
+    let next_offset = if results.len() as i64 > limit {
+        results.truncate(limit as usize);
+        Some(offset + limit)
+    } else {
+        None
+    };
+
+    Ok(Json(SearchPage { results, next_offset }))
+}
@@ -0,0 +1,3 @@
+// Offline data importers, run from the command line instead of the server.
+
+pub mod slack;
@@ -0,0 +1,341 @@
+// src/import/slack.rs
+
+// Imports a Slack workspace export (the zip produced by "Export data") into
+// the chat database:
+//
+//   users.json            -> `users` (matched by Slack id, created if missing)
+//   channels.json         -> `rooms` (one per channel)
+//   <channel>/<date>.json -> `messages`, keeping Slack timestamps and threads
+//
+// Usage: chat-backend import-slack <export.zip>
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+use zip::ZipArchive;
+
+/// Stored instead of an Argon2 hash for created users. It never parses as a
+/// valid hash, so imported accounts cannot log in until given a password.
+const IMPORTED_PASSWORD_HASH: &str = "!imported-from-slack";
+
+/// Message subtypes that carry conversation content; everything else
+/// (joins, topic changes, ...) is channel noise and skipped.
+const IMPORTED_SUBTYPES: &[&str] = &["thread_broadcast", "file_share", "me_message"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImportError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid zip archive: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("Invalid JSON in {file}: {source}")]
+    Json { file: String, source: serde_json::Error },
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+#[derive(Deserialize)]
+struct SlackUser {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct SlackChannel {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct SlackMessage {
+    #[serde(default)]
+    subtype: Option<String>,
+    #[serde(default)]
+    user: Option<String>,
+    #[serde(default)]
+    text: String,
+    ts: String,
+    #[serde(default)]
+    thread_ts: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub users_created: usize,
+    pub rooms_created: usize,
+    pub rooms_skipped: usize,
+    pub messages_imported: usize,
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(
+    archive: &mut ZipArchive<File>,
+    name: &str,
+) -> Result<T, ImportError> {
+    let mut buf = String::new();
+    archive.by_name(name)?.read_to_string(&mut buf)?;
+    serde_json::from_str(&buf).map_err(|source| ImportError::Json { file: name.to_string(), source })
+}
+
+/// Slack timestamps are "<unix seconds>.<microseconds>".
+fn parse_ts(ts: &str) -> Option<DateTime<Utc>> {
+    let (secs, micros) = ts.split_once('.').unwrap_or((ts, "0"));
+    let secs: i64 = secs.parse().ok()?;
+    let micros: u32 = format!("{:0<6}", micros).get(..6)?.parse().ok()?;
+    DateTime::from_timestamp(secs, micros * 1000)
+}
+
+/// Converts Slack markup to plain text: `<@U123>` becomes `@username` so
+/// mentions keep working, links lose their angle brackets, and the HTML
+/// entities Slack escapes are restored.
+fn convert_text(text: &str, users: &HashMap<String, ImportedUser>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        out.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('>') else {
+            out.push_str(&rest[start..]);
+            rest = "";
+            break;
+        };
+        let inner = &rest[start + 1..start + end];
+        let (target, label) = inner.split_once('|').unwrap_or((inner, ""));
+        if let Some(user_id) = target.strip_prefix('@') {
+            let name = users.get(user_id).map_or(user_id, |u| u.username.as_str());
+            out.push('@');
+            out.push_str(name);
+        } else if let Some(channel_id) = target.strip_prefix('#') {
+            out.push('#');
+            out.push_str(if label.is_empty() { channel_id } else { label });
+        } else if let Some(special) = target.strip_prefix('!') {
+            out.push('@');
+            out.push_str(special);
+        } else if label.is_empty() || label == target {
+            out.push_str(target);
+        } else {
+            out.push_str(&format!("{} ({})", label, target));
+        }
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    out.replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&")
+}
+
+/// A local account for a Slack user.
+struct ImportedUser {
+    id: Uuid,
+    username: String,
+}
+
+/// Maps Slack user ids to local accounts, creating those not imported before.
+/// A new account takes the Slack name, or `<name>-slack` (then `-slack-2`,
+/// ...) if a local user already has it.
+async fn import_users(
+    pool: &PgPool,
+    users: &[SlackUser],
+    summary: &mut ImportSummary,
+) -> Result<HashMap<String, ImportedUser>, ImportError> {
+    let mut imported = HashMap::new();
+    for user in users {
+        let existing = sqlx::query_as!(
+            ImportedUser,
+            "SELECT id, username FROM users WHERE slack_id = $1",
+            user.id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        let account = match existing {
+            Some(account) => account,
+            None => {
+                let mut attempt = 0;
+                loop {
+                    let username = match attempt {
+                        0 => user.name.clone(),
+                        1 => format!("{}-slack", user.name),
+                        n => format!("{}-slack-{}", user.name, n),
+                    };
+                    let created = sqlx::query!(
+                        r#"
+                        INSERT INTO users (username, password_hash, slack_id) VALUES ($1, $2, $3)
+                        ON CONFLICT (username) DO NOTHING
+                        RETURNING id
+                        "#,
+                        username,
+                        IMPORTED_PASSWORD_HASH,
+                        user.id
+                    )
+                    .fetch_optional(pool)
+                    .await?;
+
+                    if let Some(row) = created {
+                        if attempt > 0 {
+                            warn!("Username {} is taken; imported Slack user {} as {}", user.name, user.id, username);
+                        }
+                        summary.users_created += 1;
+                        break ImportedUser { id: row.id, username };
+                    }
+                    attempt += 1;
+                }
+            }
+        };
+        imported.insert(user.id.clone(), account);
+    }
+    Ok(imported)
+}
+
+/// Imports one channel in a single transaction. Returns `None` if a room with
+/// the same name already exists, so re-running an import never duplicates history.
+async fn import_channel(
+    pool: &PgPool,
+    name: &str,
+    messages: Vec<SlackMessage>,
+    users: &HashMap<String, ImportedUser>,
+) -> Result<Option<usize>, ImportError> {
+    let mut tx = pool.begin().await?;
+
+    let room_id = match sqlx::query!(
+        "INSERT INTO rooms (name) VALUES ($1) ON CONFLICT (name) DO NOTHING RETURNING id",
+        name
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    {
+        Some(row) => row.id,
+        None => return Ok(None),
+    };
+
+    // Split into thread roots / standalone messages and replies, since
+    // replies need the database id of their root.
+    let mut root_ts = Vec::new();
+    let mut root_users = Vec::new();
+    let mut root_contents = Vec::new();
+    let mut root_times = Vec::new();
+    let mut replies = Vec::new();
+    for message in messages {
+        if message.subtype.as_deref().is_some_and(|s| !IMPORTED_SUBTYPES.contains(&s)) {
+            continue;
+        }
+        let (Some(user_id), Some(created_at)) = (
+            message.user.as_deref().and_then(|u| users.get(u)).map(|u| &u.id),
+            parse_ts(&message.ts),
+        ) else {
+            continue;
+        };
+        let content = convert_text(&message.text, users);
+        match message.thread_ts.filter(|t| *t != message.ts) {
+            Some(thread_ts) => replies.push((*user_id, content, created_at, thread_ts)),
+            None => {
+                root_ts.push(message.ts);
+                root_users.push(*user_id);
+                root_contents.push(content);
+                root_times.push(created_at);
+            }
+        }
+    }
+
+    // Ids are drawn from the sequence up front so each can be returned next
+    // to the Slack timestamp of its message.
+    let inserted = sqlx::query!(
+        r#"
+        WITH t AS (
+            SELECT nextval(pg_get_serial_sequence('messages', 'id')) AS id, t.*
+            FROM UNNEST($2::TEXT[], $3::UUID[], $4::TEXT[], $5::TIMESTAMPTZ[])
+                 AS t(ts, user_id, content, created_at)
+        ), inserted AS (
+            INSERT INTO messages (id, room_id, user_id, content, created_at)
+            SELECT id, $1, user_id, content, created_at FROM t
+        )
+        SELECT id AS "id!", ts AS "ts!" FROM t
+        "#,
+        room_id,
+        &root_ts,
+        &root_users,
+        &root_contents,
+        &root_times
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let mut imported = inserted.len();
+    let root_ids: HashMap<String, i64> = inserted.into_iter().map(|r| (r.ts, r.id)).collect();
+
+    let mut reply_users = Vec::new();
+    let mut reply_contents = Vec::new();
+    let mut reply_times = Vec::new();
+    let mut reply_parents = Vec::new();
+    for (user_id, content, created_at, thread_ts) in replies {
+        // A reply whose root was not exported becomes a top-level message.
+        reply_users.push(user_id);
+        reply_contents.push(content);
+        reply_times.push(created_at);
+        reply_parents.push(root_ids.get(&thread_ts).copied());
+    }
+
+    // `parent_id` may be NULL, so it goes in as a nullable array.
+    imported += sqlx::query!(
+        r#"
+        INSERT INTO messages (room_id, user_id, content, created_at, parent_id)
+        SELECT $1, t.user_id, t.content, t.created_at, t.parent_id
+        FROM UNNEST($2::UUID[], $3::TEXT[], $4::TIMESTAMPTZ[], $5::BIGINT[])
+             AS t(user_id, content, created_at, parent_id)
+        "#,
+        room_id,
+        &reply_users,
+        &reply_contents,
+        &reply_times,
+        &reply_parents as &[Option<i64>]
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected() as usize;
+
+    tx.commit().await?;
+    Ok(Some(imported))
+}
+
+/// Runs a full import of the Slack export at `path`.
+pub async fn import_archive(pool: &PgPool, path: &Path) -> Result<ImportSummary, ImportError> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let mut summary = ImportSummary::default();
+
+    let users: Vec<SlackUser> = read_json(&mut archive, "users.json")?;
+    let channels: Vec<SlackChannel> = read_json(&mut archive, "channels.json")?;
+
+    let imported_users = import_users(pool, &users, &mut summary).await?;
+    info!("Mapped {} Slack users ({} created)", imported_users.len(), summary.users_created);
+
+    for channel in &channels {
+        // Each channel directory holds one file per day; names sort by date.
+        let prefix = format!("{}/", channel.name);
+        let mut day_files: Vec<String> = archive
+            .file_names()
+            .filter(|f| f.starts_with(&prefix) && f.ends_with(".json"))
+            .map(str::to_string)
+            .collect();
+        day_files.sort();
+
+        let mut messages: Vec<SlackMessage> = Vec::new();
+        for file in &day_files {
+            messages.extend(read_json::<Vec<SlackMessage>>(&mut archive, file)?);
+        }
+
+        match import_channel(pool, &channel.name, messages, &imported_users).await? {
+            Some(count) => {
+                info!("Imported {} messages into room {}", count, channel.name);
+                summary.rooms_created += 1;
+                summary.messages_imported += count;
+            }
+            None => {
+                warn!("Room {} already exists; skipping channel", channel.name);
+                summary.rooms_skipped += 1;
+            }
+        }
+    }
+
+    Ok(summary)
+}
@@ -1,27 +1,46 @@
 // src/main.rs
 
-use rocket::{routes, Build, Rocket, fs::FileServer};
-use sqlx::{postgres::PgPoolOptions, PgPool};
-use log::info;
+use rocket::{routes, fs::FileServer};
+use sqlx::postgres::PgPoolOptions;
+use log::{error, info, warn};
+use std::time::Duration;
 
 // Import the new config and the state
-use crate::config::AppConfig;
+use crate::config::{AppConfig, StorageBackend};
 use crate::state::ChatServerState;
+use crate::storage::Storage;
 
 // Import all handlers
-use crate::handlers::{auth, chat};
+use crate::handlers::{attachments, auth, bookmarks, chat, diagnostics, dm, export, mentions, scheduled, search};
 
 
 // Declare all modules
+mod access;
 mod models;
+mod retention;
+mod scheduler;
+mod schema;
 mod state;
 mod config;
+mod directory;
+mod expiry;
 mod handlers;
+mod storage;
+mod import;
 mod websocket;
 
+// `rocket::Error` is large, so it is boxed to keep `main`'s result small.
 #[rocket::main]
-async fn main() -> Result<(), rocket::Error> {
+async fn main() -> Result<(), Box<rocket::Error>> {
     env_logger::init();
+
+    // `chat-backend import-slack <export.zip>` runs a one-off import instead of the server.
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("import-slack") {
+        run_slack_import(args.get(2)).await;
+        return Ok(());
+    }
+
     info!("Starting chat server...");
 
     rocket::build()
@@ -39,22 +58,90 @@ async fn main() -> Result<(), rocket::Error> {
                 }
             };
 
-            // 2. Set up the database pool using the URL from our loaded config.
-            let pool = match PgPoolOptions::new()
-                .max_connections(5)
-                .connect(&app_config.database_url)
-                .await
-            {
-                Ok(pool) => pool,
+            // 2. Set up the storage backend. Only Postgres needs a database
+            //    pool; the other backends never connect to `database_url`.
+            let (pool, storage) = match app_config.storage {
+                StorageBackend::Postgres => {
+                    let pool = match PgPoolOptions::new()
+                        .max_connections(5)
+                        .connect(&app_config.database_url)
+                        .await
+                    {
+                        Ok(pool) => pool,
+                        Err(e) => {
+                            rocket::error!("Failed to connect to the database: {}", e);
+                            return rocket;
+                        }
+                    };
+                    // Bring the schema up to date before anything queries it.
+                    match schema::prepare(&pool, app_config.run_migrations).await {
+                        Ok(status) if status.pending > 0 => warn!(
+                            "Database schema is at {:?}, {} migrations behind; run them or enable run_migrations",
+                            status.version, status.pending
+                        ),
+                        Ok(status) => info!("Database schema is at version {:?}", status.version),
+                        Err(e) => {
+                            rocket::error!("Refusing to start: {}", e);
+                            return rocket;
+                        }
+                    }
+                    (Some(pool.clone()), Storage::postgres(pool))
+                }
+                StorageBackend::Memory => {
+                    info!("Using in-memory storage; data will not survive a restart");
+                    (None, Storage::memory())
+                }
+                #[cfg(feature = "sqlite")]
+                StorageBackend::Sqlite => match Storage::sqlite(&app_config.sqlite_path).await {
+                    Ok(storage) => {
+                        info!("Using SQLite storage at {}", app_config.sqlite_path);
+                        (None, storage)
+                    }
+                    Err(e) => {
+                        rocket::error!("Failed to open {}: {}", app_config.sqlite_path, e);
+                        return rocket;
+                    }
+                },
+            };
+
+            // 3. Start the background task that purges messages past their
+            //    room's retention period.
+            retention::spawn_purge_task(
+                storage.clone(),
+                app_config.upload_dir.clone(),
+                Duration::from_secs(app_config.retention_sweep_secs.get()),
+                app_config.retention_batch_size.get().into(),
+            );
+
+            // 4. Restore room membership, which outlives connections and restarts.
+            let chat_state = ChatServerState::new();
+            match chat_state.load_room_members(storage.rooms.as_ref()).await {
+                Ok(count) => info!("Loaded {} room memberships", count),
                 Err(e) => {
-                    rocket::error!("Failed to connect to the database: {}", e);
+                    rocket::error!("Failed to load room memberships: {}", e);
                     return rocket;
                 }
-            };
+            }
+
+            // 5. Start delivering scheduled messages and bookmark reminders,
+            //    including any that came due while the server was down, and
+            //    deleting ephemeral messages that expired.
+            scheduler::spawn_scheduler(
+                chat_state.clone(),
+                storage.clone(),
+                Duration::from_secs(app_config.scheduler_poll_secs.get()),
+            );
+            expiry::spawn_expiry_task(
+                storage.clone(),
+                chat_state.clone(),
+                app_config.upload_dir.clone(),
+                Duration::from_secs(app_config.expiry_poll_secs.get()),
+            );
 
-            // 3. Put the database pool, app configuration, and chat state into
-            //    Rocket's managed state so handlers can access them.
-            rocket.manage(pool).manage(app_config).manage(ChatServerState::new())
+            // 6. Put the storage, app configuration, and chat state into Rocket's
+            //    managed state so handlers can access them. The pool, `None`
+            //    unless storage is Postgres, only serves diagnostics.
+            rocket.manage(pool).manage(storage).manage(app_config).manage(chat_state)
         }))
         // The route mounting remains the same.
         .mount("/ws", routes![websocket::handler::ws_handler])
@@ -63,9 +150,28 @@ async fn main() -> Result<(), rocket::Error> {
             "/api",
             routes![
                 chat::get_history,
+                chat::get_thread,
                 chat::list_rooms,
                 chat::create_room,
-                chat::get_room_members
+                chat::get_room_members,
+                chat::add_moderator,
+                chat::list_pins,
+                chat::set_retention,
+                chat::set_message_ttl,
+                bookmarks::list_bookmarks,
+                bookmarks::save_bookmark,
+                bookmarks::delete_bookmark,
+                diagnostics::get_diagnostics,
+                mentions::list_mentions,
+                mentions::acknowledge_mention,
+                scheduled::list_scheduled,
+                scheduled::cancel_scheduled,
+                dm::open_direct_message,
+                dm::list_direct_messages,
+                attachments::upload_attachment,
+                attachments::download_attachment,
+                search::search_messages,
+                export::export_room
             ],
         )
         .mount("/", FileServer::from("public"))
@@ -73,4 +179,164 @@ async fn main() -> Result<(), rocket::Error> {
         .await?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Entry point of the `import-slack` command. Uses the same configuration as
+/// the server and exits the process with status 1 on failure.
+async fn run_slack_import(archive: Option<&String>) {
+    let Some(archive) = archive else {
+        eprintln!("Usage: chat-backend import-slack <export.zip>");
+        std::process::exit(1);
+    };
+
+    let app_config = match rocket::Config::figment().extract::<AppConfig>() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Failed to extract AppConfig: {}", e);
+            std::process::exit(1);
+        }
+    };
+    // The importer writes straight to Postgres; importing into a database the
+    // server does not read from would silently lose the data.
+    if app_config.storage != StorageBackend::Postgres {
+        error!("The Slack import needs Postgres storage; set storage = \"postgres\"");
+        std::process::exit(1);
+    }
+    let pool = match PgPoolOptions::new().max_connections(1).connect(&app_config.database_url).await {
+        Ok(pool) => pool,
+        Err(e) => {
+            error!("Failed to connect to the database: {}", e);
+            std::process::exit(1);
+        }
+    };
+    match schema::prepare(&pool, app_config.run_migrations).await {
+        Ok(status) if status.pending > 0 => {
+            error!(
+                "Database schema is at {:?}, {} migrations behind; run them or enable run_migrations",
+                status.version, status.pending
+            );
+            std::process::exit(1);
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!("Refusing to import: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    match import::slack::import_archive(&pool, std::path::Path::new(archive)).await {
+        Ok(summary) => info!(
+            "Slack import finished: {} rooms created, {} skipped, {} messages, {} new users",
+            summary.rooms_created, summary.rooms_skipped, summary.messages_imported, summary.users_created
+        ),
+        Err(e) => {
+            error!("Slack import failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::figment::Figment;
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::asynchronous::Client;
+    use serde_json::{json, Value};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::storage::NewMessage;
+
+    async fn client() -> Client {
+        let app_config: AppConfig = Figment::new()
+            .merge(("database_url", "unused"))
+            .merge(("jwt_secret", "test-secret"))
+            .merge(("storage", "memory"))
+            .extract()
+            .expect("valid test config");
+        let rocket = rocket::build()
+            .manage(None::<sqlx::PgPool>)
+            .manage(Storage::memory())
+            .manage(app_config)
+            .manage(ChatServerState::new())
+            .mount("/auth", routes![auth::register, auth::login])
+            .mount("/api", routes![chat::create_room, chat::get_history, diagnostics::get_diagnostics]);
+        Client::tracked(rocket).await.expect("valid rocket instance")
+    }
+
+    async fn post_json(client: &Client, uri: &str, token: Option<&str>, body: Value) -> (Status, Value) {
+        let mut request = client.post(uri.to_string()).header(ContentType::JSON).body(body.to_string());
+        if let Some(token) = token {
+            request = request.header(Header::new("Authorization", format!("Bearer {}", token)));
+        }
+        let response = request.dispatch().await;
+        let status = response.status();
+        (status, response.into_json().await.unwrap_or(Value::Null))
+    }
+
+    #[rocket::async_test]
+    async fn register_create_room_and_read_history_in_memory() {
+        let client = client().await;
+        let credentials = json!({ "username": "alice", "password": "correct horse" });
+
+        let (status, body) = post_json(&client, "/auth/register", None, credentials.clone()).await;
+        assert_eq!(status, Status::Ok);
+        let token = body["token"].as_str().expect("token").to_string();
+        let (status, _) = post_json(&client, "/auth/register", None, credentials).await;
+        assert_ne!(status, Status::Ok);
+
+        let (status, room) = post_json(&client, "/api/rooms", Some(&token), json!({ "name": "general" })).await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(room["name"], "general");
+        let room_id: Uuid = room["id"].as_str().and_then(|id| id.parse().ok()).expect("room id");
+        let (status, _) = post_json(&client, "/api/rooms", Some(&token), json!({ "name": "general" })).await;
+        assert_ne!(status, Status::Ok);
+
+        // Messages arrive over the WebSocket, so store one directly.
+        let storage = client.rocket().state::<Storage>().expect("managed storage");
+        let alice = storage.users.find_credentials("alice").await.unwrap().unwrap().id;
+        let message = NewMessage {
+            room_id,
+            user_id: alice,
+            content: "hello",
+            parent_id: None,
+            attachment_ids: &[],
+            ttl_seconds: None,
+            quote: None,
+        };
+        storage.messages.insert_message(message).await.unwrap().expect("message stored");
+
+        let history = client
+            .get(format!("/api/history/{}", room_id))
+            .header(Header::new("Authorization", format!("Bearer {}", token)))
+            .dispatch()
+            .await;
+        assert_eq!(history.status(), Status::Ok);
+        let history: Value = history.into_json().await.expect("history page");
+        assert_eq!(history["messages"].as_array().map(Vec::len), Some(1));
+        assert_eq!(history["messages"][0]["content"], "hello");
+        assert_eq!(history["messages"][0]["username"], "alice");
+
+        let anonymous = client.get(format!("/api/history/{}", room_id)).dispatch().await;
+        assert_eq!(anonymous.status(), Status::Unauthorized);
+    }
+    #[rocket::async_test]
+    async fn diagnostics_require_sign_in() {
+        let client = client().await;
+        let anonymous = client.get("/api/diagnostics").dispatch().await;
+        assert_eq!(anonymous.status(), Status::Unauthorized);
+
+        let credentials = json!({ "username": "alice", "password": "correct horse" });
+        let (_, body) = post_json(&client, "/auth/register", None, credentials).await;
+        let token = body["token"].as_str().expect("token");
+        let diagnostics = client
+            .get("/api/diagnostics")
+            .header(Header::new("Authorization", format!("Bearer {}", token)))
+            .dispatch()
+            .await;
+        assert_eq!(diagnostics.status(), Status::Ok);
+        let diagnostics: Value = diagnostics.into_json().await.expect("diagnostics");
+        assert_eq!(diagnostics["storage"], "memory");
+        assert_eq!(diagnostics["schema"], Value::Null);
+    }
+}
@@ -1,11 +1,14 @@
 //TODO: We'll define our data structures here
 
 use serde::{Deserialize, Serialize};
+use sqlx::types::Json as SqlJson;
 use uuid::Uuid;
 
 pub type UserId = Uuid;
 pub type RoomId = String;
+pub type ConnectionId = Uuid; // One per WebSocket; a user may have several.
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub id: UserId,
@@ -15,9 +18,174 @@ pub struct User {
 
 use chrono::{DateTime, Utc};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Room {
-    pub id: RoomId,
+/// Metadata of a file attached to a message, as sent to clients.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct AttachmentSummary {
+    pub id: Uuid,
+    pub file_name: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+}
+
+// Records returned by the REST API. They live here rather than in the
+// handlers because the storage backends build them too.
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct UserRecord {
+    pub id: Uuid,
+    pub username: String,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct RoomRecord {
+    pub id: Uuid,
     pub name: String,
+    pub retention_days: Option<i32>,
+    pub message_ttl_seconds: Option<i32>,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct RoomListing {
+    pub id: Uuid,
+    pub name: String,
+    pub retention_days: Option<i32>, // Messages older than this are purged; `None` keeps them forever.
+    pub message_ttl_seconds: Option<i32>, // Default lifetime of new messages.
+    pub last_read_message_id: Option<i64>,
+    pub unread_count: i64,
+    pub mention_count: i64,
+    pub draft: Option<String>, // The user's unsent message in this room, if any.
+    pub draft_updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct MessageRecord {
+    pub id: i64,
+    pub user_id: Uuid,
+    pub username: String, // We'll get this with a JOIN
+    pub room_id: String,
+    pub content: Option<String>, // `None` for deleted messages (tombstones)
+    pub created_at: DateTime<Utc>,
+    pub edited: bool,
+    pub edited_at: Option<DateTime<Utc>>,
+    pub deleted: bool,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub deleted_by: Option<Uuid>,
+    pub expires_at: Option<DateTime<Utc>>, // Ephemeral messages are hard-deleted at this time.
+    pub parent_id: Option<i64>,
+    pub reply_count: i64,
+    pub last_reply_at: Option<DateTime<Utc>>,
+    pub reactions: SqlJson<Vec<ReactionSummary>>,
+    pub attachments: SqlJson<Vec<AttachmentSummary>>,
+    pub quote: Option<SqlJson<QuotedMessage>>, // Hidden, like `content`, once deleted.
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct PinRecord {
+    pub message_id: i64,
+    pub user_id: Uuid,
+    pub username: String,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+    pub pinned_by: Option<Uuid>,
+    pub pinned_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct DirectConversation {
+    pub room_id: Uuid,
+    pub user_id: Uuid, // The other participant
+    pub username: String,
+    pub last_read_message_id: Option<i64>,
+    pub unread_count: i64,
+    pub mention_count: i64,
+    pub draft: Option<String>, // The caller's unsent message in this conversation, if any.
+    pub draft_updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct MentionRecord {
+    pub message_id: i64,
+    pub room_id: Uuid,
+    pub room_name: String,
+    pub author_id: Uuid,
+    pub author_username: String,
+    pub content: String,
     pub created_at: DateTime<Utc>,
-}
\ No newline at end of file
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct BookmarkRecord {
+    pub message_id: i64,
+    pub room_id: Uuid,
+    pub room_name: String,
+    pub author_id: Uuid,
+    pub author_username: String,
+    pub content: Option<String>, // `None` once the message is deleted.
+    pub deleted: bool,
+    pub created_at: DateTime<Utc>,
+    pub note: Option<String>,
+    pub remind_at: Option<DateTime<Utc>>,
+    pub reminded_at: Option<DateTime<Utc>>,
+    pub bookmarked_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct ScheduledRecord {
+    pub id: i64,
+    pub room_id: Uuid,
+    pub content: String,
+    pub reply_to: Option<i64>,
+    pub attachment_ids: Vec<Uuid>,
+    pub send_at: DateTime<Utc>,
+    pub ttl_seconds: Option<i32>, // Lifetime once delivered; `None` uses the room's.
+    pub quote_id: Option<i64>,    // Message to quote, copied at delivery.
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct SearchResult {
+    pub message_id: i64,
+    pub room_id: Uuid,
+    pub room_name: String,
+    pub user_id: Uuid,
+    pub username: String,
+    pub snippet: String, // HTML-escaped, with matching terms wrapped in <mark></mark>
+    pub created_at: DateTime<Utc>,
+    pub rank: f32,
+}
+
+/// One message of an export. Deleted messages keep their row with `content: None`.
+#[derive(Serialize, sqlx::FromRow)]
+pub struct ExportRow {
+    pub id: i64,
+    pub parent_id: Option<i64>,
+    pub user_id: Uuid,
+    pub username: String,
+    pub content: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub edited_at: Option<DateTime<Utc>>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+/// A message forwarded, or quoted in a reply, into another message. It is a
+/// copy taken when the message is sent, so later edits of the original do not
+/// change it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotedMessage {
+    pub message_id: i64,
+    pub room_id: Uuid,
+    pub room_name: String,
+    pub user_id: Uuid,
+    pub username: String,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+    pub forwarded: bool, // Forwarded as a whole rather than quoted in a new message.
+}
+
+/// Aggregated reactions for one emoji on a message, from the caller's point of view.
+#[derive(Serialize, Deserialize)]
+pub struct ReactionSummary {
+    pub emoji: String,
+    pub count: i64,
+    pub reacted: bool, // Whether the requesting user is among the reactors.
+}
@@ -0,0 +1,80 @@
+// src/retention.rs
+
+// Background purge of messages that outlived their room's `retention_days`.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use log::{error, info};
+use tokio::time::{interval, Duration, MissedTickBehavior};
+use uuid::Uuid;
+
+use crate::storage::{Storage, StorageError};
+
+/// Starts the purge loop. It runs once right away and then every `sweep_every`.
+pub fn spawn_purge_task(storage: Storage, upload_dir: String, sweep_every: Duration, batch_size: i64) {
+    tokio::spawn(async move {
+        let mut ticker = interval(sweep_every);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            purge_expired(&storage, &upload_dir, batch_size).await;
+        }
+    });
+}
+
+/// Deletes expired messages batch by batch until none are left, then logs
+/// how many were removed per room.
+async fn purge_expired(storage: &Storage, upload_dir: &str, batch_size: i64) {
+    let started = Instant::now();
+    let mut per_room: HashMap<Uuid, u64> = HashMap::new();
+    let mut batches = 0;
+
+    loop {
+        match purge_batch(storage, upload_dir, batch_size).await {
+            Ok(rooms) => {
+                let purged = rooms.len() as i64;
+                for room_id in rooms {
+                    *per_room.entry(room_id).or_default() += 1;
+                }
+                batches += 1;
+                if purged < batch_size {
+                    break;
+                }
+            }
+            Err(e) => {
+                error!("Retention purge failed: {}", e);
+                break;
+            }
+        }
+    }
+
+    for (room_id, count) in &per_room {
+        info!("Retention purged {} messages from room {}", count, room_id);
+    }
+    let total: u64 = per_room.values().sum();
+    info!(
+        "Retention sweep removed {} messages in {} batches across {} rooms in {:?}",
+        total,
+        batches,
+        per_room.len(),
+        started.elapsed()
+    );
+}
+
+/// Removes up to `batch_size` expired messages and their attachments.
+/// Returns the room of every deleted message. Replies to a purged message are
+/// removed with it, and their attachments too, but not counted.
+async fn purge_batch(storage: &Storage, upload_dir: &str, batch_size: i64) -> Result<Vec<Uuid>, StorageError> {
+    let purged = storage.messages.purge_retained(batch_size).await?;
+
+    for id in purged.attachment_ids {
+        let path = PathBuf::from(upload_dir).join(id.to_string());
+        if let Err(e) = tokio::fs::remove_file(&path).await {
+            error!("Failed to remove purged attachment {}: {}", path.display(), e);
+        }
+    }
+
+    Ok(purged.messages.into_iter().map(|(_, room_id)| room_id).collect())
+}
@@ -0,0 +1,174 @@
+// src/scheduler.rs
+
+// Background delivery of scheduled messages (`SendMessage` with a future
+// `send_at`). Pending messages are kept in storage, so anything that came due
+// while the server was down is delivered on the first poll. A message is only
+// removed from the schedule once it is stored, or once it can never be, in
+// which case the author is told. The same loop sends bookmark reminders.
+
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde::Serialize;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+use uuid::Uuid;
+
+use crate::state::ChatServerState;
+use crate::storage::{DueMessage, NewMessage, Storage};
+use crate::websocket::commands::{deliver_message, resolve_quote, send_to_user, Delivery};
+
+/// Most messages claimed per query; a poll keeps claiming until none are due.
+const BATCH_SIZE: i64 = 100;
+
+enum Outcome {
+    Delivered,
+    Dropped(&'static str), // Can never be delivered; the reason is sent to the author.
+    Retry,
+}
+
+/// Sent to the author when a scheduled message is dropped at delivery.
+#[derive(Debug, Serialize)]
+pub struct ScheduledMessageFailed {
+    r#type: &'static str,
+    pub id: i64, // Id of the scheduled message.
+    pub room_id: String,
+    reason: &'static str,
+}
+
+/// Sent to a user when a bookmark's `remind_at` is reached.
+#[derive(Debug, Serialize)]
+pub struct BookmarkReminder {
+    r#type: &'static str,
+    pub message_id: i64,
+    pub room_id: String,
+    note: Option<String>,
+    remind_at: DateTime<Utc>,
+}
+
+/// Starts the delivery loop. It runs once right away and then every `poll_every`.
+pub fn spawn_scheduler(state: ChatServerState, storage: Storage, poll_every: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = interval(poll_every);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            deliver_due(&state, &storage).await;
+            remind_due(&state, &storage).await;
+        }
+    });
+}
+
+/// Claims due messages `BATCH_SIZE` at a time. A claim keeps other servers
+/// polling the same database away from a message while it is delivered; one
+/// left by a failed delivery or a crashed server goes stale and is claimed
+/// again. A message is therefore delivered twice only if a server dies
+/// between storing it and removing it from the schedule.
+async fn deliver_due(state: &ChatServerState, storage: &Storage) {
+    loop {
+        let due = match storage.messages.claim_scheduled(BATCH_SIZE).await {
+            Ok(due) => due,
+            Err(e) => {
+                error!("Failed to claim scheduled messages: {}", e);
+                return;
+            }
+        };
+        let claimed = due.len() as i64;
+
+        for message in due {
+            let (id, room_id, user_id) = (message.id, message.room_id, message.user_id);
+            match deliver_one(state, storage, message).await {
+                Outcome::Delivered => {}
+                // Left claimed, so it is tried again once the claim goes stale.
+                Outcome::Retry => continue,
+                Outcome::Dropped(reason) => {
+                    let failed = ScheduledMessageFailed {
+                        r#type: "scheduled message failed",
+                        id,
+                        room_id: room_id.to_string(),
+                        reason,
+                    };
+                    send_to_user(state, &user_id, &failed);
+                }
+            }
+
+            if let Err(e) = storage.messages.finish_scheduled(id).await {
+                error!("Failed to remove delivered scheduled message {}: {}", id, e);
+            }
+        }
+
+        if claimed < BATCH_SIZE {
+            return;
+        }
+    }
+}
+/// Delivers one claimed message, checking again everything that may have
+/// changed since it was scheduled.
+async fn deliver_one(state: &ChatServerState, storage: &Storage, message: DueMessage) -> Outcome {
+    match storage.rooms.can_access(message.room_id, message.user_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            warn!(
+                "Dropping scheduled message {}: user {} may no longer post in room {}",
+                message.id, message.user_id, message.room_id
+            );
+            return Outcome::Dropped("You can no longer post in this room.");
+        }
+        Err(e) => {
+            error!("Failed to check access for scheduled message {}: {}", message.id, e);
+            return Outcome::Retry;
+        }
+    }
+
+    // A quote that went away is left out rather than holding the message back;
+    // `quote_id` is cleared if the quoted message was purged.
+    let quoted = match message.quote_id {
+        Some(quote_id) => {
+            let quoted = resolve_quote(storage, message.user_id, quote_id, false).await;
+            if quoted.is_none() {
+                warn!("Delivering scheduled message {} without its unavailable quote", message.id);
+            }
+            quoted
+        }
+        None => None,
+    };
+
+    info!("Delivering scheduled message {} due at {}", message.id, message.send_at);
+    let new_message = NewMessage {
+        room_id: message.room_id,
+        user_id: message.user_id,
+        content: &message.content,
+        parent_id: message.parent_id,
+        attachment_ids: &message.attachment_ids,
+        ttl_seconds: message.ttl_seconds,
+        quote: quoted.as_ref(),
+    };
+    match deliver_message(state, storage, new_message).await {
+        Delivery::Sent => Outcome::Delivered,
+        Delivery::Rejected => Outcome::Dropped("Its attachments can no longer be used."),
+        Delivery::Failed => Outcome::Retry,
+    }
+}
+
+/// Sends every due bookmark reminder of users who are online. Reminders of
+/// offline users wait until they reconnect.
+async fn remind_due(state: &ChatServerState, storage: &Storage) {
+    let online: Vec<Uuid> = state.connections.iter().map(|entry| *entry.key()).collect();
+    if online.is_empty() {
+        return;
+    }
+
+    match storage.messages.claim_reminders(&online).await {
+        Ok(due) => {
+            for bookmark in due {
+                let reminder = BookmarkReminder {
+                    r#type: "bookmark reminder",
+                    message_id: bookmark.message_id,
+                    room_id: bookmark.room_id.to_string(),
+                    note: bookmark.note,
+                    remind_at: bookmark.remind_at,
+                };
+                send_to_user(state, &bookmark.user_id, &reminder);
+            }
+        }
+        Err(e) => error!("Failed to claim bookmark reminders: {}", e),
+    }
+}
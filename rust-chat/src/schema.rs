@@ -0,0 +1,62 @@
+// src/schema.rs
+
+// The Postgres schema in `migrations/`, embedded in the binary and applied at
+// startup. sqlx records applied migrations in `_sqlx_migrations`.
+
+use serde::Serialize;
+use sqlx::migrate::{MigrateError, Migrator};
+use sqlx::PgPool;
+
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, thiserror::Error)]
+pub enum SchemaError {
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("migration failed: {0}")]
+    Migration(#[from] MigrateError),
+    #[error("database schema version {applied} is newer than this binary supports ({latest})")]
+    TooNew { applied: i64, latest: i64 },
+}
+
+/// Where the database schema stands relative to this binary.
+#[derive(Serialize)]
+pub struct SchemaStatus {
+    pub version: Option<i64>, // Newest applied migration; `None` if sqlx never migrated this database.
+    pub latest_version: i64,  // Newest migration embedded in the binary.
+    pub pending: usize,       // Embedded migrations not yet applied.
+}
+
+pub async fn status(pool: &PgPool) -> Result<SchemaStatus, sqlx::Error> {
+    let tracked: bool = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+        .fetch_one(pool)
+        .await?;
+    let version: Option<i64> = if tracked {
+        sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+            .fetch_one(pool)
+            .await?
+    } else {
+        None
+    };
+
+    Ok(SchemaStatus {
+        version,
+        latest_version: MIGRATOR.iter().map(|m| m.version).max().unwrap_or(0),
+        pending: MIGRATOR.iter().filter(|m| version.is_none_or(|v| m.version > v)).count(),
+    })
+}
+
+/// Refuses a schema newer than the binary, then applies any pending
+/// migrations unless `apply` is false.
+pub async fn prepare(pool: &PgPool, apply: bool) -> Result<SchemaStatus, SchemaError> {
+    let current = status(pool).await?;
+    if let Some(applied) = current.version.filter(|v| *v > current.latest_version) {
+        return Err(SchemaError::TooNew { applied, latest: current.latest_version });
+    }
+    if !apply || current.pending == 0 {
+        return Ok(current);
+    }
+
+    MIGRATOR.run(pool).await?;
+    Ok(status(pool).await?)
+}
@@ -1,21 +1,62 @@
-use crate::models::{Room, RoomId, User, UserId};
+use crate::directory::UserDirectory;
+use crate::models::{ConnectionId, RoomId, UserId};
+use crate::websocket::drafts::PendingDraft;
+use crate::websocket::typing::TypingStatus;
 use dashmap::{DashMap, DashSet};
+use crate::storage::{RoomStore, StorageResult};
 use std::sync::Arc;
 use tokio::sync::mpsc::UnboundedSender;
+use uuid::Uuid;
 
 #[derive(Clone)]
 pub struct ChatServerState {
-    pub rooms: Arc<DashMap<RoomId,Room>>,
+    // Persistent room membership, mirrored from `room_memberships`. Whether a
+    // member is online is tracked separately by `connections`.
     pub room_members: Arc<DashMap<RoomId, DashSet<UserId>>>,
-    pub connections: Arc<DashMap<UserId, UnboundedSender<String>>>,
+    // Every open WebSocket of each online user; users without one are absent.
+    pub connections: Arc<DashMap<UserId, DashMap<ConnectionId, UnboundedSender<String>>>>,
+    pub typing: Arc<DashMap<(RoomId, UserId), TypingStatus>>,
+    pub drafts: Arc<DashMap<(RoomId, UserId), PendingDraft>>,
+    pub users: Arc<UserDirectory>,
 }
 
 impl ChatServerState {
     pub fn new() -> Self{
         Self {
-            rooms: Arc::new(DashMap::new()),
             room_members: Arc::new(DashMap::new()),
             connections: Arc::new(DashMap::new()),
+            typing: Arc::new(DashMap::new()),
+            drafts: Arc::new(DashMap::new()),
+            users: Arc::new(UserDirectory::default()),
         }
     }
-}
\ No newline at end of file
+
+    /// Registers a new connection of `user_id` that receives events through `sender`.
+    pub fn connect(&self, user_id: UserId, sender: UnboundedSender<String>) -> ConnectionId {
+        let connection_id = Uuid::new_v4();
+        self.connections.entry(user_id).or_default().insert(connection_id, sender);
+        connection_id
+    }
+
+    /// Forgets a closed connection. Returns `true` if it was the user's last
+    /// one, meaning they are now offline.
+    pub fn disconnect(&self, user_id: UserId, connection_id: ConnectionId) -> bool {
+        if let Some(connections) = self.connections.get(&user_id) {
+            connections.remove(&connection_id);
+        }
+        self.connections
+            .remove_if(&user_id, |_, connections| connections.is_empty())
+            .is_some()
+    }
+
+    /// Loads every persisted membership into `room_members`. Called once at
+    /// startup, before any connection is accepted.
+    pub async fn load_room_members(&self, rooms: &dyn RoomStore) -> StorageResult<usize> {
+        let memberships = rooms.memberships().await?;
+        let count = memberships.len();
+        for (room_id, user_id) in memberships {
+            self.room_members.entry(room_id.to_string()).or_default().insert(user_id);
+        }
+        Ok(count)
+    }
+}
@@ -0,0 +1,1424 @@
+// src/storage/memory.rs
+
+// In-process backend for tests and demos. Nothing survives a restart.
+// Otherwise it behaves like Postgres, with two differences: edits do not keep
+// the previous text, as nothing reads it back, and search matches whole words
+// without stemming (see `text_search`). Attachment bytes are still written to
+// `upload_dir` by the handlers; only their records live here.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::types::Json as SqlJson;
+use uuid::Uuid;
+
+use super::text_search::TextQuery;
+use super::{
+    direct_room_name, AttachmentAccess, DueMessage, DueReminder, HistoryQuery, InsertedMessage, MessageStore,
+    NewAttachment, NewMessage, PurgedMessages, QuoteSource, RoomStore, SearchQuery, StorageError, StorageResult,
+    UserCredentials, UserStore,
+};
+use crate::models::{
+    AttachmentSummary, BookmarkRecord, DirectConversation, ExportRow, MentionRecord, MessageRecord, PinRecord,
+    QuotedMessage, ReactionSummary, RoomListing, RoomRecord, ScheduledRecord, SearchResult, UserRecord,
+};
+
+/// How long a claimed scheduled message is left alone before it is claimed again.
+const CLAIM_TIMEOUT_MINUTES: i64 = 5;
+
+struct StoredUser {
+    username: String,
+    password_hash: String,
+}
+
+struct StoredRoom {
+    name: String,
+    is_direct: bool,
+    retention_days: Option<i32>,
+    message_ttl_seconds: Option<i32>,
+}
+
+impl StoredRoom {
+    fn record(&self, id: Uuid) -> RoomRecord {
+        RoomRecord {
+            id,
+            name: self.name.clone(),
+            retention_days: self.retention_days,
+            message_ttl_seconds: self.message_ttl_seconds,
+        }
+    }
+}
+
+struct StoredMessage {
+    id: i64,
+    room_id: Uuid,
+    user_id: Uuid,
+    content: String,
+    parent_id: Option<i64>,
+    created_at: DateTime<Utc>,
+    edited_at: Option<DateTime<Utc>>,
+    deleted_at: Option<DateTime<Utc>>,
+    deleted_by: Option<Uuid>,
+    expires_at: Option<DateTime<Utc>>, // Hidden once past, deleted by `expire_messages`.
+    quote: Option<QuotedMessage>,
+}
+
+impl StoredMessage {
+    fn is_live(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_none_or(|at| at > now)
+    }
+}
+
+struct StoredReaction {
+    message_id: i64,
+    user_id: Uuid,
+    emoji: String,
+}
+
+struct StoredPin {
+    room_id: Uuid,
+    pinned_by: Option<Uuid>,
+    pinned_at: DateTime<Utc>,
+}
+
+struct StoredAttachment {
+    uploader_id: Uuid,
+    room_id: Uuid,
+    message_id: Option<i64>,
+    file_name: String,
+    content_type: String,
+    size_bytes: i64,
+    created_at: DateTime<Utc>,
+}
+
+impl StoredAttachment {
+    fn summary(&self, id: Uuid) -> AttachmentSummary {
+        AttachmentSummary {
+            id,
+            file_name: self.file_name.clone(),
+            content_type: self.content_type.clone(),
+            size_bytes: self.size_bytes,
+        }
+    }
+}
+
+struct StoredBookmark {
+    note: Option<String>,
+    remind_at: Option<DateTime<Utc>>,
+    reminded_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+}
+
+struct StoredScheduled {
+    room_id: Uuid,
+    user_id: Uuid,
+    content: String,
+    parent_id: Option<i64>,
+    attachment_ids: Vec<Uuid>,
+    send_at: DateTime<Utc>,
+    ttl_seconds: Option<i32>,
+    quote_id: Option<i64>,
+    claimed_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct Data {
+    users: HashMap<Uuid, StoredUser>,
+    rooms: HashMap<Uuid, StoredRoom>,
+    moderators: BTreeSet<(Uuid, Uuid)>,   // (room_id, user_id)
+    memberships: BTreeSet<(Uuid, Uuid)>,  // (room_id, user_id)
+    participants: BTreeSet<(Uuid, Uuid)>, // (room_id, user_id) of direct rooms
+    messages: BTreeMap<i64, StoredMessage>,
+    last_message_id: i64,
+    reactions: Vec<StoredReaction>, // In the order they were added
+    pins: HashMap<i64, StoredPin>,
+    mentions: BTreeMap<(i64, Uuid), Option<DateTime<Utc>>>, // (message_id, user_id) -> acknowledged_at
+    reads: HashMap<(Uuid, Uuid), i64>,                      // (room_id, user_id) -> last read message
+    drafts: HashMap<(Uuid, Uuid), (String, DateTime<Utc>)>, // (room_id, user_id)
+    attachments: HashMap<Uuid, StoredAttachment>,
+    bookmarks: BTreeMap<(Uuid, i64), StoredBookmark>, // (user_id, message_id)
+    scheduled: BTreeMap<i64, StoredScheduled>,
+    last_scheduled_id: i64,
+}
+
+impl Data {
+    fn username(&self, user_id: Uuid) -> String {
+        self.users.get(&user_id).map(|u| u.username.clone()).unwrap_or_default()
+    }
+
+    fn can_access(&self, room_id: Uuid, user_id: Uuid) -> bool {
+        self.rooms
+            .get(&room_id)
+            .is_some_and(|room| !room.is_direct || self.participants.contains(&(room_id, user_id)))
+    }
+
+    /// A message that is neither deleted nor expired.
+    fn live_message(&self, message_id: i64, now: DateTime<Utc>) -> Option<&StoredMessage> {
+        self.messages.get(&message_id).filter(|m| m.deleted_at.is_none() && m.is_live(now))
+    }
+
+    fn unread_count(&self, room_id: Uuid, user_id: Uuid, now: DateTime<Utc>) -> i64 {
+        let last_read = self.reads.get(&(room_id, user_id)).copied().unwrap_or(0);
+        self.messages
+            .range(last_read + 1..)
+            .filter(|(_, m)| {
+                m.room_id == room_id && m.deleted_at.is_none() && m.is_live(now) && m.user_id != user_id
+            })
+            .count() as i64
+    }
+
+    fn mention_count(&self, room_id: Uuid, user_id: Uuid, now: DateTime<Utc>) -> i64 {
+        self.mentions
+            .iter()
+            .filter(|((message_id, mentioned), acknowledged_at)| {
+                *mentioned == user_id
+                    && acknowledged_at.is_none()
+                    && self.live_message(*message_id, now).is_some_and(|m| m.room_id == room_id)
+            })
+            .count() as i64
+    }
+
+    fn reaction_summary(&self, message_id: i64, viewer: Uuid) -> Vec<ReactionSummary> {
+        let mut summary: Vec<ReactionSummary> = Vec::new();
+        for reaction in self.reactions.iter().filter(|r| r.message_id == message_id) {
+            let reacted = reaction.user_id == viewer;
+            match summary.iter_mut().find(|s| s.emoji == reaction.emoji) {
+                Some(s) => {
+                    s.count += 1;
+                    s.reacted |= reacted;
+                }
+                None => summary.push(ReactionSummary { emoji: reaction.emoji.clone(), count: 1, reacted }),
+            }
+        }
+        summary
+    }
+
+    fn attachment_summary(&self, message_id: i64) -> Vec<AttachmentSummary> {
+        let mut attachments: Vec<(&Uuid, &StoredAttachment)> =
+            self.attachments.iter().filter(|(_, a)| a.message_id == Some(message_id)).collect();
+        attachments.sort_by_key(|(id, a)| (a.created_at, **id));
+        attachments.into_iter().map(|(id, a)| a.summary(*id)).collect()
+    }
+
+    /// Whether `user_id` may link all of `attachment_ids` to a message in `room_id`.
+    fn attachments_usable(&self, attachment_ids: &[Uuid], user_id: Uuid, room_id: Uuid) -> bool {
+        attachment_ids.iter().all(|id| {
+            self.attachments.get(id).is_some_and(|a| {
+                a.uploader_id == user_id && a.room_id == room_id && a.message_id.is_none()
+            })
+        })
+    }
+
+    fn message_record(&self, m: &StoredMessage, viewer: Uuid, now: DateTime<Utc>) -> MessageRecord {
+        let deleted = m.deleted_at.is_some();
+        let replies = self
+            .messages
+            .values()
+            .filter(|r| r.parent_id == Some(m.id) && r.deleted_at.is_none() && r.is_live(now));
+        MessageRecord {
+            id: m.id,
+            user_id: m.user_id,
+            username: self.username(m.user_id),
+            room_id: m.room_id.to_string(),
+            content: (!deleted).then(|| m.content.clone()),
+            created_at: m.created_at,
+            edited: m.edited_at.is_some(),
+            edited_at: m.edited_at,
+            deleted,
+            deleted_at: m.deleted_at,
+            deleted_by: m.deleted_by,
+            expires_at: m.expires_at,
+            parent_id: m.parent_id,
+            reply_count: replies.clone().count() as i64,
+            last_reply_at: replies.map(|r| r.created_at).max(),
+            reactions: SqlJson(if deleted { Vec::new() } else { self.reaction_summary(m.id, viewer) }),
+            attachments: SqlJson(if deleted { Vec::new() } else { self.attachment_summary(m.id) }),
+            quote: m.quote.clone().filter(|_| !deleted).map(SqlJson),
+        }
+    }
+
+    /// Deletes messages with everything that references them, as the foreign
+    /// keys do in Postgres, and returns the ids of their attachments.
+    fn remove_messages(&mut self, ids: &HashSet<i64>) -> Vec<Uuid> {
+        self.messages.retain(|id, _| !ids.contains(id));
+        self.reactions.retain(|r| !ids.contains(&r.message_id));
+        self.pins.retain(|id, _| !ids.contains(id));
+        self.mentions.retain(|(id, _), _| !ids.contains(id));
+        self.bookmarks.retain(|(_, id), _| !ids.contains(id));
+        self.scheduled.retain(|_, s| !s.parent_id.is_some_and(|p| ids.contains(&p)));
+        for scheduled in self.scheduled.values_mut() {
+            scheduled.quote_id = scheduled.quote_id.filter(|q| !ids.contains(q));
+        }
+
+        let attachment_ids: Vec<Uuid> = self
+            .attachments
+            .iter()
+            .filter(|(_, a)| a.message_id.is_some_and(|m| ids.contains(&m)))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &attachment_ids {
+            self.attachments.remove(id);
+        }
+        attachment_ids
+    }
+
+    /// Deletes `selected` and their replies. `with_replies` decides whether
+    /// the replies are reported too.
+    fn purge(&mut self, selected: Vec<(i64, Uuid)>, with_replies: bool) -> PurgedMessages {
+        let mut ids: HashSet<i64> = selected.iter().map(|(id, _)| *id).collect();
+        let replies: Vec<(i64, Uuid)> = self
+            .messages
+            .values()
+            .filter(|m| m.parent_id.is_some_and(|p| ids.contains(&p)) && !ids.contains(&m.id))
+            .map(|m| (m.id, m.room_id))
+            .collect();
+        ids.extend(replies.iter().map(|(id, _)| *id));
+
+        let attachment_ids = self.remove_messages(&ids);
+        let mut messages = selected;
+        if with_replies {
+            messages.extend(replies);
+        }
+        PurgedMessages { messages, attachment_ids }
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryStore {
+    data: Mutex<Data>,
+}
+
+impl MemoryStore {
+    fn data(&self) -> std::sync::MutexGuard<'_, Data> {
+        self.data.lock().expect("memory store lock poisoned")
+    }
+}
+
+#[rocket::async_trait]
+impl UserStore for MemoryStore {
+    async fn create_user(&self, username: &str, password_hash: &str) -> StorageResult<Option<Uuid>> {
+        let mut data = self.data();
+        if data.users.values().any(|u| u.username == username) {
+            return Ok(None);
+        }
+        let id = Uuid::new_v4();
+        data.users.insert(
+            id,
+            StoredUser { username: username.to_string(), password_hash: password_hash.to_string() },
+        );
+        Ok(Some(id))
+    }
+
+    async fn find_credentials(&self, username: &str) -> StorageResult<Option<UserCredentials>> {
+        let data = self.data();
+        Ok(data
+            .users
+            .iter()
+            .find(|(_, u)| u.username == username)
+            .map(|(id, u)| UserCredentials { id: *id, password_hash: u.password_hash.clone() }))
+    }
+
+    async fn get_users(&self, ids: &[Uuid]) -> StorageResult<Vec<UserRecord>> {
+        let data = self.data();
+        let mut users: Vec<UserRecord> = ids
+            .iter()
+            .filter_map(|id| data.users.get(id).map(|u| UserRecord { id: *id, username: u.username.clone() }))
+            .collect();
+        users.sort_by(|a, b| a.username.cmp(&b.username));
+        users.dedup_by_key(|u| u.id);
+        Ok(users)
+    }
+}
+
+#[rocket::async_trait]
+impl RoomStore for MemoryStore {
+    async fn create_room(
+        &self,
+        name: &str,
+        retention_days: Option<i32>,
+        message_ttl_seconds: Option<i32>,
+        creator: Uuid,
+    ) -> StorageResult<Option<RoomRecord>> {
+        let mut data = self.data();
+        if data.rooms.values().any(|r| r.name == name) {
+            return Ok(None);
+        }
+        let id = Uuid::new_v4();
+        let room = StoredRoom { name: name.to_string(), is_direct: false, retention_days, message_ttl_seconds };
+        let record = room.record(id);
+        data.rooms.insert(id, room);
+        data.moderators.insert((id, creator));
+        Ok(Some(record))
+    }
+
+    async fn list_rooms(&self, user_id: Uuid) -> StorageResult<Vec<RoomListing>> {
+        let data = self.data();
+        let now = Utc::now();
+        let mut rooms: Vec<RoomListing> = data
+            .rooms
+            .iter()
+            .filter(|(_, room)| !room.is_direct)
+            .map(|(id, room)| {
+                let draft = data.drafts.get(&(*id, user_id));
+                RoomListing {
+                    id: *id,
+                    name: room.name.clone(),
+                    retention_days: room.retention_days,
+                    message_ttl_seconds: room.message_ttl_seconds,
+                    last_read_message_id: data.reads.get(&(*id, user_id)).copied(),
+                    unread_count: data.unread_count(*id, user_id, now),
+                    mention_count: data.mention_count(*id, user_id, now),
+                    draft: draft.map(|(content, _)| content.clone()),
+                    draft_updated_at: draft.map(|(_, updated_at)| *updated_at),
+                }
+            })
+            .collect();
+        rooms.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(rooms)
+    }
+
+    async fn can_access(&self, room_id: Uuid, user_id: Uuid) -> StorageResult<bool> {
+        Ok(self.data().can_access(room_id, user_id))
+    }
+
+    async fn add_member(&self, room_id: Uuid, user_id: Uuid) -> StorageResult<()> {
+        self.data().memberships.insert((room_id, user_id));
+        Ok(())
+    }
+
+    async fn remove_member(&self, room_id: Uuid, user_id: Uuid) -> StorageResult<bool> {
+        // Direct conversations always include both participants.
+        let mut data = self.data();
+        if data.rooms.get(&room_id).is_some_and(|room| room.is_direct) {
+            return Ok(false);
+        }
+        Ok(data.memberships.remove(&(room_id, user_id)))
+    }
+
+    async fn memberships(&self) -> StorageResult<Vec<(Uuid, Uuid)>> {
+        Ok(self.data().memberships.iter().copied().collect())
+    }
+
+    async fn save_draft(
+        &self,
+        room_id: Uuid,
+        user_id: Uuid,
+        content: &str,
+        updated_at: DateTime<Utc>,
+    ) -> StorageResult<()> {
+        let mut data = self.data();
+        if content.is_empty() {
+            data.drafts.remove(&(room_id, user_id));
+        } else {
+            data.drafts.insert((room_id, user_id), (content.to_string(), updated_at));
+        }
+        Ok(())
+    }
+
+    async fn get_room(&self, room_id: Uuid) -> StorageResult<Option<RoomRecord>> {
+        Ok(self.data().rooms.get(&room_id).map(|room| room.record(room_id)))
+    }
+
+    async fn is_moderator(&self, room_id: Uuid, user_id: Uuid) -> StorageResult<bool> {
+        Ok(self.data().moderators.contains(&(room_id, user_id)))
+    }
+
+    async fn add_moderator(&self, room_id: Uuid, user_id: Uuid) -> StorageResult<()> {
+        self.data().moderators.insert((room_id, user_id));
+        Ok(())
+    }
+
+    async fn set_retention(&self, room_id: Uuid, retention_days: Option<i32>) -> StorageResult<Option<RoomRecord>> {
+        let mut data = self.data();
+        Ok(data.rooms.get_mut(&room_id).map(|room| {
+            room.retention_days = retention_days;
+            room.record(room_id)
+        }))
+    }
+
+    async fn set_message_ttl(
+        &self,
+        room_id: Uuid,
+        message_ttl_seconds: Option<i32>,
+    ) -> StorageResult<Option<RoomRecord>> {
+        let mut data = self.data();
+        Ok(data.rooms.get_mut(&room_id).map(|room| {
+            room.message_ttl_seconds = message_ttl_seconds;
+            room.record(room_id)
+        }))
+    }
+
+    async fn mark_read(&self, room_id: Uuid, user_id: Uuid, message_id: i64) -> StorageResult<bool> {
+        let mut data = self.data();
+        if !data.can_access(room_id, user_id)
+            || data.messages.get(&message_id).is_none_or(|m| m.room_id != room_id)
+            || data.reads.get(&(room_id, user_id)).is_some_and(|last| *last >= message_id)
+        {
+            return Ok(false);
+        }
+        data.reads.insert((room_id, user_id), message_id);
+
+        let now = Utc::now();
+        let Data { mentions, messages, .. } = &mut *data;
+        for ((mentioned_id, mentioned), acknowledged_at) in mentions.iter_mut() {
+            if *mentioned == user_id
+                && acknowledged_at.is_none()
+                && *mentioned_id <= message_id
+                && messages.get(mentioned_id).is_some_and(|m| m.room_id == room_id)
+            {
+                *acknowledged_at = Some(now);
+            }
+        }
+        Ok(true)
+    }
+
+    async fn open_direct(&self, user_id: Uuid, other_id: Uuid) -> StorageResult<Uuid> {
+        let mut data = self.data();
+        let name = direct_room_name(user_id, other_id);
+        if let Some((id, _)) = data.rooms.iter().find(|(_, room)| room.is_direct && room.name == name) {
+            return Ok(*id);
+        }
+
+        let id = Uuid::new_v4();
+        data.rooms.insert(
+            id,
+            StoredRoom { name, is_direct: true, retention_days: None, message_ttl_seconds: None },
+        );
+        for participant in [user_id, other_id] {
+            data.participants.insert((id, participant));
+            data.memberships.insert((id, participant));
+        }
+        Ok(id)
+    }
+
+    async fn direct_conversations(
+        &self,
+        user_id: Uuid,
+        room_id: Option<Uuid>,
+    ) -> StorageResult<Vec<DirectConversation>> {
+        let data = self.data();
+        let now = Utc::now();
+        let mut conversations: Vec<DirectConversation> = data
+            .participants
+            .iter()
+            .filter(|(room, participant)| *participant == user_id && room_id.is_none_or(|id| id == *room))
+            .flat_map(|(room, _)| {
+                data.participants
+                    .range((*room, Uuid::nil())..=(*room, Uuid::max()))
+                    .filter(|(_, other)| *other != user_id)
+            })
+            .filter_map(|(room, other)| {
+                let other_user = data.users.get(other)?;
+                let draft = data.drafts.get(&(*room, user_id));
+                Some(DirectConversation {
+                    room_id: *room,
+                    user_id: *other,
+                    username: other_user.username.clone(),
+                    last_read_message_id: data.reads.get(&(*room, user_id)).copied(),
+                    unread_count: data.unread_count(*room, user_id, now),
+                    mention_count: data.mention_count(*room, user_id, now),
+                    draft: draft.map(|(content, _)| content.clone()),
+                    draft_updated_at: draft.map(|(_, updated_at)| *updated_at),
+                })
+            })
+            .collect();
+        conversations.sort_by(|a, b| a.username.cmp(&b.username));
+        Ok(conversations)
+    }
+}
+
+#[rocket::async_trait]
+impl MessageStore for MemoryStore {
+    async fn insert_message(&self, message: NewMessage<'_>) -> StorageResult<Option<InsertedMessage>> {
+        let mut data = self.data();
+        let Some(room) = data.rooms.get(&message.room_id) else {
+            return Err(StorageError::Database(sqlx::Error::RowNotFound));
+        };
+        if !data.attachments_usable(message.attachment_ids, message.user_id, message.room_id) {
+            return Ok(None);
+        }
+
+        let created_at = Utc::now();
+        let expires_at = message
+            .ttl_seconds
+            .or(room.message_ttl_seconds)
+            .map(|secs| created_at + Duration::seconds(secs.into()));
+        data.last_message_id += 1;
+        let id = data.last_message_id;
+        data.messages.insert(
+            id,
+            StoredMessage {
+                id,
+                room_id: message.room_id,
+                user_id: message.user_id,
+                content: message.content.to_string(),
+                parent_id: message.parent_id,
+                created_at,
+                edited_at: None,
+                deleted_at: None,
+                deleted_by: None,
+                expires_at,
+                quote: message.quote.cloned(),
+            },
+        );
+        for attachment_id in message.attachment_ids {
+            if let Some(attachment) = data.attachments.get_mut(attachment_id) {
+                attachment.message_id = Some(id);
+            }
+        }
+
+        let attachments = data.attachment_summary(id);
+        Ok(Some(InsertedMessage { id, created_at, expires_at, attachments }))
+    }
+
+    async fn quote_source(&self, message_id: i64) -> StorageResult<Option<QuoteSource>> {
+        let data = self.data();
+        let Some(m) = data
+            .messages
+            .get(&message_id)
+            .filter(|m| m.deleted_at.is_none() && m.expires_at.is_none())
+        else {
+            return Ok(None);
+        };
+        let Some(room) = data.rooms.get(&m.room_id) else {
+            return Ok(None);
+        };
+        Ok(Some(QuoteSource {
+            message: QuotedMessage {
+                message_id: m.id,
+                room_id: m.room_id,
+                room_name: room.name.clone(),
+                user_id: m.user_id,
+                username: data.username(m.user_id),
+                content: m.content.clone(),
+                created_at: m.created_at,
+                forwarded: false,
+            },
+            quote: m.quote.clone(),
+        }))
+    }
+
+    async fn thread_root(&self, room_id: Uuid, message_id: i64) -> StorageResult<Option<i64>> {
+        let data = self.data();
+        Ok(data
+            .messages
+            .get(&message_id)
+            .filter(|m| m.room_id == room_id)
+            .map(|m| m.parent_id.unwrap_or(m.id)))
+    }
+
+    async fn history(&self, query: HistoryQuery) -> StorageResult<Vec<MessageRecord>> {
+        let data = self.data();
+        let now = Utc::now();
+        let page = data.messages.values().filter(|m| {
+            m.room_id == query.room_id
+                && m.parent_id.is_none()
+                && m.is_live(now)
+                && query.before.is_none_or(|before| m.id < before)
+                && query.after.is_none_or(|after| m.id > after)
+        });
+        let page: Vec<&StoredMessage> = if query.after.is_some() {
+            page.take(query.limit as usize).collect()
+        } else {
+            page.rev().take(query.limit as usize).collect()
+        };
+
+        Ok(page.into_iter().map(|m| data.message_record(m, query.viewer, now)).collect())
+    }
+
+    async fn thread(&self, room_id: Uuid, root_id: i64, viewer: Uuid) -> StorageResult<Vec<MessageRecord>> {
+        let data = self.data();
+        let now = Utc::now();
+        let mut thread: Vec<&StoredMessage> = data
+            .messages
+            .values()
+            .filter(|m| m.room_id == room_id && (m.id == root_id || m.parent_id == Some(root_id)) && m.is_live(now))
+            .collect();
+        thread.sort_by_key(|m| (m.parent_id.is_some(), m.created_at, m.id));
+        Ok(thread.into_iter().map(|m| data.message_record(m, viewer, now)).collect())
+    }
+
+    async fn live_message_room(&self, message_id: i64) -> StorageResult<Option<Uuid>> {
+        Ok(self.data().live_message(message_id, Utc::now()).map(|m| m.room_id))
+    }
+
+    async fn edit_message(
+        &self,
+        message_id: i64,
+        user_id: Uuid,
+        content: &str,
+    ) -> StorageResult<Option<(Uuid, DateTime<Utc>)>> {
+        let mut data = self.data();
+        let Some(message) = data
+            .messages
+            .get_mut(&message_id)
+            .filter(|m| m.deleted_at.is_none() && m.user_id == user_id)
+        else {
+            return Ok(None);
+        };
+        let edited_at = Utc::now();
+        message.content = content.to_string();
+        message.edited_at = Some(edited_at);
+        Ok(Some((message.room_id, edited_at)))
+    }
+
+    async fn delete_message(&self, message_id: i64, user_id: Uuid) -> StorageResult<Option<(Uuid, DateTime<Utc>)>> {
+        let mut data = self.data();
+        let Some(message) = data.messages.get(&message_id).filter(|m| m.deleted_at.is_none()) else {
+            return Ok(None);
+        };
+        let room_id = message.room_id;
+        if message.user_id != user_id && !data.moderators.contains(&(room_id, user_id)) {
+            return Ok(None);
+        }
+
+        let deleted_at = Utc::now();
+        if let Some(message) = data.messages.get_mut(&message_id) {
+            message.deleted_at = Some(deleted_at);
+            message.deleted_by = Some(user_id);
+        }
+        Ok(Some((room_id, deleted_at)))
+    }
+
+    async fn set_reaction(
+        &self,
+        message_id: i64,
+        user_id: Uuid,
+        emoji: &str,
+        added: bool,
+    ) -> StorageResult<Option<(Uuid, i64)>> {
+        let mut data = self.data();
+        let Some(room_id) = data.messages.get(&message_id).filter(|m| m.deleted_at.is_none()).map(|m| m.room_id)
+        else {
+            return Ok(None);
+        };
+        if !data.can_access(room_id, user_id) {
+            return Ok(None);
+        }
+
+        let existing = data
+            .reactions
+            .iter()
+            .position(|r| r.message_id == message_id && r.user_id == user_id && r.emoji == emoji);
+        match (existing, added) {
+            (None, true) => data.reactions.push(StoredReaction { message_id, user_id, emoji: emoji.to_string() }),
+            (Some(index), false) => {
+                data.reactions.remove(index);
+            }
+            _ => return Ok(None),
+        }
+
+        let count = data.reactions.iter().filter(|r| r.message_id == message_id && r.emoji == emoji).count();
+        Ok(Some((room_id, count as i64)))
+    }
+
+    async fn set_pinned(&self, message_id: i64, user_id: Uuid, pinned: bool) -> StorageResult<Option<Uuid>> {
+        let mut data = self.data();
+        let Some(room_id) = data.messages.get(&message_id).filter(|m| m.deleted_at.is_none()).map(|m| m.room_id)
+        else {
+            return Ok(None);
+        };
+        if !data.moderators.contains(&(room_id, user_id)) {
+            return Ok(None);
+        }
+
+        let changed = if pinned {
+            let new = !data.pins.contains_key(&message_id);
+            if new {
+                data.pins.insert(message_id, StoredPin { room_id, pinned_by: Some(user_id), pinned_at: Utc::now() });
+            }
+            new
+        } else {
+            data.pins.remove(&message_id).is_some()
+        };
+        Ok(changed.then_some(room_id))
+    }
+
+    async fn list_pins(&self, room_id: Uuid) -> StorageResult<Vec<PinRecord>> {
+        let data = self.data();
+        let now = Utc::now();
+        let mut pins: Vec<PinRecord> = data
+            .pins
+            .iter()
+            .filter(|(_, pin)| pin.room_id == room_id)
+            .filter_map(|(message_id, pin)| {
+                let m = data.live_message(*message_id, now)?;
+                Some(PinRecord {
+                    message_id: m.id,
+                    user_id: m.user_id,
+                    username: data.username(m.user_id),
+                    content: m.content.clone(),
+                    created_at: m.created_at,
+                    pinned_by: pin.pinned_by,
+                    pinned_at: pin.pinned_at,
+                })
+            })
+            .collect();
+        pins.sort_by_key(|p| std::cmp::Reverse(p.pinned_at));
+        Ok(pins)
+    }
+
+    async fn record_mentions(
+        &self,
+        message_id: i64,
+        room_id: Uuid,
+        author_id: Uuid,
+        usernames: &[String],
+    ) -> StorageResult<Vec<Uuid>> {
+        let mut data = self.data();
+        let mentioned: Vec<Uuid> = data
+            .users
+            .iter()
+            .filter(|(id, user)| {
+                **id != author_id
+                    && usernames.contains(&user.username)
+                    && data.can_access(room_id, **id)
+                    && !data.mentions.contains_key(&(message_id, **id))
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        for user_id in &mentioned {
+            data.mentions.insert((message_id, *user_id), None);
+        }
+        Ok(mentioned)
+    }
+
+    async fn list_mentions(&self, user_id: Uuid) -> StorageResult<Vec<MentionRecord>> {
+        let data = self.data();
+        let now = Utc::now();
+        let mut mentions: Vec<MentionRecord> = data
+            .mentions
+            .iter()
+            .filter(|((_, mentioned), acknowledged_at)| *mentioned == user_id && acknowledged_at.is_none())
+            .filter_map(|((message_id, _), _)| {
+                let m = data.live_message(*message_id, now)?;
+                Some(MentionRecord {
+                    message_id: m.id,
+                    room_id: m.room_id,
+                    room_name: data.rooms.get(&m.room_id)?.name.clone(),
+                    author_id: m.user_id,
+                    author_username: data.username(m.user_id),
+                    content: m.content.clone(),
+                    created_at: m.created_at,
+                })
+            })
+            .collect();
+        mentions.sort_by_key(|m| std::cmp::Reverse(m.created_at));
+        Ok(mentions)
+    }
+
+    async fn acknowledge_mention(&self, message_id: i64, user_id: Uuid) -> StorageResult<bool> {
+        let mut data = self.data();
+        match data.mentions.get_mut(&(message_id, user_id)) {
+            Some(acknowledged_at @ None) => {
+                *acknowledged_at = Some(Utc::now());
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn bookmarks(&self, user_id: Uuid, message_id: Option<i64>) -> StorageResult<Vec<BookmarkRecord>> {
+        let data = self.data();
+        let now = Utc::now();
+        let mut bookmarks: Vec<BookmarkRecord> = data
+            .bookmarks
+            .range((user_id, i64::MIN)..=(user_id, i64::MAX))
+            .filter(|((_, id), _)| message_id.is_none_or(|wanted| wanted == *id))
+            .filter_map(|((_, id), bookmark)| {
+                let m = data.messages.get(id).filter(|m| m.is_live(now))?;
+                let deleted = m.deleted_at.is_some();
+                Some(BookmarkRecord {
+                    message_id: m.id,
+                    room_id: m.room_id,
+                    room_name: data.rooms.get(&m.room_id)?.name.clone(),
+                    author_id: m.user_id,
+                    author_username: data.username(m.user_id),
+                    content: (!deleted).then(|| m.content.clone()),
+                    deleted,
+                    created_at: m.created_at,
+                    note: bookmark.note.clone(),
+                    remind_at: bookmark.remind_at,
+                    reminded_at: bookmark.reminded_at,
+                    bookmarked_at: bookmark.created_at,
+                })
+            })
+            .collect();
+        bookmarks.sort_by_key(|b| std::cmp::Reverse((b.bookmarked_at, b.message_id)));
+        Ok(bookmarks)
+    }
+
+    async fn save_bookmark(
+        &self,
+        user_id: Uuid,
+        message_id: i64,
+        note: Option<&str>,
+        remind_at: Option<DateTime<Utc>>,
+    ) -> StorageResult<()> {
+        let mut data = self.data();
+        let bookmark = data.bookmarks.entry((user_id, message_id)).or_insert_with(|| StoredBookmark {
+            note: None,
+            remind_at: None,
+            reminded_at: None,
+            created_at: Utc::now(),
+        });
+        bookmark.note = note.map(str::to_string);
+        bookmark.remind_at = remind_at;
+        bookmark.reminded_at = None;
+        Ok(())
+    }
+
+    async fn delete_bookmark(&self, user_id: Uuid, message_id: i64) -> StorageResult<bool> {
+        Ok(self.data().bookmarks.remove(&(user_id, message_id)).is_some())
+    }
+
+    async fn claim_reminders(&self, user_ids: &[Uuid]) -> StorageResult<Vec<DueReminder>> {
+        let mut data = self.data();
+        let now = Utc::now();
+        let Data { bookmarks, messages, .. } = &mut *data;
+        let mut due = Vec::new();
+        for ((user_id, message_id), bookmark) in bookmarks.iter_mut() {
+            let Some(remind_at) = bookmark.remind_at.filter(|at| *at <= now) else {
+                continue;
+            };
+            let Some(message) = messages.get(message_id).filter(|m| m.deleted_at.is_none()) else {
+                continue;
+            };
+            if bookmark.reminded_at.is_some() || !user_ids.contains(user_id) {
+                continue;
+            }
+            bookmark.reminded_at = Some(now);
+            due.push(DueReminder {
+                user_id: *user_id,
+                message_id: *message_id,
+                room_id: message.room_id,
+                note: bookmark.note.clone(),
+                remind_at,
+            });
+        }
+        Ok(due)
+    }
+
+    async fn schedule_message(&self, message: NewMessage<'_>, send_at: DateTime<Utc>) -> StorageResult<Option<i64>> {
+        let mut data = self.data();
+        if !data.attachments_usable(message.attachment_ids, message.user_id, message.room_id) {
+            return Ok(None);
+        }
+
+        data.last_scheduled_id += 1;
+        let id = data.last_scheduled_id;
+        data.scheduled.insert(
+            id,
+            StoredScheduled {
+                room_id: message.room_id,
+                user_id: message.user_id,
+                content: message.content.to_string(),
+                parent_id: message.parent_id,
+                attachment_ids: message.attachment_ids.to_vec(),
+                send_at,
+                ttl_seconds: message.ttl_seconds,
+                quote_id: message.quote.map(|q| q.message_id),
+                claimed_at: None,
+                created_at: Utc::now(),
+            },
+        );
+        Ok(Some(id))
+    }
+
+    async fn list_scheduled(&self, user_id: Uuid) -> StorageResult<Vec<ScheduledRecord>> {
+        let data = self.data();
+        let mut scheduled: Vec<ScheduledRecord> = data
+            .scheduled
+            .iter()
+            .filter(|(_, s)| s.user_id == user_id)
+            .map(|(id, s)| ScheduledRecord {
+                id: *id,
+                room_id: s.room_id,
+                content: s.content.clone(),
+                reply_to: s.parent_id,
+                attachment_ids: s.attachment_ids.clone(),
+                send_at: s.send_at,
+                ttl_seconds: s.ttl_seconds,
+                quote_id: s.quote_id,
+                created_at: s.created_at,
+            })
+            .collect();
+        scheduled.sort_by_key(|s| (s.send_at, s.id));
+        Ok(scheduled)
+    }
+
+    async fn cancel_scheduled(&self, id: i64, user_id: Uuid) -> StorageResult<bool> {
+        let mut data = self.data();
+        let stale = Utc::now() - Duration::minutes(CLAIM_TIMEOUT_MINUTES);
+        let pending = |s: &StoredScheduled| s.user_id == user_id && s.claimed_at.is_none_or(|at| at <= stale);
+        if !data.scheduled.get(&id).is_some_and(pending) {
+            return Ok(false);
+        }
+        data.scheduled.remove(&id);
+        Ok(true)
+    }
+
+    async fn claim_scheduled(&self, limit: i64) -> StorageResult<Vec<DueMessage>> {
+        let mut data = self.data();
+        let now = Utc::now();
+        let stale = now - Duration::minutes(CLAIM_TIMEOUT_MINUTES);
+        let mut due: Vec<(&i64, &mut StoredScheduled)> = data
+            .scheduled
+            .iter_mut()
+            .filter(|(_, s)| s.send_at <= now && s.claimed_at.is_none_or(|at| at <= stale))
+            .collect();
+        due.sort_by_key(|(id, s)| (s.send_at, **id));
+        due.truncate(limit.max(0) as usize);
+
+        Ok(due
+            .into_iter()
+            .map(|(id, s)| {
+                s.claimed_at = Some(now);
+                DueMessage {
+                    id: *id,
+                    room_id: s.room_id,
+                    user_id: s.user_id,
+                    content: s.content.clone(),
+                    parent_id: s.parent_id,
+                    attachment_ids: s.attachment_ids.clone(),
+                    send_at: s.send_at,
+                    ttl_seconds: s.ttl_seconds,
+                    quote_id: s.quote_id,
+                }
+            })
+            .collect())
+    }
+
+    async fn finish_scheduled(&self, id: i64) -> StorageResult<()> {
+        self.data().scheduled.remove(&id);
+        Ok(())
+    }
+
+    async fn insert_attachment(&self, attachment: NewAttachment<'_>) -> StorageResult<()> {
+        self.data().attachments.insert(
+            attachment.id,
+            StoredAttachment {
+                uploader_id: attachment.uploader_id,
+                room_id: attachment.room_id,
+                message_id: None,
+                file_name: attachment.file_name.to_string(),
+                content_type: attachment.content_type.to_string(),
+                size_bytes: attachment.size_bytes,
+                created_at: Utc::now(),
+            },
+        );
+        Ok(())
+    }
+
+    async fn attachment(&self, id: Uuid) -> StorageResult<Option<AttachmentAccess>> {
+        let data = self.data();
+        let now = Utc::now();
+        Ok(data.attachments.get(&id).map(|a| AttachmentAccess {
+            uploader_id: a.uploader_id,
+            room_id: a.room_id,
+            file_name: a.file_name.clone(),
+            content_type: a.content_type.clone(),
+            visible: a.message_id.is_some_and(|m| data.live_message(m, now).is_some()),
+        }))
+    }
+
+    async fn search(&self, query: SearchQuery<'_>) -> StorageResult<Vec<SearchResult>> {
+        let data = self.data();
+        let now = Utc::now();
+        let text = TextQuery::parse(query.text);
+        let mut results: Vec<SearchResult> = data
+            .messages
+            .values()
+            .filter(|m| {
+                m.deleted_at.is_none()
+                    && m.is_live(now)
+                    && data.can_access(m.room_id, query.viewer)
+                    && query.room_id.is_none_or(|id| id == m.room_id)
+                    && query.author_id.is_none_or(|id| id == m.user_id)
+                    && query.from.is_none_or(|from| m.created_at >= from)
+                    && query.to.is_none_or(|to| m.created_at < to)
+                    && text.matches(&m.content)
+            })
+            .filter_map(|m| {
+                Some(SearchResult {
+                    message_id: m.id,
+                    room_id: m.room_id,
+                    room_name: data.rooms.get(&m.room_id)?.name.clone(),
+                    user_id: m.user_id,
+                    username: data.username(m.user_id),
+                    snippet: text.snippet(&m.content),
+                    created_at: m.created_at,
+                    rank: text.rank(&m.content),
+                })
+            })
+            .collect();
+        results.sort_by(|a, b| b.rank.total_cmp(&a.rank).then(b.message_id.cmp(&a.message_id)));
+        Ok(results.into_iter().skip(query.offset.max(0) as usize).take(query.limit.max(0) as usize).collect())
+    }
+
+    async fn export_page(&self, room_id: Uuid, after: i64, limit: i64) -> StorageResult<Vec<ExportRow>> {
+        let data = self.data();
+        let now = Utc::now();
+        Ok(data
+            .messages
+            .range(after.saturating_add(1)..)
+            .map(|(_, m)| m)
+            .filter(|m| m.room_id == room_id && m.is_live(now))
+            .take(limit.max(0) as usize)
+            .map(|m| {
+                let deleted = m.deleted_at.is_some();
+                ExportRow {
+                    id: m.id,
+                    parent_id: m.parent_id,
+                    user_id: m.user_id,
+                    username: data.username(m.user_id),
+                    content: (!deleted).then(|| m.content.clone()),
+                    created_at: m.created_at,
+                    edited_at: m.edited_at,
+                    deleted_at: m.deleted_at,
+                }
+            })
+            .collect())
+    }
+
+    async fn expire_messages(&self, limit: i64) -> StorageResult<PurgedMessages> {
+        let mut data = self.data();
+        let now = Utc::now();
+        let mut expired: Vec<&StoredMessage> =
+            data.messages.values().filter(|m| m.expires_at.is_some_and(|at| at <= now)).collect();
+        expired.sort_by_key(|m| (m.expires_at, m.id));
+        let expired: Vec<(i64, Uuid)> =
+            expired.into_iter().take(limit.max(0) as usize).map(|m| (m.id, m.room_id)).collect();
+
+        // Replies go with their thread, whether or not they expired themselves.
+        Ok(data.purge(expired, true))
+    }
+
+    async fn purge_retained(&self, limit: i64) -> StorageResult<PurgedMessages> {
+        let mut data = self.data();
+        let now = Utc::now();
+        let expired: Vec<(i64, Uuid)> = data
+            .messages
+            .values()
+            .filter(|m| {
+                data.rooms
+                    .get(&m.room_id)
+                    .and_then(|room| room.retention_days)
+                    .is_some_and(|days| m.created_at < now - Duration::days(days.into()))
+            })
+            .take(limit.max(0) as usize)
+            .map(|m| (m.id, m.room_id))
+            .collect();
+        Ok(data.purge(expired, false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(room_id: Uuid, user_id: Uuid, content: &str) -> NewMessage<'_> {
+        NewMessage { room_id, user_id, content, parent_id: None, attachment_ids: &[], ttl_seconds: None, quote: None }
+    }
+
+    async fn user(store: &MemoryStore, username: &str) -> Uuid {
+        store.create_user(username, "hash").await.unwrap().expect("username is free")
+    }
+
+    async fn room(store: &MemoryStore, name: &str, creator: Uuid) -> Uuid {
+        store.create_room(name, None, None, creator).await.unwrap().expect("room name is free").id
+    }
+
+    async fn post(store: &MemoryStore, message: NewMessage<'_>) -> i64 {
+        store.insert_message(message).await.unwrap().expect("message is valid").id
+    }
+
+    fn ids(messages: &[MessageRecord]) -> Vec<i64> {
+        messages.iter().map(|m| m.id).collect()
+    }
+
+    fn search_query(text: &str, viewer: Uuid) -> SearchQuery<'_> {
+        SearchQuery { text, viewer, room_id: None, author_id: None, from: None, to: None, limit: 10, offset: 0 }
+    }
+
+    /// The ids of the messages `viewer` finds with `text`, in id order.
+    async fn found(store: &MemoryStore, text: &str, viewer: Uuid) -> Vec<i64> {
+        let mut ids: Vec<i64> = store.search(search_query(text, viewer)).await.unwrap().iter().map(|r| r.message_id).collect();
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn usernames_are_unique() {
+        let store = MemoryStore::default();
+        let alice = user(&store, "alice").await;
+
+        assert!(store.create_user("alice", "other").await.unwrap().is_none());
+        let credentials = store.find_credentials("alice").await.unwrap().unwrap();
+        assert_eq!(credentials.id, alice);
+        assert_eq!(credentials.password_hash, "hash");
+        assert!(store.find_credentials("bob").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn room_names_are_unique() {
+        let store = MemoryStore::default();
+        let alice = user(&store, "alice").await;
+        let general = room(&store, "general", alice).await;
+
+        assert!(store.create_room("general", Some(7), None, alice).await.unwrap().is_none());
+        assert!(store.is_moderator(general, alice).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn history_pages_before_and_after() {
+        let store = MemoryStore::default();
+        let alice = user(&store, "alice").await;
+        let general = room(&store, "general", alice).await;
+        let mut posted = Vec::new();
+        for content in ["one", "two", "three", "four", "five"] {
+            posted.push(post(&store, message(general, alice, content)).await);
+        }
+        let page = |before, after| HistoryQuery { room_id: general, viewer: alice, before, after, limit: 2 };
+
+        let latest = store.history(page(None, None)).await.unwrap();
+        assert_eq!(ids(&latest), [posted[4], posted[3]]);
+        let older = store.history(page(Some(posted[3]), None)).await.unwrap();
+        assert_eq!(ids(&older), [posted[2], posted[1]]);
+        let newer = store.history(page(None, Some(posted[1]))).await.unwrap();
+        assert_eq!(ids(&newer), [posted[2], posted[3]]);
+    }
+
+    #[tokio::test]
+    async fn replies_resolve_to_their_thread_root() {
+        let store = MemoryStore::default();
+        let alice = user(&store, "alice").await;
+        let general = room(&store, "general", alice).await;
+        let random = room(&store, "random", alice).await;
+        let root = post(&store, message(general, alice, "root")).await;
+        let reply = post(&store, NewMessage { parent_id: Some(root), ..message(general, alice, "reply") }).await;
+
+        assert_eq!(store.thread_root(general, root).await.unwrap(), Some(root));
+        assert_eq!(store.thread_root(general, reply).await.unwrap(), Some(root));
+        assert_eq!(store.thread_root(random, reply).await.unwrap(), None);
+
+        let history = store.history(HistoryQuery { room_id: general, viewer: alice, before: None, after: None, limit: 10 });
+        let history = history.await.unwrap();
+        assert_eq!(ids(&history), [root]);
+        assert_eq!(history[0].reply_count, 1);
+        let thread = store.thread(general, root, alice).await.unwrap();
+        assert_eq!(ids(&thread), [root, reply]);
+    }
+
+    #[tokio::test]
+    async fn expired_messages_are_hidden_then_removed() {
+        let store = MemoryStore::default();
+        let alice = user(&store, "alice").await;
+        let bob = user(&store, "bob").await;
+        let general = room(&store, "general", alice).await;
+        let kept = post(&store, message(general, alice, "kept")).await;
+        let ephemeral = post(&store, NewMessage { ttl_seconds: Some(60), ..message(general, alice, "gone") }).await;
+        let reply = post(&store, NewMessage { parent_id: Some(ephemeral), ..message(general, alice, "with it") }).await;
+        let history = || store.history(HistoryQuery { room_id: general, viewer: alice, before: None, after: None, limit: 10 });
+
+        assert_eq!(ids(&history().await.unwrap()), [ephemeral, kept]);
+        assert_eq!(store.list_rooms(bob).await.unwrap()[0].unread_count, 3);
+        assert!(store.expire_messages(10).await.unwrap().messages.is_empty());
+
+        store.data().messages.get_mut(&ephemeral).unwrap().expires_at = Some(Utc::now() - Duration::seconds(1));
+        assert_eq!(ids(&history().await.unwrap()), [kept]);
+        assert_eq!(store.list_rooms(bob).await.unwrap()[0].unread_count, 2);
+        assert!(store.quote_source(ephemeral).await.unwrap().is_none());
+        let mut expired = store.expire_messages(10).await.unwrap().messages;
+        expired.sort();
+        assert_eq!(expired, [(ephemeral, general), (reply, general)]);
+        assert!(store.thread(general, ephemeral, alice).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn unread_and_mention_counts_follow_read_position() {
+        let store = MemoryStore::default();
+        let alice = user(&store, "alice").await;
+        let bob = user(&store, "bob").await;
+        let general = room(&store, "general", alice).await;
+        let hello = post(&store, message(general, alice, "hello")).await;
+        let ping = post(&store, message(general, alice, "@bob ping")).await;
+        assert_eq!(store.record_mentions(ping, general, alice, &["bob".to_string()]).await.unwrap(), [bob]);
+        post(&store, message(general, bob, "pong")).await;
+
+        let listing = store.list_rooms(bob).await.unwrap();
+        assert_eq!((listing[0].unread_count, listing[0].mention_count), (2, 1));
+
+        assert!(store.mark_read(general, bob, hello).await.unwrap());
+        let listing = store.list_rooms(bob).await.unwrap();
+        assert_eq!((listing[0].unread_count, listing[0].mention_count), (1, 1));
+
+        assert!(store.mark_read(general, bob, ping).await.unwrap());
+        assert!(!store.mark_read(general, bob, hello).await.unwrap());
+        let listing = store.list_rooms(bob).await.unwrap();
+        assert_eq!((listing[0].unread_count, listing[0].mention_count), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn direct_rooms_are_private_to_their_participants() {
+        let store = MemoryStore::default();
+        let alice = user(&store, "alice").await;
+        let bob = user(&store, "bob").await;
+        let carol = user(&store, "carol").await;
+        let general = room(&store, "general", alice).await;
+
+        let direct = store.open_direct(alice, bob).await.unwrap();
+        assert_eq!(store.open_direct(bob, alice).await.unwrap(), direct);
+        assert!(store.can_access(direct, bob).await.unwrap());
+        assert!(!store.can_access(direct, carol).await.unwrap());
+        assert!(store.can_access(general, carol).await.unwrap());
+        assert!(!store.can_access(Uuid::new_v4(), alice).await.unwrap());
+        let listed: Vec<Uuid> = store.list_rooms(alice).await.unwrap().iter().map(|r| r.id).collect();
+        assert_eq!(listed, [general]);
+    }
+
+    #[tokio::test]
+    async fn edits_deletes_and_reactions_show_in_history() {
+        let store = MemoryStore::default();
+        let alice = user(&store, "alice").await;
+        let bob = user(&store, "bob").await;
+        let general = room(&store, "general", alice).await;
+        let first = post(&store, message(general, bob, "frist")).await;
+        let second = post(&store, message(general, bob, "oops")).await;
+
+        assert!(store.edit_message(first, alice, "mine now").await.unwrap().is_none());
+        assert!(store.edit_message(first, bob, "first").await.unwrap().is_some());
+        assert!(store.set_reaction(first, alice, "👍", true).await.unwrap().is_some());
+        assert!(store.set_reaction(first, alice, "👍", true).await.unwrap().is_none());
+        // Moderators may delete anyone's message.
+        assert!(store.delete_message(second, alice).await.unwrap().is_some());
+
+        let history = store.history(HistoryQuery { room_id: general, viewer: alice, before: None, after: None, limit: 10 });
+        let history = history.await.unwrap();
+        let (deleted, edited) = (&history[0], &history[1]);
+        assert!(deleted.deleted && deleted.content.is_none());
+        assert_eq!(deleted.deleted_by, Some(alice));
+        assert!(edited.edited);
+        assert_eq!(edited.content.as_deref(), Some("first"));
+        assert_eq!(edited.reactions.0.len(), 1);
+        assert!(edited.reactions.0[0].reacted);
+    }
+
+    #[tokio::test]
+    async fn search_matches_phrases_and_exclusions() {
+        let store = MemoryStore::default();
+        let alice = user(&store, "alice").await;
+        let bob = user(&store, "bob").await;
+        let carol = user(&store, "carol").await;
+        let general = room(&store, "general", alice).await;
+        let plan = post(&store, message(general, alice, "Lunch plan: <b>tacos</b> & salsa")).await;
+        let cancelled = post(&store, message(general, bob, "lunch is cancelled")).await;
+        let dinner = post(&store, message(general, bob, "dinner plan")).await;
+        let direct = store.open_direct(alice, bob).await.unwrap();
+        let secret = post(&store, message(direct, alice, "secret lunch plan")).await;
+
+        assert_eq!(found(&store, "lunch", carol).await, [plan, cancelled]);
+        assert_eq!(found(&store, "lunch", bob).await, [plan, cancelled, secret]);
+        assert_eq!(found(&store, "\"lunch plan\"", bob).await, [plan, secret]);
+        assert_eq!(found(&store, "lunch -cancelled", carol).await, [plan]);
+        assert_eq!(found(&store, "tacos or dinner", carol).await, [plan, dinner]);
+        assert!(found(&store, "-lunch", carol).await.is_empty());
+
+        let results = store.search(search_query("tacos", carol)).await.unwrap();
+        assert_eq!(results[0].snippet, "Lunch plan: &lt;b&gt;<mark>tacos</mark>&lt;/b&gt; &amp; salsa");
+    }
+
+    #[tokio::test]
+    async fn scheduled_messages_are_claimed_once_when_due() {
+        let store = MemoryStore::default();
+        let alice = user(&store, "alice").await;
+        let bob = user(&store, "bob").await;
+        let general = room(&store, "general", alice).await;
+        let attachment = Uuid::new_v4();
+        let upload = NewAttachment {
+            id: attachment,
+            uploader_id: alice,
+            room_id: general,
+            file_name: "notes.txt",
+            content_type: "text/plain",
+            size_bytes: 5,
+        };
+        store.insert_attachment(upload).await.unwrap();
+        let now = Utc::now();
+
+        let later = store.schedule_message(message(general, alice, "later"), now + Duration::hours(1)).await.unwrap();
+        let later = later.expect("message is valid");
+        let attached = NewMessage { attachment_ids: &[attachment], ..message(general, alice, "now") };
+        let due = store.schedule_message(attached, now - Duration::seconds(1)).await.unwrap();
+        let due = due.expect("attachment belongs to alice");
+        let stolen = NewMessage { attachment_ids: &[attachment], ..message(general, bob, "mine") };
+        assert!(store.schedule_message(stolen, now).await.unwrap().is_none());
+
+        let scheduled: Vec<i64> = store.list_scheduled(alice).await.unwrap().iter().map(|s| s.id).collect();
+        assert_eq!(scheduled, [due, later]);
+        let claimed = store.claim_scheduled(10).await.unwrap();
+        assert_eq!(claimed.iter().map(|m| m.id).collect::<Vec<_>>(), [due]);
+        assert_eq!(claimed[0].attachment_ids, [attachment]);
+        assert!(store.claim_scheduled(10).await.unwrap().is_empty());
+        assert!(!store.cancel_scheduled(due, alice).await.unwrap());
+
+        store.finish_scheduled(due).await.unwrap();
+        assert!(!store.cancel_scheduled(later, bob).await.unwrap());
+        assert!(store.cancel_scheduled(later, alice).await.unwrap());
+        assert!(store.list_scheduled(alice).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn pins_and_bookmarks_follow_their_message() {
+        let store = MemoryStore::default();
+        let alice = user(&store, "alice").await;
+        let bob = user(&store, "bob").await;
+        let general = room(&store, "general", alice).await;
+        let note = post(&store, message(general, bob, "remember this")).await;
+
+        // Only moderators pin, and pinning twice changes nothing.
+        assert!(store.set_pinned(note, bob, true).await.unwrap().is_none());
+        assert_eq!(store.set_pinned(note, alice, true).await.unwrap(), Some(general));
+        assert!(store.set_pinned(note, alice, true).await.unwrap().is_none());
+        let pins = store.list_pins(general).await.unwrap();
+        assert_eq!(pins.iter().map(|p| (p.message_id, p.pinned_by)).collect::<Vec<_>>(), [(note, Some(alice))]);
+
+        let due = Utc::now() - Duration::seconds(1);
+        store.save_bookmark(bob, note, Some("todo"), Some(due)).await.unwrap();
+        let reminders = store.claim_reminders(&[alice, bob]).await.unwrap();
+        assert_eq!(reminders.iter().map(|r| (r.user_id, r.message_id, r.room_id)).collect::<Vec<_>>(), [(bob, note, general)]);
+        assert!(store.claim_reminders(&[bob]).await.unwrap().is_empty());
+
+        assert!(store.delete_message(note, bob).await.unwrap().is_some());
+        assert!(store.list_pins(general).await.unwrap().is_empty());
+        let bookmarks = store.bookmarks(bob, None).await.unwrap();
+        assert!(bookmarks[0].deleted && bookmarks[0].content.is_none());
+        assert_eq!(bookmarks[0].note.as_deref(), Some("todo"));
+        assert!(bookmarks[0].reminded_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn retention_purges_old_messages_with_their_replies() {
+        let store = MemoryStore::default();
+        let alice = user(&store, "alice").await;
+        let kept = store.create_room("kept", None, None, alice).await.unwrap().unwrap().id;
+        let purged = store.create_room("purged", Some(7), None, alice).await.unwrap().unwrap().id;
+        let old = post(&store, message(purged, alice, "old")).await;
+        let reply = post(&store, NewMessage { parent_id: Some(old), ..message(purged, alice, "recent reply") }).await;
+        let recent = post(&store, message(purged, alice, "recent")).await;
+        let forever = post(&store, message(kept, alice, "forever")).await;
+        let month_ago = Utc::now() - Duration::days(30);
+        for id in [old, forever] {
+            store.data().messages.get_mut(&id).unwrap().created_at = month_ago;
+        }
+        let removed = store.purge_retained(10).await.unwrap();
+        assert_eq!(removed.messages, [(old, purged)]);
+        assert!(store.purge_retained(10).await.unwrap().messages.is_empty());
+        let history = |room_id| store.history(HistoryQuery { room_id, viewer: alice, before: None, after: None, limit: 10 });
+        assert_eq!(ids(&history(purged).await.unwrap()), [recent]);
+        assert_eq!(ids(&history(kept).await.unwrap()), [forever]);
+        assert_eq!(store.thread_root(purged, reply).await.unwrap(), None);
+    }
+}
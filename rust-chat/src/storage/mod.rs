@@ -0,0 +1,364 @@
+// src/storage/mod.rs
+
+// Storage traits for everything the chat keeps: users, rooms, messages and
+// what hangs off them (reactions, mentions, pins, attachments, bookmarks,
+// scheduled messages, ...).
+//
+// The server talks to these through `Storage`, which is chosen at startup by
+// the `storage` setting. `postgres` is the production backend; `memory` keeps
+// everything in process so the server can run without a database, and
+// `sqlite` (behind the `sqlite` cargo feature) uses a single file. Handlers,
+// WebSocket commands and background tasks behave the same on all three.
+
+pub mod memory;
+pub mod postgres;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+mod text_search;
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::models::{
+    AttachmentSummary, BookmarkRecord, DirectConversation, ExportRow, MentionRecord, MessageRecord, PinRecord,
+    QuotedMessage, RoomListing, RoomRecord, ScheduledRecord, SearchResult, UserRecord,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[cfg(feature = "sqlite")]
+    #[error("migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+}
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+/// What `login` needs to verify a password.
+#[derive(sqlx::FromRow)]
+pub struct UserCredentials {
+    pub id: Uuid,
+    pub password_hash: String,
+}
+
+/// A message to be stored by `MessageStore::insert_message`.
+pub struct NewMessage<'a> {
+    pub room_id: Uuid,
+    pub user_id: Uuid,
+    pub content: &'a str,
+    pub parent_id: Option<i64>,
+    pub attachment_ids: &'a [Uuid],
+    // Lifetime of the message; the room's `message_ttl_seconds` applies when `None`.
+    pub ttl_seconds: Option<i32>,
+    pub quote: Option<&'a QuotedMessage>,
+}
+
+/// A message that may be forwarded or quoted; see `MessageStore::quote_source`.
+pub struct QuoteSource {
+    pub message: QuotedMessage, // With `forwarded` unset.
+    pub quote: Option<QuotedMessage>, // What the message itself quotes or forwards.
+}
+
+/// A message as stored by `MessageStore::insert_message`.
+pub struct InsertedMessage {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub attachments: Vec<AttachmentSummary>,
+}
+
+/// Selects a page of top-level messages; see `chat::get_history`.
+pub struct HistoryQuery {
+    pub room_id: Uuid,
+    pub viewer: Uuid, // Reactions are reported from this user's point of view.
+    pub before: Option<i64>,
+    pub after: Option<i64>,
+    pub limit: i64,
+}
+
+/// A scheduled message that came due; see `MessageStore::claim_scheduled`.
+pub struct DueMessage {
+    pub id: i64,
+    pub room_id: Uuid,
+    pub user_id: Uuid,
+    pub content: String,
+    pub parent_id: Option<i64>,
+    pub attachment_ids: Vec<Uuid>,
+    pub send_at: DateTime<Utc>,
+    pub ttl_seconds: Option<i32>,
+    pub quote_id: Option<i64>,
+}
+
+/// A bookmark whose `remind_at` passed; see `MessageStore::claim_reminders`.
+#[derive(sqlx::FromRow)]
+pub struct DueReminder {
+    pub user_id: Uuid,
+    pub message_id: i64,
+    pub room_id: Uuid,
+    pub note: Option<String>,
+    pub remind_at: DateTime<Utc>,
+}
+
+/// An uploaded file to record; see `MessageStore::insert_attachment`.
+pub struct NewAttachment<'a> {
+    pub id: Uuid, // Also the name of the file under `upload_dir`.
+    pub uploader_id: Uuid,
+    pub room_id: Uuid,
+    pub file_name: &'a str,
+    pub content_type: &'a str,
+    pub size_bytes: i64,
+}
+
+/// What `download_attachment` needs to decide who may fetch a file.
+#[derive(sqlx::FromRow)]
+pub struct AttachmentAccess {
+    pub uploader_id: Uuid,
+    pub room_id: Uuid,
+    pub file_name: String,
+    pub content_type: String,
+    // Linked to a message that is neither deleted nor expired.
+    pub visible: bool,
+}
+
+/// Filters of `search::search_messages`.
+pub struct SearchQuery<'a> {
+    pub text: &'a str,
+    pub viewer: Uuid, // Only rooms this user may read are searched.
+    pub room_id: Option<Uuid>,
+    pub author_id: Option<Uuid>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+/// Messages removed by expiry or retention, and the attachments whose files
+/// the caller must now delete from `upload_dir`.
+#[derive(Default)]
+pub struct PurgedMessages {
+    pub messages: Vec<(i64, Uuid)>, // (id, room_id)
+    pub attachment_ids: Vec<Uuid>,
+}
+
+/// Direct rooms are named after both participants, lowest id first, so the
+/// same pair always maps to the same room.
+fn direct_room_name(a: Uuid, b: Uuid) -> String {
+    let (low, high) = if a < b { (a, b) } else { (b, a) };
+    format!("dm:{}:{}", low, high)
+}
+
+#[rocket::async_trait]
+pub trait UserStore: Send + Sync {
+    /// Creates a user, or returns `None` if the username is taken.
+    async fn create_user(&self, username: &str, password_hash: &str) -> StorageResult<Option<Uuid>>;
+    async fn find_credentials(&self, username: &str) -> StorageResult<Option<UserCredentials>>;
+    /// The users among `ids` that exist, ordered by username.
+    async fn get_users(&self, ids: &[Uuid]) -> StorageResult<Vec<UserRecord>>;
+}
+
+#[rocket::async_trait]
+pub trait RoomStore: Send + Sync {
+    /// Creates a room moderated by `creator`, or returns `None` if the name is taken.
+    async fn create_room(
+        &self,
+        name: &str,
+        retention_days: Option<i32>,
+        message_ttl_seconds: Option<i32>,
+        creator: Uuid,
+    ) -> StorageResult<Option<RoomRecord>>;
+    /// Non-direct rooms with `user_id`'s unread and mention counts and draft,
+    /// ordered by name.
+    async fn list_rooms(&self, user_id: Uuid) -> StorageResult<Vec<RoomListing>>;
+    /// Whether `user_id` may join and read `room_id`. Public rooms are open to
+    /// every user; direct rooms only to their two participants. Unknown rooms
+    /// are never accessible.
+    async fn can_access(&self, room_id: Uuid, user_id: Uuid) -> StorageResult<bool>;
+    async fn add_member(&self, room_id: Uuid, user_id: Uuid) -> StorageResult<()>;
+    /// Returns `false` if the user was not a member or the room is direct,
+    /// which cannot be left.
+    async fn remove_member(&self, room_id: Uuid, user_id: Uuid) -> StorageResult<bool>;
+    /// Every `(room_id, user_id)` membership, used to hydrate `ChatServerState`.
+    async fn memberships(&self) -> StorageResult<Vec<(Uuid, Uuid)>>;
+    /// Replaces `user_id`'s draft in `room_id`. An empty `content` deletes it.
+    async fn save_draft(
+        &self,
+        room_id: Uuid,
+        user_id: Uuid,
+        content: &str,
+        updated_at: DateTime<Utc>,
+    ) -> StorageResult<()>;
+    async fn get_room(&self, room_id: Uuid) -> StorageResult<Option<RoomRecord>>;
+    async fn is_moderator(&self, room_id: Uuid, user_id: Uuid) -> StorageResult<bool>;
+    async fn add_moderator(&self, room_id: Uuid, user_id: Uuid) -> StorageResult<()>;
+    /// Returns the updated room, or `None` if it does not exist.
+    async fn set_retention(&self, room_id: Uuid, retention_days: Option<i32>) -> StorageResult<Option<RoomRecord>>;
+    /// Returns the updated room, or `None` if it does not exist.
+    async fn set_message_ttl(
+        &self,
+        room_id: Uuid,
+        message_ttl_seconds: Option<i32>,
+    ) -> StorageResult<Option<RoomRecord>>;
+    /// Moves the user's read marker in `room_id` forward to `message_id` and
+    /// acknowledges their mentions up to that point. The marker never moves
+    /// backwards; returns `true` only if it advanced.
+    async fn mark_read(&self, room_id: Uuid, user_id: Uuid, message_id: i64) -> StorageResult<bool>;
+    /// The direct room of two users, created with both as participants and
+    /// members on first use.
+    async fn open_direct(&self, user_id: Uuid, other_id: Uuid) -> StorageResult<Uuid>;
+    /// `user_id`'s direct conversations ordered by the other participant's
+    /// name, or only the one in `room_id`.
+    async fn direct_conversations(
+        &self,
+        user_id: Uuid,
+        room_id: Option<Uuid>,
+    ) -> StorageResult<Vec<DirectConversation>>;
+}
+
+#[rocket::async_trait]
+pub trait MessageStore: Send + Sync {
+    /// Stores a message and links its attachments to it. Returns `None`, and
+    /// stores nothing, if any attachment is not an unused upload of the author
+    /// in the message's room.
+    async fn insert_message(&self, message: NewMessage<'_>) -> StorageResult<Option<InsertedMessage>>;
+    /// `message_id` as it would be quoted, or `None` if it does not exist or
+    /// cannot be quoted: deleted and ephemeral messages never are.
+    async fn quote_source(&self, message_id: i64) -> StorageResult<Option<QuoteSource>>;
+    /// The root of `message_id`'s thread, or `None` if it is not in `room_id`.
+    async fn thread_root(&self, room_id: Uuid, message_id: i64) -> StorageResult<Option<i64>>;
+    /// Up to `query.limit` top-level messages, newest first unless `after` is
+    /// set. Expired messages are left out even before they are deleted.
+    async fn history(&self, query: HistoryQuery) -> StorageResult<Vec<MessageRecord>>;
+    /// The thread of `root_id` in `room_id`: the root, then its replies oldest
+    /// first. The first record is not `root_id` if that is not a top-level
+    /// message of the room.
+    async fn thread(&self, room_id: Uuid, root_id: i64, viewer: Uuid) -> StorageResult<Vec<MessageRecord>>;
+    /// The room of `message_id`, or `None` if it is deleted or expired.
+    async fn live_message_room(&self, message_id: i64) -> StorageResult<Option<Uuid>>;
+
+    /// Replaces the content of a message authored by `user_id`, keeping the
+    /// previous text as a revision. Returns the room and edit time, or `None`
+    /// if the message does not exist, is deleted or belongs to someone else.
+    async fn edit_message(
+        &self,
+        message_id: i64,
+        user_id: Uuid,
+        content: &str,
+    ) -> StorageResult<Option<(Uuid, DateTime<Utc>)>>;
+    /// Soft-deletes a message, leaving a tombstone behind. Authors may delete
+    /// their own messages; room moderators may delete anyone's. Returns the
+    /// room and deletion time, or `None` if the message does not exist, is
+    /// already deleted, or the user lacks permission.
+    async fn delete_message(&self, message_id: i64, user_id: Uuid) -> StorageResult<Option<(Uuid, DateTime<Utc>)>>;
+    /// Adds or removes one reaction of a user who can read the message.
+    /// Returns the message's room and the new count for `emoji`, or `None` if
+    /// nothing changed.
+    async fn set_reaction(
+        &self,
+        message_id: i64,
+        user_id: Uuid,
+        emoji: &str,
+        added: bool,
+    ) -> StorageResult<Option<(Uuid, i64)>>;
+    /// Pins or unpins a message on behalf of a room moderator. Returns the room
+    /// of the message, or `None` if the user is not a moderator, the message is
+    /// missing or deleted, or the pin was already in the requested state.
+    async fn set_pinned(&self, message_id: i64, user_id: Uuid, pinned: bool) -> StorageResult<Option<Uuid>>;
+    /// Live pinned messages of a room, most recently pinned first.
+    async fn list_pins(&self, room_id: Uuid) -> StorageResult<Vec<PinRecord>>;
+
+    /// Stores a mention for every existing user named in `usernames`, skipping
+    /// the author and anyone who cannot read the room (a third party named in
+    /// a direct conversation). Returns the ids of the users that were mentioned.
+    async fn record_mentions(
+        &self,
+        message_id: i64,
+        room_id: Uuid,
+        author_id: Uuid,
+        usernames: &[String],
+    ) -> StorageResult<Vec<Uuid>>;
+    /// `user_id`'s unacknowledged mentions in live messages, newest first.
+    async fn list_mentions(&self, user_id: Uuid) -> StorageResult<Vec<MentionRecord>>;
+    /// Returns `false` if there was no pending mention to acknowledge.
+    async fn acknowledge_mention(&self, message_id: i64, user_id: Uuid) -> StorageResult<bool>;
+
+    /// `user_id`'s bookmarks of unexpired messages, newest first, or only the
+    /// one of `message_id`.
+    async fn bookmarks(&self, user_id: Uuid, message_id: Option<i64>) -> StorageResult<Vec<BookmarkRecord>>;
+    /// Creates a bookmark, or replaces its note and reminder and re-arms it.
+    async fn save_bookmark(
+        &self,
+        user_id: Uuid,
+        message_id: i64,
+        note: Option<&str>,
+        remind_at: Option<DateTime<Utc>>,
+    ) -> StorageResult<()>;
+    /// Returns `false` if there was no such bookmark.
+    async fn delete_bookmark(&self, user_id: Uuid, message_id: i64) -> StorageResult<bool>;
+    /// Marks as sent, and returns, the due reminders of `user_ids` on
+    /// messages that are not deleted.
+    async fn claim_reminders(&self, user_ids: &[Uuid]) -> StorageResult<Vec<DueReminder>>;
+
+    /// Stores a message to be delivered at `send_at`. Returns its id, or
+    /// `None` if the author cannot attach all of its attachments to a message
+    /// in the room. They are only linked at delivery.
+    async fn schedule_message(&self, message: NewMessage<'_>, send_at: DateTime<Utc>) -> StorageResult<Option<i64>>;
+    /// `user_id`'s pending scheduled messages, soonest first.
+    async fn list_scheduled(&self, user_id: Uuid) -> StorageResult<Vec<ScheduledRecord>>;
+    /// Returns `false` if `user_id` has no pending message `id`. A message
+    /// claimed for delivery is no longer pending, unless its claim is stale.
+    async fn cancel_scheduled(&self, id: i64, user_id: Uuid) -> StorageResult<bool>;
+    /// Claims up to `limit` due messages, oldest first. A claim keeps other
+    /// pollers away from a message until it goes stale after five minutes,
+    /// so one whose delivery failed is claimed again later.
+    async fn claim_scheduled(&self, limit: i64) -> StorageResult<Vec<DueMessage>>;
+    /// Removes a claimed message once it is delivered or dropped.
+    async fn finish_scheduled(&self, id: i64) -> StorageResult<()>;
+
+    /// Records an upload that is not linked to a message yet.
+    async fn insert_attachment(&self, attachment: NewAttachment<'_>) -> StorageResult<()>;
+    async fn attachment(&self, id: Uuid) -> StorageResult<Option<AttachmentAccess>>;
+
+    /// Live messages matching `query.text`, best match first.
+    async fn search(&self, query: SearchQuery<'_>) -> StorageResult<Vec<SearchResult>>;
+    /// Up to `limit` unexpired messages of a room, replies included, with an
+    /// id above `after`, in id order.
+    async fn export_page(&self, room_id: Uuid, after: i64, limit: i64) -> StorageResult<Vec<ExportRow>>;
+
+    /// Deletes up to `limit` messages past their `expires_at`, with their
+    /// replies and the attachments of both. Returns every deleted message.
+    async fn expire_messages(&self, limit: i64) -> StorageResult<PurgedMessages>;
+    /// Deletes up to `limit` messages older than their room's
+    /// `retention_days`. Replies and the attachments of both go with them;
+    /// only the selected messages are returned.
+    async fn purge_retained(&self, limit: i64) -> StorageResult<PurgedMessages>;
+}
+
+/// The storage backends in use, shared through Rocket's managed state.
+#[derive(Clone)]
+pub struct Storage {
+    pub users: Arc<dyn UserStore>,
+    pub rooms: Arc<dyn RoomStore>,
+    pub messages: Arc<dyn MessageStore>,
+}
+
+impl Storage {
+    pub fn postgres(pool: PgPool) -> Self {
+        let store = Arc::new(postgres::PgStore::new(pool));
+        Storage { users: store.clone(), rooms: store.clone(), messages: store }
+    }
+
+    pub fn memory() -> Self {
+        let store = Arc::new(memory::MemoryStore::default());
+        Storage { users: store.clone(), rooms: store.clone(), messages: store }
+    }
+
+    #[cfg(feature = "sqlite")]
+    pub async fn sqlite(path: &str) -> StorageResult<Self> {
+        let store = Arc::new(sqlite::SqliteStore::open(path).await?);
+        Ok(Storage { users: store.clone(), rooms: store.clone(), messages: store })
+    }
+}
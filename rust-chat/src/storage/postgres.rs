@@ -0,0 +1,1222 @@
+// src/storage/postgres.rs
+
+use chrono::{DateTime, Utc};
+use sqlx::{types::Json as SqlJson, PgPool};
+use uuid::Uuid;
+
+use super::{
+    direct_room_name, AttachmentAccess, DueMessage, DueReminder, HistoryQuery, InsertedMessage, MessageStore,
+    NewAttachment, NewMessage, PurgedMessages, QuoteSource, RoomStore, SearchQuery, StorageResult,
+    UserCredentials, UserStore,
+};
+use crate::access::{can_access_room, is_room_moderator};
+use crate::models::{
+    AttachmentSummary, BookmarkRecord, DirectConversation, ExportRow, MentionRecord, MessageRecord, PinRecord,
+    QuotedMessage, ReactionSummary, RoomListing, RoomRecord, ScheduledRecord, SearchResult, UserRecord,
+};
+
+pub struct PgStore {
+    pool: PgPool,
+}
+
+impl PgStore {
+    pub fn new(pool: PgPool) -> Self {
+        PgStore { pool }
+    }
+}
+
+#[rocket::async_trait]
+impl UserStore for PgStore {
+    async fn create_user(&self, username: &str, password_hash: &str) -> StorageResult<Option<Uuid>> {
+        let row = sqlx::query!(
+            "INSERT INTO users (username, password_hash) VALUES ($1, $2) ON CONFLICT (username) DO NOTHING RETURNING id",
+            username,
+            password_hash
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|r| r.id))
+    }
+
+    async fn find_credentials(&self, username: &str) -> StorageResult<Option<UserCredentials>> {
+        let row = sqlx::query_as!(
+            UserCredentials,
+            "SELECT id, password_hash FROM users WHERE username = $1",
+            username
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    async fn get_users(&self, ids: &[Uuid]) -> StorageResult<Vec<UserRecord>> {
+        let users = sqlx::query_as!(
+            UserRecord,
+            "SELECT id, username FROM users WHERE id = ANY($1) ORDER BY username",
+            ids
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(users)
+    }
+}
+
+#[rocket::async_trait]
+impl RoomStore for PgStore {
+    async fn create_room(
+        &self,
+        name: &str,
+        retention_days: Option<i32>,
+        message_ttl_seconds: Option<i32>,
+        creator: Uuid,
+    ) -> StorageResult<Option<RoomRecord>> {
+        let mut tx = self.pool.begin().await?;
+
+        // The `ON CONFLICT (name) DO NOTHING` clause prevents duplicate room names.
+        // If a conflict occurs, the query does nothing and returns no rows.
+        let Some(room) = sqlx::query_as!(
+            RoomRecord,
+            r#"
+            INSERT INTO rooms (name, retention_days, message_ttl_seconds) VALUES ($1, $2, $3)
+            ON CONFLICT (name) DO NOTHING
+            RETURNING id, name, retention_days, message_ttl_seconds
+            "#,
+            name,
+            retention_days,
+            message_ttl_seconds
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        // The creator of a room is its first moderator.
+        sqlx::query!(
+            "INSERT INTO room_moderators (room_id, user_id) VALUES ($1, $2)",
+            room.id,
+            creator
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(Some(room))
+    }
+
+    async fn list_rooms(&self, user_id: Uuid) -> StorageResult<Vec<RoomListing>> {
+        let rooms = sqlx::query_as!(
+            RoomListing,
+            r#"
+            SELECT r.id, r.name, r.retention_days, r.message_ttl_seconds,
+                   rr.last_read_message_id AS "last_read_message_id?",
+                   (SELECT COUNT(*) FROM messages m
+                    WHERE m.room_id = r.id AND m.deleted_at IS NULL AND m.user_id <> $1
+                      AND (m.expires_at IS NULL OR m.expires_at > NOW())
+                      AND m.id > COALESCE(rr.last_read_message_id, 0)) AS "unread_count!",
+                   (SELECT COUNT(*) FROM message_mentions mm
+                    JOIN messages m ON mm.message_id = m.id
+                    WHERE mm.user_id = $1 AND mm.acknowledged_at IS NULL
+                      AND m.room_id = r.id AND m.deleted_at IS NULL
+                      AND (m.expires_at IS NULL OR m.expires_at > NOW())) AS "mention_count!",
+                   d.content AS "draft?", d.updated_at AS "draft_updated_at?"
+            FROM rooms r
+            LEFT JOIN room_reads rr ON rr.room_id = r.id AND rr.user_id = $1
+            LEFT JOIN drafts d ON d.room_id = r.id AND d.user_id = $1
+            WHERE NOT r.is_direct
+            ORDER BY r.name
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rooms)
+    }
+
+    async fn can_access(&self, room_id: Uuid, user_id: Uuid) -> StorageResult<bool> {
+        Ok(can_access_room(&self.pool, room_id, user_id).await?)
+    }
+
+    async fn add_member(&self, room_id: Uuid, user_id: Uuid) -> StorageResult<()> {
+        sqlx::query!(
+            "INSERT INTO room_memberships (room_id, user_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            room_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn remove_member(&self, room_id: Uuid, user_id: Uuid) -> StorageResult<bool> {
+        // Direct conversations always include both participants.
+        let done = sqlx::query!(
+            r#"
+            DELETE FROM room_memberships rm
+            USING rooms r
+            WHERE rm.room_id = r.id AND rm.room_id = $1 AND rm.user_id = $2 AND NOT r.is_direct
+            "#,
+            room_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(done.rows_affected() > 0)
+    }
+
+    async fn memberships(&self) -> StorageResult<Vec<(Uuid, Uuid)>> {
+        let rows = sqlx::query!("SELECT room_id, user_id FROM room_memberships")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(|r| (r.room_id, r.user_id)).collect())
+    }
+
+    async fn save_draft(
+        &self,
+        room_id: Uuid,
+        user_id: Uuid,
+        content: &str,
+        updated_at: DateTime<Utc>,
+    ) -> StorageResult<()> {
+        if content.is_empty() {
+            sqlx::query!("DELETE FROM drafts WHERE room_id = $1 AND user_id = $2", room_id, user_id)
+                .execute(&self.pool)
+                .await?;
+            return Ok(());
+        }
+        sqlx::query!(
+            r#"
+            INSERT INTO drafts (user_id, room_id, content, updated_at) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id, room_id) DO UPDATE SET content = $3, updated_at = $4
+            "#,
+            user_id,
+            room_id,
+            content,
+            updated_at
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_room(&self, room_id: Uuid) -> StorageResult<Option<RoomRecord>> {
+        let room = sqlx::query_as!(
+            RoomRecord,
+            "SELECT id, name, retention_days, message_ttl_seconds FROM rooms WHERE id = $1",
+            room_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(room)
+    }
+
+    async fn is_moderator(&self, room_id: Uuid, user_id: Uuid) -> StorageResult<bool> {
+        Ok(is_room_moderator(&self.pool, room_id, user_id).await?)
+    }
+
+    async fn add_moderator(&self, room_id: Uuid, user_id: Uuid) -> StorageResult<()> {
+        sqlx::query!(
+            "INSERT INTO room_moderators (room_id, user_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            room_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn set_retention(&self, room_id: Uuid, retention_days: Option<i32>) -> StorageResult<Option<RoomRecord>> {
+        let room = sqlx::query_as!(
+            RoomRecord,
+            "UPDATE rooms SET retention_days = $1 WHERE id = $2 RETURNING id, name, retention_days, message_ttl_seconds",
+            retention_days,
+            room_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(room)
+    }
+
+    async fn set_message_ttl(
+        &self,
+        room_id: Uuid,
+        message_ttl_seconds: Option<i32>,
+    ) -> StorageResult<Option<RoomRecord>> {
+        let room = sqlx::query_as!(
+            RoomRecord,
+            "UPDATE rooms SET message_ttl_seconds = $1 WHERE id = $2 RETURNING id, name, retention_days, message_ttl_seconds",
+            message_ttl_seconds,
+            room_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(room)
+    }
+
+    async fn mark_read(&self, room_id: Uuid, user_id: Uuid, message_id: i64) -> StorageResult<bool> {
+        if !can_access_room(&self.pool, room_id, user_id).await? {
+            return Ok(false);
+        }
+
+        let mut tx = self.pool.begin().await?;
+
+        let advanced = sqlx::query!(
+            r#"
+            INSERT INTO room_reads (user_id, room_id, last_read_message_id)
+            SELECT $1, room_id, id FROM messages WHERE id = $3 AND room_id = $2
+            ON CONFLICT (user_id, room_id) DO UPDATE
+                SET last_read_message_id = EXCLUDED.last_read_message_id, updated_at = NOW()
+                WHERE room_reads.last_read_message_id < EXCLUDED.last_read_message_id
+            "#,
+            user_id,
+            room_id,
+            message_id
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            > 0;
+
+        if advanced {
+            sqlx::query!(
+                r#"
+                UPDATE message_mentions SET acknowledged_at = NOW()
+                WHERE user_id = $1 AND acknowledged_at IS NULL
+                  AND message_id IN (SELECT id FROM messages WHERE room_id = $2 AND id <= $3)
+                "#,
+                user_id,
+                room_id,
+                message_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(advanced)
+    }
+
+    async fn open_direct(&self, user_id: Uuid, other_id: Uuid) -> StorageResult<Uuid> {
+        let name = direct_room_name(user_id, other_id);
+        let mut tx = self.pool.begin().await?;
+
+        let created = sqlx::query!(
+            "INSERT INTO rooms (name, is_direct) VALUES ($1, TRUE) ON CONFLICT (name) DO NOTHING RETURNING id",
+            name
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let room_id = match created {
+            Some(row) => {
+                sqlx::query!(
+                    "INSERT INTO direct_participants (room_id, user_id) VALUES ($1, $2), ($1, $3)",
+                    row.id,
+                    user_id,
+                    other_id
+                )
+                .execute(&mut *tx)
+                .await?;
+                sqlx::query!(
+                    "INSERT INTO room_memberships (room_id, user_id) VALUES ($1, $2), ($1, $3)",
+                    row.id,
+                    user_id,
+                    other_id
+                )
+                .execute(&mut *tx)
+                .await?;
+                row.id
+            }
+            // The conversation already exists; a conflict means someone created it first.
+            None => sqlx::query!("SELECT id FROM rooms WHERE name = $1 AND is_direct", name)
+                .fetch_one(&mut *tx)
+                .await?
+                .id,
+        };
+
+        tx.commit().await?;
+        Ok(room_id)
+    }
+
+    async fn direct_conversations(
+        &self,
+        user_id: Uuid,
+        room_id: Option<Uuid>,
+    ) -> StorageResult<Vec<DirectConversation>> {
+        // Counts follow `list_rooms`.
+        let conversations = sqlx::query_as!(
+            DirectConversation,
+            r#"
+            SELECT me.room_id, other.user_id, u.username,
+                   rr.last_read_message_id AS "last_read_message_id?",
+                   (SELECT COUNT(*) FROM messages m
+                    WHERE m.room_id = me.room_id AND m.deleted_at IS NULL AND m.user_id <> $1
+                      AND (m.expires_at IS NULL OR m.expires_at > NOW())
+                      AND m.id > COALESCE(rr.last_read_message_id, 0)) AS "unread_count!",
+                   (SELECT COUNT(*) FROM message_mentions mm
+                    JOIN messages m ON mm.message_id = m.id
+                    WHERE mm.user_id = $1 AND mm.acknowledged_at IS NULL
+                      AND m.room_id = me.room_id AND m.deleted_at IS NULL
+                      AND (m.expires_at IS NULL OR m.expires_at > NOW())) AS "mention_count!",
+                   d.content AS "draft?", d.updated_at AS "draft_updated_at?"
+            FROM direct_participants me
+            JOIN direct_participants other ON other.room_id = me.room_id AND other.user_id <> me.user_id
+            JOIN users u ON other.user_id = u.id
+            LEFT JOIN room_reads rr ON rr.room_id = me.room_id AND rr.user_id = $1
+            LEFT JOIN drafts d ON d.room_id = me.room_id AND d.user_id = $1
+            WHERE me.user_id = $1 AND ($2::UUID IS NULL OR me.room_id = $2)
+            ORDER BY u.username
+            "#,
+            user_id,
+            room_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(conversations)
+    }
+}
+
+#[rocket::async_trait]
+impl MessageStore for PgStore {
+    async fn insert_message(&self, message: NewMessage<'_>) -> StorageResult<Option<InsertedMessage>> {
+        let NewMessage { room_id, user_id, content, parent_id, attachment_ids, ttl_seconds, quote } = message;
+        let mut tx = self.pool.begin().await?;
+
+        // `created_at` defaults to the same `NOW()`, so the lifetime is exact.
+        let inserted = sqlx::query!(
+            r#"
+            INSERT INTO messages (room_id, user_id, content, parent_id, expires_at, quote)
+            SELECT $1, $2, $3, $4,
+                   NOW() + make_interval(secs => COALESCE($5, r.message_ttl_seconds)::DOUBLE PRECISION),
+                   $6
+            FROM rooms r
+            WHERE r.id = $1
+            RETURNING id, created_at, expires_at
+            "#,
+            room_id,
+            user_id,
+            content,
+            parent_id,
+            ttl_seconds,
+            quote.map(SqlJson) as _
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let mut attachments = Vec::new();
+        if !attachment_ids.is_empty() {
+            attachments = sqlx::query_as!(
+                AttachmentSummary,
+                r#"
+                UPDATE attachments SET message_id = $1
+                WHERE id = ANY($2) AND uploader_id = $3 AND room_id = $4 AND message_id IS NULL
+                RETURNING id, file_name, content_type, size_bytes
+                "#,
+                inserted.id,
+                attachment_ids,
+                user_id,
+                room_id
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+
+            // Dropping the transaction rolls the message back.
+            let mut requested = attachment_ids.to_vec();
+            requested.sort();
+            requested.dedup();
+            if attachments.len() != requested.len() {
+                return Ok(None);
+            }
+        }
+
+        tx.commit().await?;
+        Ok(Some(InsertedMessage {
+            id: inserted.id,
+            created_at: inserted.created_at,
+            expires_at: inserted.expires_at,
+            attachments,
+        }))
+    }
+
+    async fn quote_source(&self, message_id: i64) -> StorageResult<Option<QuoteSource>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT m.id, m.room_id, r.name AS room_name, m.user_id, u.username, m.content, m.created_at,
+                   m.quote AS "quote: SqlJson<QuotedMessage>"
+            FROM messages m
+            JOIN rooms r ON m.room_id = r.id
+            JOIN users u ON m.user_id = u.id
+            WHERE m.id = $1 AND m.deleted_at IS NULL AND m.expires_at IS NULL
+            "#,
+            message_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| QuoteSource {
+            message: QuotedMessage {
+                message_id: r.id,
+                room_id: r.room_id,
+                room_name: r.room_name,
+                user_id: r.user_id,
+                username: r.username,
+                content: r.content,
+                created_at: r.created_at,
+                forwarded: false,
+            },
+            quote: r.quote.map(|q| q.0),
+        }))
+    }
+
+    async fn thread_root(&self, room_id: Uuid, message_id: i64) -> StorageResult<Option<i64>> {
+        let row = sqlx::query!(
+            "SELECT COALESCE(parent_id, id) AS \"root_id!\" FROM messages WHERE id = $1 AND room_id = $2",
+            message_id,
+            room_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|r| r.root_id))
+    }
+
+    async fn history(&self, query: HistoryQuery) -> StorageResult<Vec<MessageRecord>> {
+        let messages = sqlx::query_as!(
+            MessageRecord,
+            r#"
+            SELECT m.id, m.user_id, u.username, m.room_id,
+                   CASE WHEN m.deleted_at IS NULL THEN m.content END AS content,
+                   m.created_at,
+                   m.edited_at IS NOT NULL AS "edited!", m.edited_at,
+                   m.deleted_at IS NOT NULL AS "deleted!", m.deleted_at, m.deleted_by,
+                   m.expires_at, m.parent_id, t.reply_count AS "reply_count!", t.last_reply_at,
+                   CASE WHEN m.deleted_at IS NULL THEN message_reaction_summary(m.id, $2) ELSE '[]' END
+                       AS "reactions!: SqlJson<Vec<ReactionSummary>>",
+                   CASE WHEN m.deleted_at IS NULL THEN message_attachment_summary(m.id) ELSE '[]' END
+                       AS "attachments!: SqlJson<Vec<AttachmentSummary>>",
+                   CASE WHEN m.deleted_at IS NULL THEN m.quote END AS "quote: SqlJson<QuotedMessage>"
+            FROM messages m
+            JOIN users u ON m.user_id = u.id
+            CROSS JOIN LATERAL (
+                SELECT COUNT(*) AS reply_count, MAX(r.created_at) AS last_reply_at
+                FROM messages r
+                WHERE r.parent_id = m.id AND r.deleted_at IS NULL
+                  AND (r.expires_at IS NULL OR r.expires_at > NOW())
+            ) t
+            WHERE m.room_id = $1 AND m.parent_id IS NULL
+              AND (m.expires_at IS NULL OR m.expires_at > NOW())
+              AND ($3::BIGINT IS NULL OR m.id < $3)
+              AND ($4::BIGINT IS NULL OR m.id > $4)
+            ORDER BY CASE WHEN $4::BIGINT IS NOT NULL THEN m.id END ASC, m.id DESC
+            LIMIT $5
+            "#,
+            query.room_id,
+            query.viewer,
+            query.before,
+            query.after,
+            query.limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(messages)
+    }
+
+    async fn thread(&self, room_id: Uuid, root_id: i64, viewer: Uuid) -> StorageResult<Vec<MessageRecord>> {
+        let messages = sqlx::query_as!(
+            MessageRecord,
+            r#"
+            SELECT m.id, m.user_id, u.username, m.room_id,
+                   CASE WHEN m.deleted_at IS NULL THEN m.content END AS content,
+                   m.created_at,
+                   m.edited_at IS NOT NULL AS "edited!", m.edited_at,
+                   m.deleted_at IS NOT NULL AS "deleted!", m.deleted_at, m.deleted_by,
+                   m.expires_at, m.parent_id, t.reply_count AS "reply_count!", t.last_reply_at,
+                   CASE WHEN m.deleted_at IS NULL THEN message_reaction_summary(m.id, $2) ELSE '[]' END
+                       AS "reactions!: SqlJson<Vec<ReactionSummary>>",
+                   CASE WHEN m.deleted_at IS NULL THEN message_attachment_summary(m.id) ELSE '[]' END
+                       AS "attachments!: SqlJson<Vec<AttachmentSummary>>",
+                   CASE WHEN m.deleted_at IS NULL THEN m.quote END AS "quote: SqlJson<QuotedMessage>"
+            FROM messages m
+            JOIN users u ON m.user_id = u.id
+            CROSS JOIN LATERAL (
+                SELECT COUNT(*) AS reply_count, MAX(r.created_at) AS last_reply_at
+                FROM messages r
+                WHERE r.parent_id = m.id AND r.deleted_at IS NULL
+                  AND (r.expires_at IS NULL OR r.expires_at > NOW())
+            ) t
+            WHERE m.room_id = $1 AND (m.id = $3 OR m.parent_id = $3)
+              AND (m.expires_at IS NULL OR m.expires_at > NOW())
+            ORDER BY m.parent_id NULLS FIRST, m.created_at ASC, m.id ASC
+            "#,
+            room_id,
+            viewer,
+            root_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(messages)
+    }
+
+    async fn live_message_room(&self, message_id: i64) -> StorageResult<Option<Uuid>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT room_id FROM messages
+            WHERE id = $1 AND deleted_at IS NULL
+              AND (expires_at IS NULL OR expires_at > NOW())
+            "#,
+            message_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|r| r.room_id))
+    }
+
+    async fn edit_message(
+        &self,
+        message_id: i64,
+        user_id: Uuid,
+        content: &str,
+    ) -> StorageResult<Option<(Uuid, DateTime<Utc>)>> {
+        let mut tx = self.pool.begin().await?;
+
+        // Lock the row so two concurrent edits cannot both record the same prior text.
+        let current = sqlx::query!(
+            "SELECT room_id, user_id, content FROM messages WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+            message_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let current = match current {
+            Some(row) if row.user_id == user_id => row,
+            _ => return Ok(None),
+        };
+
+        sqlx::query!(
+            "INSERT INTO message_revisions (message_id, content) VALUES ($1, $2)",
+            message_id,
+            current.content
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let updated = sqlx::query!(
+            "UPDATE messages SET content = $1, edited_at = NOW() WHERE id = $2 RETURNING edited_at AS \"edited_at!\"",
+            content,
+            message_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(Some((current.room_id, updated.edited_at)))
+    }
+
+    async fn delete_message(&self, message_id: i64, user_id: Uuid) -> StorageResult<Option<(Uuid, DateTime<Utc>)>> {
+        let mut tx = self.pool.begin().await?;
+
+        let current = match sqlx::query!(
+            "SELECT room_id, user_id FROM messages WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+            message_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        if current.user_id != user_id && !is_room_moderator(&mut *tx, current.room_id, user_id).await? {
+            return Ok(None);
+        }
+
+        let updated = sqlx::query!(
+            "UPDATE messages SET deleted_at = NOW(), deleted_by = $1 WHERE id = $2 RETURNING deleted_at AS \"deleted_at!\"",
+            user_id,
+            message_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(Some((current.room_id, updated.deleted_at)))
+    }
+
+    async fn set_reaction(
+        &self,
+        message_id: i64,
+        user_id: Uuid,
+        emoji: &str,
+        added: bool,
+    ) -> StorageResult<Option<(Uuid, i64)>> {
+        let room_id = match sqlx::query!(
+            "SELECT room_id FROM messages WHERE id = $1 AND deleted_at IS NULL",
+            message_id
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        {
+            Some(row) => row.room_id,
+            None => return Ok(None),
+        };
+
+        if !can_access_room(&self.pool, room_id, user_id).await? {
+            return Ok(None);
+        }
+
+        let changed = if added {
+            sqlx::query!(
+                "INSERT INTO message_reactions (message_id, user_id, emoji) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+                message_id,
+                user_id,
+                emoji
+            )
+            .execute(&self.pool)
+            .await?
+        } else {
+            sqlx::query!(
+                "DELETE FROM message_reactions WHERE message_id = $1 AND user_id = $2 AND emoji = $3",
+                message_id,
+                user_id,
+                emoji
+            )
+            .execute(&self.pool)
+            .await?
+        };
+
+        if changed.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        let count = sqlx::query!(
+            "SELECT COUNT(*) AS \"count!\" FROM message_reactions WHERE message_id = $1 AND emoji = $2",
+            message_id,
+            emoji
+        )
+        .fetch_one(&self.pool)
+        .await?
+        .count;
+
+        Ok(Some((room_id, count)))
+    }
+
+    async fn set_pinned(&self, message_id: i64, user_id: Uuid, pinned: bool) -> StorageResult<Option<Uuid>> {
+        let mut tx = self.pool.begin().await?;
+
+        let room_id = match sqlx::query!(
+            "SELECT room_id FROM messages WHERE id = $1 AND deleted_at IS NULL",
+            message_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        {
+            Some(row) => row.room_id,
+            None => return Ok(None),
+        };
+
+        if !is_room_moderator(&mut *tx, room_id, user_id).await? {
+            return Ok(None);
+        }
+
+        let changed = if pinned {
+            sqlx::query!(
+                "INSERT INTO pinned_messages (message_id, room_id, pinned_by) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+                message_id,
+                room_id,
+                user_id
+            )
+            .execute(&mut *tx)
+            .await?
+        } else {
+            sqlx::query!("DELETE FROM pinned_messages WHERE message_id = $1", message_id)
+                .execute(&mut *tx)
+                .await?
+        };
+
+        tx.commit().await?;
+        Ok((changed.rows_affected() > 0).then_some(room_id))
+    }
+
+    async fn list_pins(&self, room_id: Uuid) -> StorageResult<Vec<PinRecord>> {
+        let pins = sqlx::query_as!(
+            PinRecord,
+            r#"
+            SELECT m.id AS message_id, m.user_id, u.username, m.content, m.created_at,
+                   p.pinned_by, p.pinned_at
+            FROM pinned_messages p
+            JOIN messages m ON p.message_id = m.id
+            JOIN users u ON m.user_id = u.id
+            WHERE p.room_id = $1 AND m.deleted_at IS NULL
+              AND (m.expires_at IS NULL OR m.expires_at > NOW())
+            ORDER BY p.pinned_at DESC
+            "#,
+            room_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(pins)
+    }
+
+    async fn record_mentions(
+        &self,
+        message_id: i64,
+        room_id: Uuid,
+        author_id: Uuid,
+        usernames: &[String],
+    ) -> StorageResult<Vec<Uuid>> {
+        let rows = sqlx::query!(
+            r#"
+            INSERT INTO message_mentions (message_id, user_id)
+            SELECT $1, u.id
+            FROM users u
+            JOIN rooms r ON r.id = $4
+            WHERE u.username = ANY($2) AND u.id <> $3
+              AND (NOT r.is_direct OR EXISTS (
+                      SELECT 1 FROM direct_participants p
+                      WHERE p.room_id = r.id AND p.user_id = u.id
+                  ))
+            ON CONFLICT DO NOTHING
+            RETURNING user_id
+            "#,
+            message_id,
+            usernames,
+            author_id,
+            room_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(|r| r.user_id).collect())
+    }
+
+    async fn list_mentions(&self, user_id: Uuid) -> StorageResult<Vec<MentionRecord>> {
+        let mentions = sqlx::query_as!(
+            MentionRecord,
+            r#"
+            SELECT m.id AS message_id, m.room_id, r.name AS room_name,
+                   m.user_id AS author_id, u.username AS author_username,
+                   m.content, m.created_at
+            FROM message_mentions mm
+            JOIN messages m ON mm.message_id = m.id
+            JOIN rooms r ON m.room_id = r.id
+            JOIN users u ON m.user_id = u.id
+            WHERE mm.user_id = $1 AND mm.acknowledged_at IS NULL AND m.deleted_at IS NULL
+              AND (m.expires_at IS NULL OR m.expires_at > NOW())
+            ORDER BY m.created_at DESC
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(mentions)
+    }
+
+    async fn acknowledge_mention(&self, message_id: i64, user_id: Uuid) -> StorageResult<bool> {
+        let result = sqlx::query!(
+            "UPDATE message_mentions SET acknowledged_at = NOW() WHERE message_id = $1 AND user_id = $2 AND acknowledged_at IS NULL",
+            message_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn bookmarks(&self, user_id: Uuid, message_id: Option<i64>) -> StorageResult<Vec<BookmarkRecord>> {
+        // Bookmarks of purged messages are removed with them by the foreign key.
+        let bookmarks = sqlx::query_as!(
+            BookmarkRecord,
+            r#"
+            SELECT m.id AS message_id, m.room_id, r.name AS room_name,
+                   m.user_id AS author_id, u.username AS author_username,
+                   CASE WHEN m.deleted_at IS NULL THEN m.content END AS content,
+                   m.deleted_at IS NOT NULL AS "deleted!", m.created_at,
+                   b.note, b.remind_at, b.reminded_at, b.created_at AS bookmarked_at
+            FROM bookmarks b
+            JOIN messages m ON b.message_id = m.id
+            JOIN rooms r ON m.room_id = r.id
+            JOIN users u ON m.user_id = u.id
+            WHERE b.user_id = $1 AND ($2::BIGINT IS NULL OR b.message_id = $2)
+              AND (m.expires_at IS NULL OR m.expires_at > NOW())
+            ORDER BY b.created_at DESC, b.message_id DESC
+            "#,
+            user_id,
+            message_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(bookmarks)
+    }
+
+    async fn save_bookmark(
+        &self,
+        user_id: Uuid,
+        message_id: i64,
+        note: Option<&str>,
+        remind_at: Option<DateTime<Utc>>,
+    ) -> StorageResult<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO bookmarks (user_id, message_id, note, remind_at) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id, message_id) DO UPDATE
+            SET note = $3, remind_at = $4, reminded_at = NULL
+            "#,
+            user_id,
+            message_id,
+            note,
+            remind_at
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_bookmark(&self, user_id: Uuid, message_id: i64) -> StorageResult<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM bookmarks WHERE user_id = $1 AND message_id = $2",
+            user_id,
+            message_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn claim_reminders(&self, user_ids: &[Uuid]) -> StorageResult<Vec<DueReminder>> {
+        // Marking the reminders as sent in the same statement means each is sent
+        // once, even with several servers polling the same database.
+        let due = sqlx::query_as!(
+            DueReminder,
+            r#"
+            UPDATE bookmarks b SET reminded_at = NOW()
+            FROM messages m
+            WHERE b.message_id = m.id AND b.user_id = ANY($1)
+              AND b.remind_at <= NOW() AND b.reminded_at IS NULL
+              AND m.deleted_at IS NULL
+            RETURNING b.user_id, b.message_id, m.room_id, b.note, b.remind_at AS "remind_at!"
+            "#,
+            user_ids
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(due)
+    }
+
+    async fn schedule_message(&self, message: NewMessage<'_>, send_at: DateTime<Utc>) -> StorageResult<Option<i64>> {
+        if !message.attachment_ids.is_empty() {
+            let usable = sqlx::query_scalar!(
+                r#"
+                SELECT COUNT(*) AS "count!" FROM attachments
+                WHERE id = ANY($1) AND uploader_id = $2 AND room_id = $3 AND message_id IS NULL
+                "#,
+                message.attachment_ids,
+                message.user_id,
+                message.room_id
+            )
+            .fetch_one(&self.pool)
+            .await?;
+
+            let mut requested = message.attachment_ids.to_vec();
+            requested.sort();
+            requested.dedup();
+            if usable != requested.len() as i64 {
+                return Ok(None);
+            }
+        }
+
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO scheduled_messages (room_id, user_id, content, parent_id, attachment_ids, send_at, ttl_seconds, quote_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING id
+            "#,
+            message.room_id,
+            message.user_id,
+            message.content,
+            message.parent_id,
+            message.attachment_ids,
+            send_at,
+            message.ttl_seconds,
+            message.quote.map(|q| q.message_id)
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(Some(row.id))
+    }
+
+    async fn list_scheduled(&self, user_id: Uuid) -> StorageResult<Vec<ScheduledRecord>> {
+        let scheduled = sqlx::query_as!(
+            ScheduledRecord,
+            r#"
+            SELECT id, room_id, content, parent_id AS reply_to, attachment_ids, send_at, ttl_seconds, quote_id, created_at
+            FROM scheduled_messages
+            WHERE user_id = $1
+            ORDER BY send_at, id
+            "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(scheduled)
+    }
+
+    async fn cancel_scheduled(&self, id: i64, user_id: Uuid) -> StorageResult<bool> {
+        // A claimed message is being delivered; cancelling it now would not
+        // stop the delivery.
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM scheduled_messages
+            WHERE id = $1 AND user_id = $2
+              AND (claimed_at IS NULL OR claimed_at <= NOW() - INTERVAL '5 minutes')
+            "#,
+            id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn claim_scheduled(&self, limit: i64) -> StorageResult<Vec<DueMessage>> {
+        // `SKIP LOCKED` keeps servers polling the same database from claiming
+        // the same rows.
+        let mut due = sqlx::query_as!(
+            DueMessage,
+            r#"
+            UPDATE scheduled_messages SET claimed_at = NOW()
+            WHERE id IN (
+                SELECT id FROM scheduled_messages
+                WHERE send_at <= NOW()
+                  AND (claimed_at IS NULL OR claimed_at <= NOW() - INTERVAL '5 minutes')
+                ORDER BY send_at, id
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, room_id, user_id, content, parent_id, attachment_ids, send_at, ttl_seconds, quote_id
+            "#,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        due.sort_by_key(|m| (m.send_at, m.id));
+        Ok(due)
+    }
+
+    async fn finish_scheduled(&self, id: i64) -> StorageResult<()> {
+        sqlx::query!("DELETE FROM scheduled_messages WHERE id = $1", id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn insert_attachment(&self, attachment: NewAttachment<'_>) -> StorageResult<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO attachments (id, uploader_id, room_id, file_name, content_type, size_bytes)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            attachment.id,
+            attachment.uploader_id,
+            attachment.room_id,
+            attachment.file_name,
+            attachment.content_type,
+            attachment.size_bytes
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn attachment(&self, id: Uuid) -> StorageResult<Option<AttachmentAccess>> {
+        let attachment = sqlx::query_as!(
+            AttachmentAccess,
+            r#"
+            SELECT a.uploader_id, a.room_id, a.file_name, a.content_type,
+                   (m.id IS NOT NULL AND m.deleted_at IS NULL
+                    AND (m.expires_at IS NULL OR m.expires_at > NOW())) AS "visible!"
+            FROM attachments a
+            LEFT JOIN messages m ON a.message_id = m.id
+            WHERE a.id = $1
+            "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(attachment)
+    }
+
+    async fn search(&self, query: SearchQuery<'_>) -> StorageResult<Vec<SearchResult>> {
+        let results = sqlx::query_as!(
+            SearchResult,
+            r#"
+            SELECT m.id AS message_id, m.room_id, r.name AS room_name,
+                   m.user_id, u.username,
+                   -- Content is HTML-escaped first, so the <mark> tags are the only markup.
+                   ts_headline('english',
+                               replace(replace(replace(replace(m.content,
+                                   '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), '"', '&quot;'),
+                               query,
+                               'StartSel=<mark>, StopSel=</mark>, MaxFragments=2, MaxWords=20, MinWords=5') AS "snippet!",
+                   m.created_at,
+                   ts_rank(m.content_tsv, query) AS "rank!"
+            FROM messages m
+            CROSS JOIN websearch_to_tsquery('english', $1) AS query
+            JOIN rooms r ON m.room_id = r.id
+            JOIN users u ON m.user_id = u.id
+            WHERE m.content_tsv @@ query
+              AND m.deleted_at IS NULL
+              AND (m.expires_at IS NULL OR m.expires_at > NOW())
+              AND (NOT r.is_direct OR EXISTS (
+                      SELECT 1 FROM direct_participants p
+                      WHERE p.room_id = r.id AND p.user_id = $2
+                  ))
+              AND ($3::UUID IS NULL OR m.room_id = $3)
+              AND ($4::UUID IS NULL OR m.user_id = $4)
+              AND ($5::TIMESTAMPTZ IS NULL OR m.created_at >= $5)
+              AND ($6::TIMESTAMPTZ IS NULL OR m.created_at < $6)
+            ORDER BY "rank!" DESC, m.id DESC
+            LIMIT $7 OFFSET $8
+            "#,
+            query.text,
+            query.viewer,
+            query.room_id,
+            query.author_id,
+            query.from,
+            query.to,
+            query.limit,
+            query.offset
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(results)
+    }
+
+    async fn export_page(&self, room_id: Uuid, after: i64, limit: i64) -> StorageResult<Vec<ExportRow>> {
+        let rows = sqlx::query_as!(
+            ExportRow,
+            r#"
+            SELECT m.id, m.parent_id, m.user_id, u.username,
+                   CASE WHEN m.deleted_at IS NULL THEN m.content END AS content,
+                   m.created_at, m.edited_at, m.deleted_at
+            FROM messages m
+            JOIN users u ON m.user_id = u.id
+            WHERE m.room_id = $1 AND m.id > $2 AND (m.expires_at IS NULL OR m.expires_at > NOW())
+            ORDER BY m.id
+            LIMIT $3
+            "#,
+            room_id,
+            after,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    async fn expire_messages(&self, limit: i64) -> StorageResult<PurgedMessages> {
+        let mut tx = self.pool.begin().await?;
+
+        let expired: Vec<i64> = sqlx::query!(
+            r#"
+            SELECT id
+            FROM messages
+            WHERE expires_at <= NOW()
+            ORDER BY expires_at, id
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+            "#,
+            limit
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|row| row.id)
+        .collect();
+
+        if expired.is_empty() {
+            return Ok(PurgedMessages::default());
+        }
+
+        // Replies go with their thread, whether or not they expired themselves.
+        let attachment_ids: Vec<Uuid> = sqlx::query!(
+            r#"
+            DELETE FROM attachments
+            WHERE message_id IN (SELECT id FROM messages WHERE id = ANY($1) OR parent_id = ANY($1))
+            RETURNING id
+            "#,
+            &expired
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|row| row.id)
+        .collect();
+
+        let messages: Vec<(i64, Uuid)> = sqlx::query!(
+            "DELETE FROM messages WHERE id = ANY($1) OR parent_id = ANY($1) RETURNING id, room_id",
+            &expired
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|row| (row.id, row.room_id))
+        .collect();
+
+        tx.commit().await?;
+        Ok(PurgedMessages { messages, attachment_ids })
+    }
+
+    async fn purge_retained(&self, limit: i64) -> StorageResult<PurgedMessages> {
+        let mut tx = self.pool.begin().await?;
+
+        let expired: Vec<i64> = sqlx::query!(
+            r#"
+            SELECT m.id
+            FROM messages m
+            JOIN rooms r ON m.room_id = r.id
+            WHERE r.retention_days IS NOT NULL
+              AND m.created_at < NOW() - make_interval(days => r.retention_days)
+            ORDER BY m.id
+            LIMIT $1
+            FOR UPDATE OF m SKIP LOCKED
+            "#,
+            limit
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|row| row.id)
+        .collect();
+
+        if expired.is_empty() {
+            return Ok(PurgedMessages::default());
+        }
+
+        // Attachment rows would otherwise outlive their message with `message_id` NULL.
+        let attachment_ids: Vec<Uuid> = sqlx::query!(
+            r#"
+            DELETE FROM attachments
+            WHERE message_id IN (SELECT id FROM messages WHERE id = ANY($1) OR parent_id = ANY($1))
+            RETURNING id
+            "#,
+            &expired
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|row| row.id)
+        .collect();
+
+        // Replies to a purged message are removed with it by the `parent_id` cascade.
+        let messages: Vec<(i64, Uuid)> = sqlx::query!(
+            "DELETE FROM messages WHERE id = ANY($1) RETURNING id, room_id",
+            &expired
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|row| (row.id, row.room_id))
+        .collect();
+
+        tx.commit().await?;
+        Ok(PurgedMessages { messages, attachment_ids })
+    }
+}
@@ -0,0 +1,1680 @@
+// src/storage/sqlite.rs
+
+// Single-file backend for small deployments and development, built with the
+// `sqlite` feature. Its schema lives in `migrations_sqlite/` and is applied
+// when the store is opened. Queries are checked at runtime rather than by
+// `sqlx::query!`, so building does not need a SQLite database.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use sqlx::types::Json as SqlJson;
+use sqlx::{QueryBuilder, Sqlite};
+use uuid::Uuid;
+
+use super::text_search::TextQuery;
+use super::{
+    direct_room_name, AttachmentAccess, DueMessage, DueReminder, HistoryQuery, InsertedMessage, MessageStore,
+    NewAttachment, NewMessage, PurgedMessages, QuoteSource, RoomStore, SearchQuery, StorageResult,
+    UserCredentials, UserStore,
+};
+use crate::models::{
+    AttachmentSummary, BookmarkRecord, DirectConversation, ExportRow, MentionRecord, MessageRecord, PinRecord,
+    QuotedMessage, ReactionSummary, RoomListing, RoomRecord, ScheduledRecord, SearchResult, UserRecord,
+};
+
+pub struct SqliteStore {
+    pool: SqlitePool,
+}
+
+impl SqliteStore {
+    /// Opens the database at `path`, creating it if needed, and brings its
+    /// schema up to date.
+    pub async fn open(path: &str) -> StorageResult<Self> {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            .foreign_keys(true);
+        let pool = SqlitePoolOptions::new().max_connections(5).connect_with(options).await?;
+        sqlx::migrate!("./migrations_sqlite").run(&pool).await?;
+        Ok(SqliteStore { pool })
+    }
+
+    /// Adds reactions, from `viewer`'s point of view, and attachments to `rows`.
+    async fn message_records(&self, rows: Vec<MessageRow>, viewer: Uuid) -> StorageResult<Vec<MessageRecord>> {
+        // Reactions and attachments of deleted messages are hidden.
+        let live_ids: Vec<i64> = rows.iter().filter(|m| m.deleted_at.is_none()).map(|m| m.id).collect();
+        let mut reactions: HashMap<i64, Vec<ReactionSummary>> = HashMap::new();
+        let mut attachments: HashMap<i64, Vec<AttachmentSummary>> = HashMap::new();
+        if !live_ids.is_empty() {
+            let mut reaction_query = QueryBuilder::<Sqlite>::new(
+                "SELECT message_id, emoji, COUNT(*), MAX(user_id = ",
+            );
+            reaction_query.push_bind(viewer);
+            reaction_query.push(") FROM message_reactions WHERE message_id IN (");
+            let mut list = reaction_query.separated(", ");
+            for id in &live_ids {
+                list.push_bind(*id);
+            }
+            reaction_query.push(") GROUP BY message_id, emoji ORDER BY MIN(created_at)");
+            let reaction_rows: Vec<(i64, String, i64, bool)> =
+                reaction_query.build_query_as().fetch_all(&self.pool).await?;
+            for (message_id, emoji, count, reacted) in reaction_rows {
+                reactions.entry(message_id).or_default().push(ReactionSummary { emoji, count, reacted });
+            }
+
+            let mut attachment_query = QueryBuilder::<Sqlite>::new(
+                "SELECT message_id, id, file_name, content_type, size_bytes FROM attachments WHERE message_id IN (",
+            );
+            let mut list = attachment_query.separated(", ");
+            for id in &live_ids {
+                list.push_bind(*id);
+            }
+            attachment_query.push(") ORDER BY created_at");
+            let attachment_rows: Vec<(i64, Uuid, String, String, i64)> =
+                attachment_query.build_query_as().fetch_all(&self.pool).await?;
+            for (message_id, id, file_name, content_type, size_bytes) in attachment_rows {
+                attachments
+                    .entry(message_id)
+                    .or_default()
+                    .push(AttachmentSummary { id, file_name, content_type, size_bytes });
+            }
+        }
+
+        Ok(rows
+            .into_iter()
+            .map(|m| {
+                let deleted = m.deleted_at.is_some();
+                MessageRecord {
+                    id: m.id,
+                    user_id: m.user_id,
+                    username: m.username,
+                    room_id: m.room_id.to_string(),
+                    content: (!deleted).then_some(m.content),
+                    created_at: m.created_at,
+                    edited: m.edited_at.is_some(),
+                    edited_at: m.edited_at,
+                    deleted,
+                    deleted_at: m.deleted_at,
+                    deleted_by: m.deleted_by,
+                    expires_at: m.expires_at,
+                    parent_id: m.parent_id,
+                    reply_count: m.reply_count,
+                    last_reply_at: m.last_reply_at,
+                    reactions: SqlJson(reactions.remove(&m.id).unwrap_or_default()),
+                    attachments: SqlJson(attachments.remove(&m.id).unwrap_or_default()),
+                    quote: m.quote.filter(|_| !deleted),
+                }
+            })
+            .collect())
+    }
+}
+
+/// `at` in the format of the schema's timestamp defaults, so it compares
+/// correctly with stored timestamps as text.
+fn timestamp_text(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+}
+
+fn now_text() -> String {
+    timestamp_text(Utc::now())
+}
+
+/// The columns of `MessageRow`, for `history` and `thread`. `?1` is the
+/// current time, from `now_text`; replies that expired are not counted.
+const MESSAGE_SELECT: &str = r#"
+    SELECT m.id, m.user_id, u.username, m.room_id, m.content, m.created_at,
+           m.edited_at, m.deleted_at, m.deleted_by, m.expires_at, m.parent_id, m.quote,
+           (SELECT COUNT(*) FROM messages r
+            WHERE r.parent_id = m.id AND r.deleted_at IS NULL
+              AND (r.expires_at IS NULL OR r.expires_at > ?1)) AS reply_count,
+           (SELECT MAX(r.created_at) FROM messages r
+            WHERE r.parent_id = m.id AND r.deleted_at IS NULL
+              AND (r.expires_at IS NULL OR r.expires_at > ?1)) AS last_reply_at
+    FROM messages m
+    JOIN users u ON m.user_id = u.id
+"#;
+
+/// A history row before its reactions and attachments are attached.
+#[derive(sqlx::FromRow)]
+struct MessageRow {
+    id: i64,
+    user_id: Uuid,
+    username: String,
+    room_id: Uuid,
+    content: String,
+    created_at: DateTime<Utc>,
+    edited_at: Option<DateTime<Utc>>,
+    deleted_at: Option<DateTime<Utc>>,
+    deleted_by: Option<Uuid>,
+    expires_at: Option<DateTime<Utc>>,
+    parent_id: Option<i64>,
+    reply_count: i64,
+    last_reply_at: Option<DateTime<Utc>>,
+    quote: Option<SqlJson<QuotedMessage>>,
+}
+
+/// A `ScheduledRecord`, with `attachment_ids` stored as JSON.
+#[derive(sqlx::FromRow)]
+struct ScheduledRow {
+    id: i64,
+    room_id: Uuid,
+    content: String,
+    reply_to: Option<i64>,
+    attachment_ids: SqlJson<Vec<Uuid>>,
+    send_at: DateTime<Utc>,
+    ttl_seconds: Option<i32>,
+    quote_id: Option<i64>,
+    created_at: DateTime<Utc>,
+}
+
+/// A `DueMessage`, with `attachment_ids` stored as JSON.
+#[derive(sqlx::FromRow)]
+struct DueRow {
+    id: i64,
+    room_id: Uuid,
+    user_id: Uuid,
+    content: String,
+    parent_id: Option<i64>,
+    attachment_ids: SqlJson<Vec<Uuid>>,
+    send_at: DateTime<Utc>,
+    ttl_seconds: Option<i32>,
+    quote_id: Option<i64>,
+}
+
+/// A message that may be quoted, with what it quotes itself.
+#[derive(sqlx::FromRow)]
+struct QuoteRow {
+    id: i64,
+    room_id: Uuid,
+    room_name: String,
+    user_id: Uuid,
+    username: String,
+    content: String,
+    created_at: DateTime<Utc>,
+    quote: Option<SqlJson<QuotedMessage>>,
+}
+
+#[rocket::async_trait]
+impl UserStore for SqliteStore {
+    async fn create_user(&self, username: &str, password_hash: &str) -> StorageResult<Option<Uuid>> {
+        let id = sqlx::query_scalar(
+            "INSERT INTO users (id, username, password_hash) VALUES (?1, ?2, ?3) ON CONFLICT (username) DO NOTHING RETURNING id",
+        )
+        .bind(Uuid::new_v4())
+        .bind(username)
+        .bind(password_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(id)
+    }
+
+    async fn find_credentials(&self, username: &str) -> StorageResult<Option<UserCredentials>> {
+        let row = sqlx::query_as("SELECT id, password_hash FROM users WHERE username = ?1")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row)
+    }
+
+    async fn get_users(&self, ids: &[Uuid]) -> StorageResult<Vec<UserRecord>> {
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT id, username FROM users WHERE id IN (");
+        let mut list = query.separated(", ");
+        for id in ids {
+            list.push_bind(*id);
+        }
+        query.push(") ORDER BY username");
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+}
+
+#[rocket::async_trait]
+impl RoomStore for SqliteStore {
+    async fn create_room(
+        &self,
+        name: &str,
+        retention_days: Option<i32>,
+        message_ttl_seconds: Option<i32>,
+        creator: Uuid,
+    ) -> StorageResult<Option<RoomRecord>> {
+        let mut tx = self.pool.begin().await?;
+
+        let Some(room) = sqlx::query_as::<_, RoomRecord>(
+            r#"
+            INSERT INTO rooms (id, name, retention_days, message_ttl_seconds) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (name) DO NOTHING
+            RETURNING id, name, retention_days, message_ttl_seconds
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(name)
+        .bind(retention_days)
+        .bind(message_ttl_seconds)
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        // The creator of a room is its first moderator.
+        sqlx::query("INSERT INTO room_moderators (room_id, user_id) VALUES (?1, ?2)")
+            .bind(room.id)
+            .bind(creator)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(Some(room))
+    }
+
+    async fn list_rooms(&self, user_id: Uuid) -> StorageResult<Vec<RoomListing>> {
+        let rooms = sqlx::query_as(
+            r#"
+            SELECT r.id, r.name, r.retention_days, r.message_ttl_seconds,
+                   rr.last_read_message_id,
+                   (SELECT COUNT(*) FROM messages m
+                    WHERE m.room_id = r.id AND m.deleted_at IS NULL AND m.user_id <> ?1
+                      AND (m.expires_at IS NULL OR m.expires_at > ?2)
+                      AND m.id > COALESCE(rr.last_read_message_id, 0)) AS unread_count,
+                   (SELECT COUNT(*) FROM message_mentions mm
+                    JOIN messages m ON mm.message_id = m.id
+                    WHERE mm.user_id = ?1 AND mm.acknowledged_at IS NULL
+                      AND m.room_id = r.id AND m.deleted_at IS NULL
+                      AND (m.expires_at IS NULL OR m.expires_at > ?2)) AS mention_count,
+                   d.content AS draft, d.updated_at AS draft_updated_at
+            FROM rooms r
+            LEFT JOIN room_reads rr ON rr.room_id = r.id AND rr.user_id = ?1
+            LEFT JOIN drafts d ON d.room_id = r.id AND d.user_id = ?1
+            WHERE NOT r.is_direct
+            ORDER BY r.name
+            "#,
+        )
+        .bind(user_id)
+        .bind(now_text())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rooms)
+    }
+
+    async fn can_access(&self, room_id: Uuid, user_id: Uuid) -> StorageResult<bool> {
+        let allowed: Option<bool> = sqlx::query_scalar(
+            r#"
+            SELECT (NOT r.is_direct OR EXISTS (
+                       SELECT 1 FROM direct_participants p
+                       WHERE p.room_id = r.id AND p.user_id = ?2
+                   ))
+            FROM rooms r
+            WHERE r.id = ?1
+            "#,
+        )
+        .bind(room_id)
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(allowed.unwrap_or(false))
+    }
+
+    async fn add_member(&self, room_id: Uuid, user_id: Uuid) -> StorageResult<()> {
+        sqlx::query("INSERT INTO room_memberships (room_id, user_id) VALUES (?1, ?2) ON CONFLICT DO NOTHING")
+            .bind(room_id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_member(&self, room_id: Uuid, user_id: Uuid) -> StorageResult<bool> {
+        // Direct conversations always include both participants.
+        let done = sqlx::query(
+            r#"
+            DELETE FROM room_memberships
+            WHERE room_id = ?1 AND user_id = ?2
+              AND room_id IN (SELECT id FROM rooms WHERE NOT is_direct)
+            "#,
+        )
+        .bind(room_id)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(done.rows_affected() > 0)
+    }
+
+    async fn memberships(&self) -> StorageResult<Vec<(Uuid, Uuid)>> {
+        let rows = sqlx::query_as("SELECT room_id, user_id FROM room_memberships")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows)
+    }
+
+    async fn save_draft(
+        &self,
+        room_id: Uuid,
+        user_id: Uuid,
+        content: &str,
+        updated_at: DateTime<Utc>,
+    ) -> StorageResult<()> {
+        if content.is_empty() {
+            sqlx::query("DELETE FROM drafts WHERE room_id = ?1 AND user_id = ?2")
+                .bind(room_id)
+                .bind(user_id)
+                .execute(&self.pool)
+                .await?;
+            return Ok(());
+        }
+        sqlx::query(
+            r#"
+            INSERT INTO drafts (user_id, room_id, content, updated_at) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (user_id, room_id) DO UPDATE SET content = ?3, updated_at = ?4
+            "#,
+        )
+        .bind(user_id)
+        .bind(room_id)
+        .bind(content)
+        .bind(updated_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_room(&self, room_id: Uuid) -> StorageResult<Option<RoomRecord>> {
+        let room = sqlx::query_as("SELECT id, name, retention_days, message_ttl_seconds FROM rooms WHERE id = ?1")
+            .bind(room_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(room)
+    }
+
+    async fn is_moderator(&self, room_id: Uuid, user_id: Uuid) -> StorageResult<bool> {
+        let moderator = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM room_moderators WHERE room_id = ?1 AND user_id = ?2)",
+        )
+        .bind(room_id)
+        .bind(user_id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(moderator)
+    }
+
+    async fn add_moderator(&self, room_id: Uuid, user_id: Uuid) -> StorageResult<()> {
+        sqlx::query("INSERT INTO room_moderators (room_id, user_id) VALUES (?1, ?2) ON CONFLICT DO NOTHING")
+            .bind(room_id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_retention(&self, room_id: Uuid, retention_days: Option<i32>) -> StorageResult<Option<RoomRecord>> {
+        let room = sqlx::query_as(
+            "UPDATE rooms SET retention_days = ?1 WHERE id = ?2 RETURNING id, name, retention_days, message_ttl_seconds",
+        )
+        .bind(retention_days)
+        .bind(room_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(room)
+    }
+
+    async fn set_message_ttl(
+        &self,
+        room_id: Uuid,
+        message_ttl_seconds: Option<i32>,
+    ) -> StorageResult<Option<RoomRecord>> {
+        let room = sqlx::query_as(
+            "UPDATE rooms SET message_ttl_seconds = ?1 WHERE id = ?2 RETURNING id, name, retention_days, message_ttl_seconds",
+        )
+        .bind(message_ttl_seconds)
+        .bind(room_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(room)
+    }
+
+    async fn mark_read(&self, room_id: Uuid, user_id: Uuid, message_id: i64) -> StorageResult<bool> {
+        if !self.can_access(room_id, user_id).await? {
+            return Ok(false);
+        }
+
+        let mut tx = self.pool.begin().await?;
+        let now = now_text();
+
+        let advanced = sqlx::query(
+            r#"
+            INSERT INTO room_reads (user_id, room_id, last_read_message_id, updated_at)
+            SELECT ?1, room_id, id, ?4 FROM messages WHERE id = ?3 AND room_id = ?2
+            ON CONFLICT (user_id, room_id) DO UPDATE
+                SET last_read_message_id = excluded.last_read_message_id, updated_at = excluded.updated_at
+                WHERE room_reads.last_read_message_id < excluded.last_read_message_id
+            "#,
+        )
+        .bind(user_id)
+        .bind(room_id)
+        .bind(message_id)
+        .bind(&now)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            > 0;
+
+        if advanced {
+            sqlx::query(
+                r#"
+                UPDATE message_mentions SET acknowledged_at = ?4
+                WHERE user_id = ?1 AND acknowledged_at IS NULL
+                  AND message_id IN (SELECT id FROM messages WHERE room_id = ?2 AND id <= ?3)
+                "#,
+            )
+            .bind(user_id)
+            .bind(room_id)
+            .bind(message_id)
+            .bind(&now)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(advanced)
+    }
+
+    async fn open_direct(&self, user_id: Uuid, other_id: Uuid) -> StorageResult<Uuid> {
+        let name = direct_room_name(user_id, other_id);
+        let mut tx = self.pool.begin().await?;
+
+        let created: Option<Uuid> = sqlx::query_scalar(
+            "INSERT INTO rooms (id, name, is_direct) VALUES (?1, ?2, TRUE) ON CONFLICT (name) DO NOTHING RETURNING id",
+        )
+        .bind(Uuid::new_v4())
+        .bind(&name)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let room_id = match created {
+            Some(room_id) => {
+                sqlx::query("INSERT INTO direct_participants (room_id, user_id) VALUES (?1, ?2), (?1, ?3)")
+                    .bind(room_id)
+                    .bind(user_id)
+                    .bind(other_id)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query("INSERT INTO room_memberships (room_id, user_id) VALUES (?1, ?2), (?1, ?3)")
+                    .bind(room_id)
+                    .bind(user_id)
+                    .bind(other_id)
+                    .execute(&mut *tx)
+                    .await?;
+                room_id
+            }
+            // The conversation already exists; a conflict means someone created it first.
+            None => {
+                sqlx::query_scalar("SELECT id FROM rooms WHERE name = ?1 AND is_direct")
+                    .bind(&name)
+                    .fetch_one(&mut *tx)
+                    .await?
+            }
+        };
+
+        tx.commit().await?;
+        Ok(room_id)
+    }
+
+    async fn direct_conversations(
+        &self,
+        user_id: Uuid,
+        room_id: Option<Uuid>,
+    ) -> StorageResult<Vec<DirectConversation>> {
+        // Counts follow `list_rooms`.
+        let conversations = sqlx::query_as(
+            r#"
+            SELECT me.room_id, other.user_id, u.username,
+                   rr.last_read_message_id,
+                   (SELECT COUNT(*) FROM messages m
+                    WHERE m.room_id = me.room_id AND m.deleted_at IS NULL AND m.user_id <> ?1
+                      AND (m.expires_at IS NULL OR m.expires_at > ?3)
+                      AND m.id > COALESCE(rr.last_read_message_id, 0)) AS unread_count,
+                   (SELECT COUNT(*) FROM message_mentions mm
+                    JOIN messages m ON mm.message_id = m.id
+                    WHERE mm.user_id = ?1 AND mm.acknowledged_at IS NULL
+                      AND m.room_id = me.room_id AND m.deleted_at IS NULL
+                      AND (m.expires_at IS NULL OR m.expires_at > ?3)) AS mention_count,
+                   d.content AS draft, d.updated_at AS draft_updated_at
+            FROM direct_participants me
+            JOIN direct_participants other ON other.room_id = me.room_id AND other.user_id <> me.user_id
+            JOIN users u ON other.user_id = u.id
+            LEFT JOIN room_reads rr ON rr.room_id = me.room_id AND rr.user_id = ?1
+            LEFT JOIN drafts d ON d.room_id = me.room_id AND d.user_id = ?1
+            WHERE me.user_id = ?1 AND (?2 IS NULL OR me.room_id = ?2)
+            ORDER BY u.username
+            "#,
+        )
+        .bind(user_id)
+        .bind(room_id)
+        .bind(now_text())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(conversations)
+    }
+}
+
+#[rocket::async_trait]
+impl MessageStore for SqliteStore {
+    async fn insert_message(&self, message: NewMessage<'_>) -> StorageResult<Option<InsertedMessage>> {
+        let NewMessage { room_id, user_id, content, parent_id, attachment_ids, ttl_seconds, quote } = message;
+        let mut tx = self.pool.begin().await?;
+
+        let (id, created_at, expires_at): (i64, DateTime<Utc>, Option<DateTime<Utc>>) = sqlx::query_as(
+            r#"
+            INSERT INTO messages (room_id, user_id, content, parent_id, created_at, expires_at, quote)
+            SELECT ?1, ?2, ?3, ?4, strftime('%Y-%m-%dT%H:%M:%fZ', 'now'),
+                   CASE WHEN COALESCE(?5, r.message_ttl_seconds) IS NOT NULL THEN
+                       strftime('%Y-%m-%dT%H:%M:%fZ', 'now',
+                                printf('+%d seconds', COALESCE(?5, r.message_ttl_seconds)))
+                   END,
+                   ?6
+            FROM rooms r
+            WHERE r.id = ?1
+            RETURNING id, created_at, expires_at
+            "#,
+        )
+        .bind(room_id)
+        .bind(user_id)
+        .bind(content)
+        .bind(parent_id)
+        .bind(ttl_seconds)
+        .bind(quote.map(SqlJson))
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let mut attachments = Vec::new();
+        if !attachment_ids.is_empty() {
+            let mut query = QueryBuilder::<Sqlite>::new("UPDATE attachments SET message_id = ");
+            query.push_bind(id);
+            query.push(" WHERE uploader_id = ");
+            query.push_bind(user_id);
+            query.push(" AND room_id = ");
+            query.push_bind(room_id);
+            query.push(" AND message_id IS NULL AND id IN (");
+            let mut list = query.separated(", ");
+            for attachment_id in attachment_ids {
+                list.push_bind(*attachment_id);
+            }
+            query.push(") RETURNING id, file_name, content_type, size_bytes");
+            attachments = query.build_query_as::<AttachmentSummary>().fetch_all(&mut *tx).await?;
+
+            // Dropping the transaction rolls the message back.
+            let mut requested = attachment_ids.to_vec();
+            requested.sort();
+            requested.dedup();
+            if attachments.len() != requested.len() {
+                return Ok(None);
+            }
+        }
+
+        tx.commit().await?;
+        Ok(Some(InsertedMessage { id, created_at, expires_at, attachments }))
+    }
+
+    async fn quote_source(&self, message_id: i64) -> StorageResult<Option<QuoteSource>> {
+        let row: Option<QuoteRow> = sqlx::query_as(
+            r#"
+            SELECT m.id, m.room_id, r.name AS room_name, m.user_id, u.username, m.content, m.created_at, m.quote
+            FROM messages m
+            JOIN rooms r ON m.room_id = r.id
+            JOIN users u ON m.user_id = u.id
+            WHERE m.id = ?1 AND m.deleted_at IS NULL AND m.expires_at IS NULL
+            "#,
+        )
+        .bind(message_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| QuoteSource {
+            message: QuotedMessage {
+                message_id: r.id,
+                room_id: r.room_id,
+                room_name: r.room_name,
+                user_id: r.user_id,
+                username: r.username,
+                content: r.content,
+                created_at: r.created_at,
+                forwarded: false,
+            },
+            quote: r.quote.map(|q| q.0),
+        }))
+    }
+
+    async fn thread_root(&self, room_id: Uuid, message_id: i64) -> StorageResult<Option<i64>> {
+        let root = sqlx::query_scalar("SELECT COALESCE(parent_id, id) FROM messages WHERE id = ?1 AND room_id = ?2")
+            .bind(message_id)
+            .bind(room_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(root)
+    }
+
+    async fn history(&self, query: HistoryQuery) -> StorageResult<Vec<MessageRecord>> {
+        let rows: Vec<MessageRow> = sqlx::query_as(&format!(
+            r#"
+            {}
+            WHERE m.room_id = ?2 AND m.parent_id IS NULL
+              AND (m.expires_at IS NULL OR m.expires_at > ?1)
+              AND (?3 IS NULL OR m.id < ?3)
+              AND (?4 IS NULL OR m.id > ?4)
+            ORDER BY CASE WHEN ?4 IS NOT NULL THEN m.id END ASC, m.id DESC
+            LIMIT ?5
+            "#,
+            MESSAGE_SELECT
+        ))
+        .bind(now_text())
+        .bind(query.room_id)
+        .bind(query.before)
+        .bind(query.after)
+        .bind(query.limit)
+        .fetch_all(&self.pool)
+        .await?;
+        self.message_records(rows, query.viewer).await
+    }
+
+    async fn thread(&self, room_id: Uuid, root_id: i64, viewer: Uuid) -> StorageResult<Vec<MessageRecord>> {
+        let rows: Vec<MessageRow> = sqlx::query_as(&format!(
+            r#"
+            {}
+            WHERE m.room_id = ?2 AND (m.id = ?3 OR m.parent_id = ?3)
+              AND (m.expires_at IS NULL OR m.expires_at > ?1)
+            ORDER BY m.parent_id NULLS FIRST, m.created_at ASC, m.id ASC
+            "#,
+            MESSAGE_SELECT
+        ))
+        .bind(now_text())
+        .bind(room_id)
+        .bind(root_id)
+        .fetch_all(&self.pool)
+        .await?;
+        self.message_records(rows, viewer).await
+    }
+
+    async fn live_message_room(&self, message_id: i64) -> StorageResult<Option<Uuid>> {
+        let room_id = sqlx::query_scalar(
+            r#"
+            SELECT room_id FROM messages
+            WHERE id = ?1 AND deleted_at IS NULL
+              AND (expires_at IS NULL OR expires_at > ?2)
+            "#,
+        )
+        .bind(message_id)
+        .bind(now_text())
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(room_id)
+    }
+
+    async fn edit_message(
+        &self,
+        message_id: i64,
+        user_id: Uuid,
+        content: &str,
+    ) -> StorageResult<Option<(Uuid, DateTime<Utc>)>> {
+        // Take the write lock up front so two concurrent edits cannot both record the same prior text.
+        let mut tx = self.pool.begin_with("BEGIN IMMEDIATE").await?;
+
+        let current: Option<(Uuid, Uuid, String)> =
+            sqlx::query_as("SELECT room_id, user_id, content FROM messages WHERE id = ?1 AND deleted_at IS NULL")
+                .bind(message_id)
+                .fetch_optional(&mut *tx)
+                .await?;
+
+        let (room_id, previous) = match current {
+            Some((room_id, author_id, content)) if author_id == user_id => (room_id, content),
+            _ => return Ok(None),
+        };
+
+        sqlx::query("INSERT INTO message_revisions (message_id, content) VALUES (?1, ?2)")
+            .bind(message_id)
+            .bind(previous)
+            .execute(&mut *tx)
+            .await?;
+
+        let edited_at = sqlx::query_scalar("UPDATE messages SET content = ?1, edited_at = ?2 WHERE id = ?3 RETURNING edited_at")
+            .bind(content)
+            .bind(now_text())
+            .bind(message_id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(Some((room_id, edited_at)))
+    }
+
+    async fn delete_message(&self, message_id: i64, user_id: Uuid) -> StorageResult<Option<(Uuid, DateTime<Utc>)>> {
+        // Authors may delete their own messages, moderators anyone's.
+        let deleted = sqlx::query_as(
+            r#"
+            UPDATE messages SET deleted_at = ?3, deleted_by = ?2
+            WHERE id = ?1 AND deleted_at IS NULL
+              AND (user_id = ?2 OR EXISTS (
+                      SELECT 1 FROM room_moderators rm
+                      WHERE rm.room_id = messages.room_id AND rm.user_id = ?2
+                  ))
+            RETURNING room_id, deleted_at
+            "#,
+        )
+        .bind(message_id)
+        .bind(user_id)
+        .bind(now_text())
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(deleted)
+    }
+
+    async fn set_reaction(
+        &self,
+        message_id: i64,
+        user_id: Uuid,
+        emoji: &str,
+        added: bool,
+    ) -> StorageResult<Option<(Uuid, i64)>> {
+        let room_id: Option<Uuid> =
+            sqlx::query_scalar("SELECT room_id FROM messages WHERE id = ?1 AND deleted_at IS NULL")
+                .bind(message_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        let Some(room_id) = room_id else {
+            return Ok(None);
+        };
+
+        if !self.can_access(room_id, user_id).await? {
+            return Ok(None);
+        }
+
+        let statement = if added {
+            "INSERT INTO message_reactions (message_id, user_id, emoji) VALUES (?1, ?2, ?3) ON CONFLICT DO NOTHING"
+        } else {
+            "DELETE FROM message_reactions WHERE message_id = ?1 AND user_id = ?2 AND emoji = ?3"
+        };
+        let changed = sqlx::query(statement)
+            .bind(message_id)
+            .bind(user_id)
+            .bind(emoji)
+            .execute(&self.pool)
+            .await?;
+
+        if changed.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        let count = sqlx::query_scalar("SELECT COUNT(*) FROM message_reactions WHERE message_id = ?1 AND emoji = ?2")
+            .bind(message_id)
+            .bind(emoji)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(Some((room_id, count)))
+    }
+
+    async fn set_pinned(&self, message_id: i64, user_id: Uuid, pinned: bool) -> StorageResult<Option<Uuid>> {
+        // Only moderators of the message's room may pin, and only live messages.
+        let statement = if pinned {
+            r#"
+            INSERT INTO pinned_messages (message_id, room_id, pinned_by)
+            SELECT m.id, m.room_id, ?2 FROM messages m
+            WHERE m.id = ?1 AND m.deleted_at IS NULL
+              AND EXISTS (SELECT 1 FROM room_moderators rm WHERE rm.room_id = m.room_id AND rm.user_id = ?2)
+            ON CONFLICT DO NOTHING
+            RETURNING room_id
+            "#
+        } else {
+            r#"
+            DELETE FROM pinned_messages
+            WHERE message_id = ?1
+              AND EXISTS (SELECT 1 FROM messages m WHERE m.id = ?1 AND m.deleted_at IS NULL)
+              AND EXISTS (
+                      SELECT 1 FROM room_moderators rm
+                      WHERE rm.room_id = pinned_messages.room_id AND rm.user_id = ?2
+                  )
+            RETURNING room_id
+            "#
+        };
+        let room_id = sqlx::query_scalar(statement)
+            .bind(message_id)
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(room_id)
+    }
+
+    async fn list_pins(&self, room_id: Uuid) -> StorageResult<Vec<PinRecord>> {
+        let pins = sqlx::query_as(
+            r#"
+            SELECT m.id AS message_id, m.user_id, u.username, m.content, m.created_at,
+                   p.pinned_by, p.pinned_at
+            FROM pinned_messages p
+            JOIN messages m ON p.message_id = m.id
+            JOIN users u ON m.user_id = u.id
+            WHERE p.room_id = ?1 AND m.deleted_at IS NULL
+              AND (m.expires_at IS NULL OR m.expires_at > ?2)
+            ORDER BY p.pinned_at DESC
+            "#,
+        )
+        .bind(room_id)
+        .bind(now_text())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(pins)
+    }
+
+    async fn record_mentions(
+        &self,
+        message_id: i64,
+        room_id: Uuid,
+        author_id: Uuid,
+        usernames: &[String],
+    ) -> StorageResult<Vec<Uuid>> {
+        let mut query = QueryBuilder::<Sqlite>::new("INSERT INTO message_mentions (message_id, user_id) SELECT ");
+        query.push_bind(message_id);
+        query.push(", u.id FROM users u JOIN rooms r ON r.id = ");
+        query.push_bind(room_id);
+        query.push(" WHERE u.id <> ");
+        query.push_bind(author_id);
+        query.push(
+            r#"
+              AND (NOT r.is_direct OR EXISTS (
+                      SELECT 1 FROM direct_participants p
+                      WHERE p.room_id = r.id AND p.user_id = u.id
+                  ))
+              AND u.username IN ("#,
+        );
+        let mut list = query.separated(", ");
+        for username in usernames {
+            list.push_bind(username);
+        }
+        query.push(") ON CONFLICT DO NOTHING RETURNING user_id");
+        Ok(query.build_query_scalar().fetch_all(&self.pool).await?)
+    }
+
+    async fn list_mentions(&self, user_id: Uuid) -> StorageResult<Vec<MentionRecord>> {
+        let mentions = sqlx::query_as(
+            r#"
+            SELECT m.id AS message_id, m.room_id, r.name AS room_name,
+                   m.user_id AS author_id, u.username AS author_username,
+                   m.content, m.created_at
+            FROM message_mentions mm
+            JOIN messages m ON mm.message_id = m.id
+            JOIN rooms r ON m.room_id = r.id
+            JOIN users u ON m.user_id = u.id
+            WHERE mm.user_id = ?1 AND mm.acknowledged_at IS NULL AND m.deleted_at IS NULL
+              AND (m.expires_at IS NULL OR m.expires_at > ?2)
+            ORDER BY m.created_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .bind(now_text())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(mentions)
+    }
+
+    async fn acknowledge_mention(&self, message_id: i64, user_id: Uuid) -> StorageResult<bool> {
+        let result = sqlx::query(
+            "UPDATE message_mentions SET acknowledged_at = ?3 WHERE message_id = ?1 AND user_id = ?2 AND acknowledged_at IS NULL",
+        )
+        .bind(message_id)
+        .bind(user_id)
+        .bind(now_text())
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn bookmarks(&self, user_id: Uuid, message_id: Option<i64>) -> StorageResult<Vec<BookmarkRecord>> {
+        // Bookmarks of purged messages are removed with them by the foreign key.
+        let bookmarks = sqlx::query_as(
+            r#"
+            SELECT m.id AS message_id, m.room_id, r.name AS room_name,
+                   m.user_id AS author_id, u.username AS author_username,
+                   CASE WHEN m.deleted_at IS NULL THEN m.content END AS content,
+                   m.deleted_at IS NOT NULL AS deleted, m.created_at,
+                   b.note, b.remind_at, b.reminded_at, b.created_at AS bookmarked_at
+            FROM bookmarks b
+            JOIN messages m ON b.message_id = m.id
+            JOIN rooms r ON m.room_id = r.id
+            JOIN users u ON m.user_id = u.id
+            WHERE b.user_id = ?1 AND (?2 IS NULL OR b.message_id = ?2)
+              AND (m.expires_at IS NULL OR m.expires_at > ?3)
+            ORDER BY b.created_at DESC, b.message_id DESC
+            "#,
+        )
+        .bind(user_id)
+        .bind(message_id)
+        .bind(now_text())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(bookmarks)
+    }
+
+    async fn save_bookmark(
+        &self,
+        user_id: Uuid,
+        message_id: i64,
+        note: Option<&str>,
+        remind_at: Option<DateTime<Utc>>,
+    ) -> StorageResult<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO bookmarks (user_id, message_id, note, remind_at) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (user_id, message_id) DO UPDATE
+            SET note = ?3, remind_at = ?4, reminded_at = NULL
+            "#,
+        )
+        .bind(user_id)
+        .bind(message_id)
+        .bind(note)
+        .bind(remind_at.map(timestamp_text))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_bookmark(&self, user_id: Uuid, message_id: i64) -> StorageResult<bool> {
+        let result = sqlx::query("DELETE FROM bookmarks WHERE user_id = ?1 AND message_id = ?2")
+            .bind(user_id)
+            .bind(message_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn claim_reminders(&self, user_ids: &[Uuid]) -> StorageResult<Vec<DueReminder>> {
+        // Marking the reminders as sent in the same statement means each is sent
+        // once, even with several servers sharing the database file.
+        let now = now_text();
+        let mut query = QueryBuilder::<Sqlite>::new("UPDATE bookmarks SET reminded_at = ");
+        query.push_bind(&now);
+        query.push(" WHERE remind_at <= ");
+        query.push_bind(&now);
+        query.push(
+            r#"
+              AND reminded_at IS NULL
+              AND EXISTS (SELECT 1 FROM messages m WHERE m.id = bookmarks.message_id AND m.deleted_at IS NULL)
+              AND user_id IN ("#,
+        );
+        let mut list = query.separated(", ");
+        for user_id in user_ids {
+            list.push_bind(*user_id);
+        }
+        query.push(
+            r#")
+            RETURNING user_id, message_id,
+                      (SELECT m.room_id FROM messages m WHERE m.id = bookmarks.message_id) AS room_id,
+                      note, remind_at
+            "#,
+        );
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    async fn schedule_message(&self, message: NewMessage<'_>, send_at: DateTime<Utc>) -> StorageResult<Option<i64>> {
+        if !message.attachment_ids.is_empty() {
+            let mut query = QueryBuilder::<Sqlite>::new("SELECT COUNT(*) FROM attachments WHERE uploader_id = ");
+            query.push_bind(message.user_id);
+            query.push(" AND room_id = ");
+            query.push_bind(message.room_id);
+            query.push(" AND message_id IS NULL AND id IN (");
+            let mut list = query.separated(", ");
+            for attachment_id in message.attachment_ids {
+                list.push_bind(*attachment_id);
+            }
+            query.push(")");
+            let usable: i64 = query.build_query_scalar().fetch_one(&self.pool).await?;
+
+            let mut requested = message.attachment_ids.to_vec();
+            requested.sort();
+            requested.dedup();
+            if usable != requested.len() as i64 {
+                return Ok(None);
+            }
+        }
+
+        let id = sqlx::query_scalar(
+            r#"
+            INSERT INTO scheduled_messages (room_id, user_id, content, parent_id, attachment_ids, send_at, ttl_seconds, quote_id)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            RETURNING id
+            "#,
+        )
+        .bind(message.room_id)
+        .bind(message.user_id)
+        .bind(message.content)
+        .bind(message.parent_id)
+        .bind(SqlJson(message.attachment_ids))
+        .bind(timestamp_text(send_at))
+        .bind(message.ttl_seconds)
+        .bind(message.quote.map(|q| q.message_id))
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(Some(id))
+    }
+
+    async fn list_scheduled(&self, user_id: Uuid) -> StorageResult<Vec<ScheduledRecord>> {
+        let rows: Vec<ScheduledRow> = sqlx::query_as(
+            r#"
+            SELECT id, room_id, content, parent_id AS reply_to, attachment_ids, send_at, ttl_seconds, quote_id, created_at
+            FROM scheduled_messages
+            WHERE user_id = ?1
+            ORDER BY send_at, id
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| ScheduledRecord {
+                id: r.id,
+                room_id: r.room_id,
+                content: r.content,
+                reply_to: r.reply_to,
+                attachment_ids: r.attachment_ids.0,
+                send_at: r.send_at,
+                ttl_seconds: r.ttl_seconds,
+                quote_id: r.quote_id,
+                created_at: r.created_at,
+            })
+            .collect())
+    }
+
+    async fn cancel_scheduled(&self, id: i64, user_id: Uuid) -> StorageResult<bool> {
+        // Follows the Postgres backend: a live claim means delivery is under way.
+        let result = sqlx::query(
+            r#"
+            DELETE FROM scheduled_messages
+            WHERE id = ?1 AND user_id = ?2 AND (claimed_at IS NULL OR claimed_at <= ?3)
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(timestamp_text(Utc::now() - Duration::minutes(5)))
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn claim_scheduled(&self, limit: i64) -> StorageResult<Vec<DueMessage>> {
+        // One statement, so servers sharing the database file cannot claim the
+        // same rows. Claims older than five minutes are from a server that
+        // stopped mid-delivery and are taken over.
+        let now = Utc::now();
+        let rows: Vec<DueRow> = sqlx::query_as(
+            r#"
+            UPDATE scheduled_messages SET claimed_at = ?1
+            WHERE id IN (
+                SELECT id FROM scheduled_messages
+                WHERE send_at <= ?1
+                  AND (claimed_at IS NULL OR claimed_at <= ?2)
+                ORDER BY send_at, id
+                LIMIT ?3
+            )
+            RETURNING id, room_id, user_id, content, parent_id, attachment_ids, send_at, ttl_seconds, quote_id
+            "#,
+        )
+        .bind(timestamp_text(now))
+        .bind(timestamp_text(now - Duration::minutes(5)))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut due: Vec<DueMessage> = rows
+            .into_iter()
+            .map(|r| DueMessage {
+                id: r.id,
+                room_id: r.room_id,
+                user_id: r.user_id,
+                content: r.content,
+                parent_id: r.parent_id,
+                attachment_ids: r.attachment_ids.0,
+                send_at: r.send_at,
+                ttl_seconds: r.ttl_seconds,
+                quote_id: r.quote_id,
+            })
+            .collect();
+        due.sort_by_key(|m| (m.send_at, m.id));
+        Ok(due)
+    }
+
+    async fn finish_scheduled(&self, id: i64) -> StorageResult<()> {
+        sqlx::query("DELETE FROM scheduled_messages WHERE id = ?1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn insert_attachment(&self, attachment: NewAttachment<'_>) -> StorageResult<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO attachments (id, uploader_id, room_id, file_name, content_type, size_bytes)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            "#,
+        )
+        .bind(attachment.id)
+        .bind(attachment.uploader_id)
+        .bind(attachment.room_id)
+        .bind(attachment.file_name)
+        .bind(attachment.content_type)
+        .bind(attachment.size_bytes)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn attachment(&self, id: Uuid) -> StorageResult<Option<AttachmentAccess>> {
+        let attachment = sqlx::query_as(
+            r#"
+            SELECT a.uploader_id, a.room_id, a.file_name, a.content_type,
+                   (m.id IS NOT NULL AND m.deleted_at IS NULL
+                    AND (m.expires_at IS NULL OR m.expires_at > ?2)) AS visible
+            FROM attachments a
+            LEFT JOIN messages m ON a.message_id = m.id
+            WHERE a.id = ?1
+            "#,
+        )
+        .bind(id)
+        .bind(now_text())
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(attachment)
+    }
+
+    async fn search(&self, query: SearchQuery<'_>) -> StorageResult<Vec<SearchResult>> {
+        let text = TextQuery::parse(query.text);
+        let Some(expression) = text.to_fts5() else {
+            return Ok(Vec::new());
+        };
+
+        // `snippet` starts out as the raw content and is marked up below.
+        let mut results: Vec<SearchResult> = sqlx::query_as(
+            r#"
+            SELECT m.id AS message_id, m.room_id, r.name AS room_name,
+                   m.user_id, u.username, m.content AS snippet, m.created_at,
+                   -bm25(message_search) AS rank
+            FROM message_search
+            JOIN messages m ON m.id = message_search.rowid
+            JOIN rooms r ON m.room_id = r.id
+            JOIN users u ON m.user_id = u.id
+            WHERE message_search MATCH ?1
+              AND m.deleted_at IS NULL
+              AND (m.expires_at IS NULL OR m.expires_at > ?9)
+              AND (NOT r.is_direct OR EXISTS (
+                      SELECT 1 FROM direct_participants p
+                      WHERE p.room_id = r.id AND p.user_id = ?2
+                  ))
+              AND (?3 IS NULL OR m.room_id = ?3)
+              AND (?4 IS NULL OR m.user_id = ?4)
+              AND (?5 IS NULL OR m.created_at >= ?5)
+              AND (?6 IS NULL OR m.created_at < ?6)
+            ORDER BY bm25(message_search), m.id DESC
+            LIMIT ?7 OFFSET ?8
+            "#,
+        )
+        .bind(expression)
+        .bind(query.viewer)
+        .bind(query.room_id)
+        .bind(query.author_id)
+        .bind(query.from.map(timestamp_text))
+        .bind(query.to.map(timestamp_text))
+        .bind(query.limit)
+        .bind(query.offset)
+        .bind(now_text())
+        .fetch_all(&self.pool)
+        .await?;
+
+        for result in &mut results {
+            result.snippet = text.snippet(&result.snippet);
+        }
+        Ok(results)
+    }
+
+    async fn export_page(&self, room_id: Uuid, after: i64, limit: i64) -> StorageResult<Vec<ExportRow>> {
+        let rows = sqlx::query_as(
+            r#"
+            SELECT m.id, m.parent_id, m.user_id, u.username,
+                   CASE WHEN m.deleted_at IS NULL THEN m.content END AS content,
+                   m.created_at, m.edited_at, m.deleted_at
+            FROM messages m
+            JOIN users u ON m.user_id = u.id
+            WHERE m.room_id = ?1 AND m.id > ?2 AND (m.expires_at IS NULL OR m.expires_at > ?4)
+            ORDER BY m.id
+            LIMIT ?3
+            "#,
+        )
+        .bind(room_id)
+        .bind(after)
+        .bind(limit)
+        .bind(now_text())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    async fn expire_messages(&self, limit: i64) -> StorageResult<PurgedMessages> {
+        let mut tx = self.pool.begin_with("BEGIN IMMEDIATE").await?;
+
+        let expired: Vec<i64> = sqlx::query_scalar(
+            "SELECT id FROM messages WHERE expires_at <= ?1 ORDER BY expires_at, id LIMIT ?2",
+        )
+        .bind(now_text())
+        .bind(limit)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        if expired.is_empty() {
+            return Ok(PurgedMessages::default());
+        }
+
+        // Replies go with their thread, whether or not they expired themselves.
+        let purged = delete_messages(&mut tx, &expired, true).await?;
+        tx.commit().await?;
+        Ok(purged)
+    }
+
+    async fn purge_retained(&self, limit: i64) -> StorageResult<PurgedMessages> {
+        let mut tx = self.pool.begin_with("BEGIN IMMEDIATE").await?;
+
+        let expired: Vec<i64> = sqlx::query_scalar(
+            r#"
+            SELECT m.id
+            FROM messages m
+            JOIN rooms r ON m.room_id = r.id
+            WHERE r.retention_days IS NOT NULL
+              AND m.created_at < strftime('%Y-%m-%dT%H:%M:%fZ', 'now', printf('-%d days', r.retention_days))
+            ORDER BY m.id
+            LIMIT ?1
+            "#,
+        )
+        .bind(limit)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        if expired.is_empty() {
+            return Ok(PurgedMessages::default());
+        }
+
+        // Replies to a purged message are removed with it by the `parent_id` cascade.
+        let purged = delete_messages(&mut tx, &expired, false).await?;
+        tx.commit().await?;
+        Ok(purged)
+    }
+}
+
+/// Deletes the messages `ids` with their attachment rows, which would
+/// otherwise outlive them with `message_id` NULL. With `with_replies`, the
+/// replies to `ids` are deleted and reported too; otherwise they only go by
+/// the `parent_id` cascade, as with Postgres.
+async fn delete_messages(
+    tx: &mut sqlx::Transaction<'_, Sqlite>,
+    ids: &[i64],
+    with_replies: bool,
+) -> StorageResult<PurgedMessages> {
+    let mut query = QueryBuilder::<Sqlite>::new("DELETE FROM attachments WHERE message_id IN (SELECT id FROM messages WHERE ");
+    push_selection(&mut query, ids, true);
+    query.push(") RETURNING id");
+    let attachment_ids: Vec<Uuid> = query.build_query_scalar().fetch_all(&mut **tx).await?;
+
+    // SQLite cascades as soon as a row is deleted, so replies go first; a reply
+    // removed by its parent's cascade would not be reported.
+    let mut query = QueryBuilder::<Sqlite>::new("DELETE FROM messages WHERE parent_id IS NOT NULL AND (");
+    push_selection(&mut query, ids, with_replies);
+    query.push(") RETURNING id, room_id");
+    let mut messages: Vec<(i64, Uuid)> = query.build_query_as().fetch_all(&mut **tx).await?;
+
+    let mut query = QueryBuilder::<Sqlite>::new("DELETE FROM messages WHERE ");
+    push_selection(&mut query, ids, false);
+    query.push(" RETURNING id, room_id");
+    messages.extend(query.build_query_as::<(i64, Uuid)>().fetch_all(&mut **tx).await?);
+
+    Ok(PurgedMessages { messages, attachment_ids })
+}
+
+/// Pushes a condition selecting the messages `ids`, and with `replies` also
+/// their replies.
+fn push_selection(query: &mut QueryBuilder<'_, Sqlite>, ids: &[i64], replies: bool) {
+    query.push("(id IN (");
+    let mut list = query.separated(", ");
+    for id in ids {
+        list.push_bind(*id);
+    }
+    query.push(")");
+    if replies {
+        query.push(" OR parent_id IN (");
+        let mut list = query.separated(", ");
+        for id in ids {
+            list.push_bind(*id);
+        }
+        query.push(")");
+    }
+    query.push(")");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A store on a fresh database file; `:memory:` would give each pooled
+    /// connection a database of its own.
+    async fn open() -> SqliteStore {
+        let path = std::env::temp_dir().join(format!("chat-backend-test-{}.db", Uuid::new_v4()));
+        SqliteStore::open(path.to_str().expect("temp dir is UTF-8")).await.expect("test database opens")
+    }
+
+    fn message(room_id: Uuid, user_id: Uuid, content: &str) -> NewMessage<'_> {
+        NewMessage { room_id, user_id, content, parent_id: None, attachment_ids: &[], ttl_seconds: None, quote: None }
+    }
+
+    async fn user(store: &SqliteStore, username: &str) -> Uuid {
+        store.create_user(username, "hash").await.unwrap().expect("username is free")
+    }
+
+    async fn room(store: &SqliteStore, name: &str, creator: Uuid) -> Uuid {
+        store.create_room(name, None, None, creator).await.unwrap().expect("room name is free").id
+    }
+
+    async fn post(store: &SqliteStore, message: NewMessage<'_>) -> i64 {
+        store.insert_message(message).await.unwrap().expect("message is valid").id
+    }
+
+    fn ids(messages: &[MessageRecord]) -> Vec<i64> {
+        messages.iter().map(|m| m.id).collect()
+    }
+
+    fn search_query(text: &str, viewer: Uuid) -> SearchQuery<'_> {
+        SearchQuery { text, viewer, room_id: None, author_id: None, from: None, to: None, limit: 10, offset: 0 }
+    }
+
+    /// The ids of the messages `viewer` finds with `text`, in id order.
+    async fn found(store: &SqliteStore, text: &str, viewer: Uuid) -> Vec<i64> {
+        let mut ids: Vec<i64> = store.search(search_query(text, viewer)).await.unwrap().iter().map(|r| r.message_id).collect();
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn usernames_are_unique() {
+        let store = open().await;
+        let alice = user(&store, "alice").await;
+
+        assert!(store.create_user("alice", "other").await.unwrap().is_none());
+        let credentials = store.find_credentials("alice").await.unwrap().unwrap();
+        assert_eq!(credentials.id, alice);
+        assert_eq!(credentials.password_hash, "hash");
+        assert!(store.find_credentials("bob").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn room_names_are_unique() {
+        let store = open().await;
+        let alice = user(&store, "alice").await;
+        let general = room(&store, "general", alice).await;
+
+        assert!(store.create_room("general", Some(7), None, alice).await.unwrap().is_none());
+        assert!(store.is_moderator(general, alice).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn history_pages_before_and_after() {
+        let store = open().await;
+        let alice = user(&store, "alice").await;
+        let general = room(&store, "general", alice).await;
+        let mut posted = Vec::new();
+        for content in ["one", "two", "three", "four", "five"] {
+            posted.push(post(&store, message(general, alice, content)).await);
+        }
+        let page = |before, after| HistoryQuery { room_id: general, viewer: alice, before, after, limit: 2 };
+
+        let latest = store.history(page(None, None)).await.unwrap();
+        assert_eq!(ids(&latest), [posted[4], posted[3]]);
+        let older = store.history(page(Some(posted[3]), None)).await.unwrap();
+        assert_eq!(ids(&older), [posted[2], posted[1]]);
+        let newer = store.history(page(None, Some(posted[1]))).await.unwrap();
+        assert_eq!(ids(&newer), [posted[2], posted[3]]);
+    }
+
+    #[tokio::test]
+    async fn replies_resolve_to_their_thread_root() {
+        let store = open().await;
+        let alice = user(&store, "alice").await;
+        let general = room(&store, "general", alice).await;
+        let random = room(&store, "random", alice).await;
+        let root = post(&store, message(general, alice, "root")).await;
+        let reply = post(&store, NewMessage { parent_id: Some(root), ..message(general, alice, "reply") }).await;
+
+        assert_eq!(store.thread_root(general, root).await.unwrap(), Some(root));
+        assert_eq!(store.thread_root(general, reply).await.unwrap(), Some(root));
+        assert_eq!(store.thread_root(random, reply).await.unwrap(), None);
+
+        let history = store.history(HistoryQuery { room_id: general, viewer: alice, before: None, after: None, limit: 10 });
+        let history = history.await.unwrap();
+        assert_eq!(ids(&history), [root]);
+        assert_eq!(history[0].reply_count, 1);
+        let thread = store.thread(general, root, alice).await.unwrap();
+        assert_eq!(ids(&thread), [root, reply]);
+    }
+
+    #[tokio::test]
+    async fn expired_messages_are_hidden_then_removed() {
+        let store = open().await;
+        let alice = user(&store, "alice").await;
+        let bob = user(&store, "bob").await;
+        let general = room(&store, "general", alice).await;
+        let kept = post(&store, message(general, alice, "kept")).await;
+        let ephemeral = post(&store, NewMessage { ttl_seconds: Some(60), ..message(general, alice, "gone") }).await;
+        let reply = post(&store, NewMessage { parent_id: Some(ephemeral), ..message(general, alice, "with it") }).await;
+        let history = || store.history(HistoryQuery { room_id: general, viewer: alice, before: None, after: None, limit: 10 });
+
+        assert_eq!(ids(&history().await.unwrap()), [ephemeral, kept]);
+        assert_eq!(store.list_rooms(bob).await.unwrap()[0].unread_count, 3);
+        assert!(store.expire_messages(10).await.unwrap().messages.is_empty());
+
+        sqlx::query("UPDATE messages SET expires_at = ?1 WHERE id = ?2")
+            .bind(timestamp_text(Utc::now() - Duration::seconds(1)))
+            .bind(ephemeral)
+            .execute(&store.pool)
+            .await
+            .unwrap();
+        assert_eq!(ids(&history().await.unwrap()), [kept]);
+        assert_eq!(store.list_rooms(bob).await.unwrap()[0].unread_count, 2);
+        assert!(store.quote_source(ephemeral).await.unwrap().is_none());
+        let mut expired = store.expire_messages(10).await.unwrap().messages;
+        expired.sort();
+        assert_eq!(expired, [(ephemeral, general), (reply, general)]);
+        assert!(store.thread(general, ephemeral, alice).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn unread_and_mention_counts_follow_read_position() {
+        let store = open().await;
+        let alice = user(&store, "alice").await;
+        let bob = user(&store, "bob").await;
+        let general = room(&store, "general", alice).await;
+        let hello = post(&store, message(general, alice, "hello")).await;
+        let ping = post(&store, message(general, alice, "@bob ping")).await;
+        assert_eq!(store.record_mentions(ping, general, alice, &["bob".to_string()]).await.unwrap(), [bob]);
+        post(&store, message(general, bob, "pong")).await;
+
+        let listing = store.list_rooms(bob).await.unwrap();
+        assert_eq!((listing[0].unread_count, listing[0].mention_count), (2, 1));
+
+        assert!(store.mark_read(general, bob, hello).await.unwrap());
+        let listing = store.list_rooms(bob).await.unwrap();
+        assert_eq!((listing[0].unread_count, listing[0].mention_count), (1, 1));
+
+        assert!(store.mark_read(general, bob, ping).await.unwrap());
+        assert!(!store.mark_read(general, bob, hello).await.unwrap());
+        let listing = store.list_rooms(bob).await.unwrap();
+        assert_eq!((listing[0].unread_count, listing[0].mention_count), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn direct_rooms_are_private_to_their_participants() {
+        let store = open().await;
+        let alice = user(&store, "alice").await;
+        let bob = user(&store, "bob").await;
+        let carol = user(&store, "carol").await;
+        let general = room(&store, "general", alice).await;
+
+        let direct = store.open_direct(alice, bob).await.unwrap();
+        assert_eq!(store.open_direct(bob, alice).await.unwrap(), direct);
+        assert!(store.can_access(direct, bob).await.unwrap());
+        assert!(!store.can_access(direct, carol).await.unwrap());
+        assert!(store.can_access(general, carol).await.unwrap());
+        assert!(!store.can_access(Uuid::new_v4(), alice).await.unwrap());
+        let listed: Vec<Uuid> = store.list_rooms(alice).await.unwrap().iter().map(|r| r.id).collect();
+        assert_eq!(listed, [general]);
+    }
+
+    #[tokio::test]
+    async fn edits_deletes_and_reactions_show_in_history() {
+        let store = open().await;
+        let alice = user(&store, "alice").await;
+        let bob = user(&store, "bob").await;
+        let general = room(&store, "general", alice).await;
+        let first = post(&store, message(general, bob, "frist")).await;
+        let second = post(&store, message(general, bob, "oops")).await;
+
+        assert!(store.edit_message(first, alice, "mine now").await.unwrap().is_none());
+        assert!(store.edit_message(first, bob, "first").await.unwrap().is_some());
+        assert!(store.set_reaction(first, alice, "👍", true).await.unwrap().is_some());
+        assert!(store.set_reaction(first, alice, "👍", true).await.unwrap().is_none());
+        // Moderators may delete anyone's message.
+        assert!(store.delete_message(second, alice).await.unwrap().is_some());
+
+        let history = store.history(HistoryQuery { room_id: general, viewer: alice, before: None, after: None, limit: 10 });
+        let history = history.await.unwrap();
+        let (deleted, edited) = (&history[0], &history[1]);
+        assert!(deleted.deleted && deleted.content.is_none());
+        assert_eq!(deleted.deleted_by, Some(alice));
+        assert!(edited.edited);
+        assert_eq!(edited.content.as_deref(), Some("first"));
+        assert_eq!(edited.reactions.0.len(), 1);
+        assert!(edited.reactions.0[0].reacted);
+    }
+
+    #[tokio::test]
+    async fn search_matches_phrases_and_exclusions() {
+        let store = open().await;
+        let alice = user(&store, "alice").await;
+        let bob = user(&store, "bob").await;
+        let carol = user(&store, "carol").await;
+        let general = room(&store, "general", alice).await;
+        let plan = post(&store, message(general, alice, "Lunch plan: <b>tacos</b> & salsa")).await;
+        let cancelled = post(&store, message(general, bob, "lunch is cancelled")).await;
+        let dinner = post(&store, message(general, bob, "dinner plan")).await;
+        let direct = store.open_direct(alice, bob).await.unwrap();
+        let secret = post(&store, message(direct, alice, "secret lunch plan")).await;
+
+        assert_eq!(found(&store, "lunch", carol).await, [plan, cancelled]);
+        assert_eq!(found(&store, "lunch", bob).await, [plan, cancelled, secret]);
+        assert_eq!(found(&store, "\"lunch plan\"", bob).await, [plan, secret]);
+        assert_eq!(found(&store, "lunch -cancelled", carol).await, [plan]);
+        assert_eq!(found(&store, "tacos or dinner", carol).await, [plan, dinner]);
+        assert!(found(&store, "-lunch", carol).await.is_empty());
+
+        let results = store.search(search_query("tacos", carol)).await.unwrap();
+        assert_eq!(results[0].snippet, "Lunch plan: &lt;b&gt;<mark>tacos</mark>&lt;/b&gt; &amp; salsa");
+    }
+
+    #[tokio::test]
+    async fn scheduled_messages_are_claimed_once_when_due() {
+        let store = open().await;
+        let alice = user(&store, "alice").await;
+        let bob = user(&store, "bob").await;
+        let general = room(&store, "general", alice).await;
+        let attachment = Uuid::new_v4();
+        let upload = NewAttachment {
+            id: attachment,
+            uploader_id: alice,
+            room_id: general,
+            file_name: "notes.txt",
+            content_type: "text/plain",
+            size_bytes: 5,
+        };
+        store.insert_attachment(upload).await.unwrap();
+        let now = Utc::now();
+
+        let later = store.schedule_message(message(general, alice, "later"), now + Duration::hours(1)).await.unwrap();
+        let later = later.expect("message is valid");
+        let attached = NewMessage { attachment_ids: &[attachment], ..message(general, alice, "now") };
+        let due = store.schedule_message(attached, now - Duration::seconds(1)).await.unwrap();
+        let due = due.expect("attachment belongs to alice");
+        let stolen = NewMessage { attachment_ids: &[attachment], ..message(general, bob, "mine") };
+        assert!(store.schedule_message(stolen, now).await.unwrap().is_none());
+
+        let scheduled: Vec<i64> = store.list_scheduled(alice).await.unwrap().iter().map(|s| s.id).collect();
+        assert_eq!(scheduled, [due, later]);
+        let claimed = store.claim_scheduled(10).await.unwrap();
+        assert_eq!(claimed.iter().map(|m| m.id).collect::<Vec<_>>(), [due]);
+        assert_eq!(claimed[0].attachment_ids, [attachment]);
+        assert!(store.claim_scheduled(10).await.unwrap().is_empty());
+        assert!(!store.cancel_scheduled(due, alice).await.unwrap());
+
+        store.finish_scheduled(due).await.unwrap();
+        assert!(!store.cancel_scheduled(later, bob).await.unwrap());
+        assert!(store.cancel_scheduled(later, alice).await.unwrap());
+        assert!(store.list_scheduled(alice).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn pins_and_bookmarks_follow_their_message() {
+        let store = open().await;
+        let alice = user(&store, "alice").await;
+        let bob = user(&store, "bob").await;
+        let general = room(&store, "general", alice).await;
+        let note = post(&store, message(general, bob, "remember this")).await;
+
+        // Only moderators pin, and pinning twice changes nothing.
+        assert!(store.set_pinned(note, bob, true).await.unwrap().is_none());
+        assert_eq!(store.set_pinned(note, alice, true).await.unwrap(), Some(general));
+        assert!(store.set_pinned(note, alice, true).await.unwrap().is_none());
+        let pins = store.list_pins(general).await.unwrap();
+        assert_eq!(pins.iter().map(|p| (p.message_id, p.pinned_by)).collect::<Vec<_>>(), [(note, Some(alice))]);
+
+        let due = Utc::now() - Duration::seconds(1);
+        store.save_bookmark(bob, note, Some("todo"), Some(due)).await.unwrap();
+        let reminders = store.claim_reminders(&[alice, bob]).await.unwrap();
+        assert_eq!(reminders.iter().map(|r| (r.user_id, r.message_id, r.room_id)).collect::<Vec<_>>(), [(bob, note, general)]);
+        assert!(store.claim_reminders(&[bob]).await.unwrap().is_empty());
+
+        assert!(store.delete_message(note, bob).await.unwrap().is_some());
+        assert!(store.list_pins(general).await.unwrap().is_empty());
+        let bookmarks = store.bookmarks(bob, None).await.unwrap();
+        assert!(bookmarks[0].deleted && bookmarks[0].content.is_none());
+        assert_eq!(bookmarks[0].note.as_deref(), Some("todo"));
+        assert!(bookmarks[0].reminded_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn retention_purges_old_messages_with_their_replies() {
+        let store = open().await;
+        let alice = user(&store, "alice").await;
+        let kept = store.create_room("kept", None, None, alice).await.unwrap().unwrap().id;
+        let purged = store.create_room("purged", Some(7), None, alice).await.unwrap().unwrap().id;
+        let old = post(&store, message(purged, alice, "old")).await;
+        let reply = post(&store, NewMessage { parent_id: Some(old), ..message(purged, alice, "recent reply") }).await;
+        let recent = post(&store, message(purged, alice, "recent")).await;
+        let forever = post(&store, message(kept, alice, "forever")).await;
+        let month_ago = Utc::now() - Duration::days(30);
+        for id in [old, forever] {
+            sqlx::query("UPDATE messages SET created_at = ?1 WHERE id = ?2")
+                .bind(timestamp_text(month_ago))
+                .bind(id)
+                .execute(&store.pool)
+                .await
+                .unwrap();
+        }
+        let removed = store.purge_retained(10).await.unwrap();
+        assert_eq!(removed.messages, [(old, purged)]);
+        assert!(store.purge_retained(10).await.unwrap().messages.is_empty());
+        let history = |room_id| store.history(HistoryQuery { room_id, viewer: alice, before: None, after: None, limit: 10 });
+        assert_eq!(ids(&history(purged).await.unwrap()), [recent]);
+        assert_eq!(ids(&history(kept).await.unwrap()), [forever]);
+        assert_eq!(store.thread_root(purged, reply).await.unwrap(), None);
+    }
+}
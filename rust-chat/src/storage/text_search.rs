@@ -0,0 +1,164 @@
+// src/storage/text_search.rs
+
+// Message search for the backends without Postgres' text search. Queries use
+// the same web-search syntax as `websearch_to_tsquery`: words, quoted
+// phrases, `or` between alternatives and `-` to exclude. Words are compared
+// case-insensitively but not stemmed, so "meeting" does not find "meetings".
+// The memory backend matches in Rust; SQLite hands `to_fts5` to its FTS5
+// index, which splits words the same way.
+
+/// One word of a text, with its byte range in the original.
+struct Word {
+    start: usize,
+    end: usize,
+    text: String, // Lowercased.
+}
+
+fn words(text: &str) -> Vec<Word> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+        match (start, c.is_alphanumeric()) {
+            (None, true) => start = Some(i),
+            (Some(s), false) => {
+                words.push(Word { start: s, end: i, text: text[s..i].to_lowercase() });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    words
+}
+
+/// Words that must appear consecutively.
+type Phrase = Vec<String>;
+
+/// Alternatives joined by `or`, of which one must match (or, when negated,
+/// none may).
+struct Clause {
+    negated: bool,
+    alternatives: Vec<Phrase>,
+}
+
+pub struct TextQuery {
+    clauses: Vec<Clause>, // All must hold.
+}
+
+impl TextQuery {
+    pub fn parse(text: &str) -> Self {
+        let mut clauses: Vec<Clause> = Vec::new();
+        let mut pending_or = false;
+        let mut rest = text.trim_start();
+
+        while !rest.is_empty() {
+            let negated = rest.starts_with('-');
+            if negated {
+                rest = &rest[1..];
+            }
+            let token;
+            if let Some(quoted) = rest.strip_prefix('"') {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                token = &quoted[..end];
+                rest = quoted.get(end + 1..).unwrap_or("");
+            } else {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                token = &rest[..end];
+                rest = &rest[end..];
+            }
+            rest = rest.trim_start();
+
+            let phrase: Phrase = words(token).into_iter().map(|w| w.text).collect();
+            if phrase.is_empty() {
+                continue;
+            }
+            if !negated && phrase == ["or"] && !clauses.is_empty() {
+                pending_or = true;
+                continue;
+            }
+            match clauses.last_mut() {
+                Some(last) if pending_or && !negated && !last.negated => last.alternatives.push(phrase),
+                _ => clauses.push(Clause { negated, alternatives: vec![phrase] }),
+            }
+            pending_or = false;
+        }
+        TextQuery { clauses }
+    }
+
+    /// Ranges of `words` matched by the query's positive phrases.
+    fn hits(&self, words: &[Word]) -> Vec<(usize, usize)> {
+        let mut hits = Vec::new();
+        for clause in self.clauses.iter().filter(|c| !c.negated) {
+            for phrase in &clause.alternatives {
+                for (i, window) in words.windows(phrase.len()).enumerate() {
+                    if window.iter().zip(phrase).all(|(w, p)| w.text == *p) {
+                        hits.push((i, i + phrase.len()));
+                    }
+                }
+            }
+        }
+        hits
+    }
+
+    pub fn matches(&self, content: &str) -> bool {
+        let words = words(content);
+        let contains = |phrase: &Phrase| {
+            words.windows(phrase.len()).any(|window| window.iter().zip(phrase).all(|(w, p)| w.text == *p))
+        };
+        self.clauses.iter().any(|c| !c.negated)
+            && self
+                .clauses
+                .iter()
+                .all(|c| c.alternatives.iter().any(contains) != c.negated)
+    }
+
+    /// The query as an FTS5 match expression, or `None` if nothing can match.
+    #[cfg(feature = "sqlite")]
+    pub fn to_fts5(&self) -> Option<String> {
+        let clause = |c: &Clause| {
+            let alternatives: Vec<String> = c.alternatives.iter().map(|p| format!("\"{}\"", p.join(" "))).collect();
+            format!("({})", alternatives.join(" OR "))
+        };
+        let positive: Vec<String> = self.clauses.iter().filter(|c| !c.negated).map(clause).collect();
+        if positive.is_empty() {
+            return None;
+        }
+        let mut expression = positive.join(" AND ");
+        for negated in self.clauses.iter().filter(|c| c.negated) {
+            expression = format!("({}) NOT {}", expression, clause(negated));
+        }
+        Some(expression)
+    }
+
+    /// Share of the content's words that match, so short messages that are
+    /// mostly about the query come first.
+    pub fn rank(&self, content: &str) -> f32 {
+        let words = words(content);
+        let matched: usize = self.hits(&words).iter().map(|(start, end)| end - start).sum();
+        matched as f32 / words.len().max(1) as f32
+    }
+
+    /// `content` HTML-escaped, with every matching word wrapped in <mark></mark>.
+    pub fn snippet(&self, content: &str) -> String {
+        let words = words(content);
+        let mut marked = vec![false; words.len()];
+        for (start, end) in self.hits(&words) {
+            marked[start..end].iter_mut().for_each(|m| *m = true);
+        }
+
+        let mut snippet = String::with_capacity(content.len());
+        let mut copied = 0;
+        for (word, _) in words.iter().zip(&marked).filter(|(_, marked)| **marked) {
+            snippet.push_str(&escape_html(&content[copied..word.start]));
+            snippet.push_str("<mark>");
+            snippet.push_str(&escape_html(&content[word.start..word.end]));
+            snippet.push_str("</mark>");
+            copied = word.end;
+        }
+        snippet.push_str(&escape_html(&content[copied..]));
+        snippet
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -1,12 +1,12 @@
-use core::error;
-use std::sync::Arc;
-
-use log::{info, error};
+use chrono::{DateTime, Utc};
+use log::{info, error, warn};
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
 use uuid::Uuid;
 
+use crate::models::{AttachmentSummary, ConnectionId, QuotedMessage};
 use crate::state::ChatServerState;
+use crate::storage::{NewMessage, Storage};
+use crate::websocket::{drafts, typing};
 
 #[derive(Debug, Deserialize)]
 #[serde(tag = "type")]
@@ -17,36 +17,344 @@ pub enum ChatCommand {
         username: String,
     },
 
+    #[serde(rename = "leave_room")]
+    LeaveRoom {
+        room_id: String,
+    },
+
     #[serde(rename = "send_message")]
     SendMessage {
         room_id: String,
         content: String,
+        // Id of the message being replied to, if this message belongs to a thread.
+        #[serde(default)]
+        reply_to: Option<i64>,
+        // Previously uploaded attachments (see `POST /api/attachments`) to link to this message.
+        #[serde(default)]
+        attachment_ids: Vec<Uuid>,
+        // Delivers the message at this time instead of now; past times send immediately.
+        #[serde(default)]
+        send_at: Option<DateTime<Utc>>,
+        // Deletes the message this many seconds after it is sent, overriding
+        // the room's `message_ttl_seconds`.
+        #[serde(default)]
+        ttl_seconds: Option<i32>,
+        // Id of a message to quote, from any room the user can read.
+        #[serde(default)]
+        quote: Option<i64>,
+    },
+
+    // Reposts a message the user can read into `room_id`, with an optional comment.
+    #[serde(rename = "forward_message")]
+    ForwardMessage {
+        message_id: i64,
+        room_id: String,
+        #[serde(default)]
+        comment: String,
+    },
+
+    #[serde(rename = "edit_message")]
+    EditMessage {
+        message_id: i64,
+        content: String,
+    },
+
+    #[serde(rename = "delete_message")]
+    DeleteMessage {
+        message_id: i64,
+    },
+
+    #[serde(rename = "react")]
+    React {
+        message_id: i64,
+        emoji: String,
+    },
+
+    #[serde(rename = "unreact")]
+    Unreact {
+        message_id: i64,
+        emoji: String,
+    },
+
+    // Ephemeral: forwarded to the room but never persisted.
+    #[serde(rename = "typing")]
+    Typing {
+        room_id: String,
+        is_typing: bool,
+    },
+
+    // Replaces the user's unsent draft in a room; empty `content` clears it.
+    // Clients may send this on every keystroke, as saves are debounced.
+    #[serde(rename = "save_draft")]
+    SaveDraft {
+        room_id: String,
+        content: String,
+    },
+
+    #[serde(rename = "mark_read")]
+    MarkRead {
+        room_id: String,
+        message_id: i64,
+        // Whether other members should get a read receipt, e.g. for "seen" in DMs.
+        #[serde(default)]
+        send_receipt: bool,
+    },
+
+    #[serde(rename = "pin_message")]
+    PinMessage {
+        message_id: i64,
+    },
+
+    #[serde(rename = "unpin_message")]
+    UnpinMessage {
+        message_id: i64,
     },
 }
 
+/// Longest emoji string (in bytes) accepted by `React`; matches the column width.
+const MAX_EMOJI_LEN: usize = 64;
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct OutboundMessage {
+    pub id: i64,
     pub room_id: String,
     pub content: String,
     r#type: &'static str,
     username: String,
-    
+    created_at: DateTime<Utc>,
+    reply_to: Option<i64>,
+    attachments: Vec<AttachmentSummary>,
+    expires_at: Option<DateTime<Utc>>, // When the message will be deleted, if ever.
+    quote: Option<QuotedMessage>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MessageEdited {
+    r#type: &'static str,
+    pub id: i64,
+    pub room_id: String,
+    pub content: String,
+    edited_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MessageDeleted {
+    r#type: &'static str,
+    pub id: i64,
+    pub room_id: String,
+    deleted_by: Uuid,
+    deleted_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReactionChanged {
+    r#type: &'static str,
+    pub message_id: i64,
+    pub room_id: String,
+    emoji: String,
+    user_id: Uuid,
+    added: bool,
+    count: i64, // Total reactions with this emoji after the change.
+}
+
+#[derive(Debug, Serialize)]
+pub struct PinChanged {
+    r#type: &'static str,
+    pub message_id: i64,
+    pub room_id: String,
+    pinned: bool,
+    changed_by: Uuid,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReadReceipt {
+    r#type: &'static str,
+    pub room_id: String,
+    user_id: Uuid,
+    message_id: i64,
+}
+
+/// Sent when an ephemeral message reaches its `expires_at` and is deleted, and
+/// for each reply deleted along with it.
+#[derive(Debug, Serialize)]
+pub struct MessageExpired {
+    r#type: &'static str,
+    pub id: i64,
+    pub room_id: String,
+}
+
+impl MessageExpired {
+    pub fn new(id: i64, room_id: String) -> Self {
+        MessageExpired { r#type: "message expired", id, room_id }
+    }
+}
+
+/// Sent only to the author, confirming a `SendMessage` with a future `send_at`.
+#[derive(Debug, Serialize)]
+pub struct MessageScheduled {
+    r#type: &'static str,
+    pub id: i64, // Id of the scheduled message, not of the message it becomes.
+    pub room_id: String,
+    send_at: DateTime<Utc>,
+}
+
+/// Sent only to a mentioned user, whether or not they have joined the room.
+#[derive(Debug, Serialize)]
+pub struct MentionNotification {
+    r#type: &'static str,
+    pub message_id: i64,
+    pub room_id: String,
+    pub content: String,
+    username: String, // Author of the message
+    created_at: DateTime<Utc>,
+}
+
+/// Serializes `event` once and pushes it to every connected member of `room_id`.
+pub(crate) fn broadcast_to_room<T: Serialize>(state: &ChatServerState, room_id: &str, event: &T) {
+    broadcast_to_room_except(state, room_id, event, None);
+}
+
+/// Like `broadcast_to_room`, but skips `except` (usually the user who caused the event).
+pub(crate) fn broadcast_to_room_except<T: Serialize>(
+    state: &ChatServerState,
+    room_id: &str,
+    event: &T,
+    except: Option<&Uuid>,
+) {
+    let message_json = match serde_json::to_string(event) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Failed to serialize event for room {}: {}", room_id, e);
+            return;
+        }
+    };
+
+    if let Some(members) = state.room_members.get(room_id){
+
+        for member_id_ref in members.iter() {
+            let member_id = member_id_ref.key();
+            if except == Some(member_id) {
+                continue;
+            }
+
+            if let Some(connections) = state.connections.get(member_id) {
+                for connection in connections.iter() {
+                    let _ = connection.send(message_json.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Serializes `event` and pushes it to every connection of a single user, if they are online.
+pub(crate) fn send_to_user<T: Serialize>(state: &ChatServerState, user_id: &Uuid, event: &T) {
+    send_to_user_except(state, user_id, event, None);
+}
+
+/// Like `send_to_user`, but skips the connection `except` (usually the one that caused the event).
+pub(crate) fn send_to_user_except<T: Serialize>(
+    state: &ChatServerState,
+    user_id: &Uuid,
+    event: &T,
+    except: Option<ConnectionId>,
+) {
+    if let Some(connections) = state.connections.get(user_id) {
+        match serde_json::to_string(event) {
+            Ok(json) => {
+                for connection in connections.iter() {
+                    if except != Some(*connection.key()) {
+                        let _ = connection.send(json.clone());
+                    }
+                }
+            }
+            Err(e) => error!("Failed to serialize event for user {}: {}", user_id, e),
+        }
+    }
+}
+
+/// Extracts the distinct usernames referenced as `@username` in `content`.
+/// A mention must start the text or follow a non-word character, so e-mail
+/// addresses like `a@b.com` are not treated as mentions.
+fn parse_mentions(content: &str) -> Vec<String> {
+    let is_name_char = |c: char| c.is_alphanumeric() || c == '_' || c == '-' || c == '.';
+    let mut names: Vec<String> = Vec::new();
+    let mut prev: Option<char> = None;
+
+    for (i, c) in content.char_indices() {
+        if c == '@' && !prev.is_some_and(is_name_char) {
+            let rest = &content[i + 1..];
+            let end = rest.find(|c: char| !is_name_char(c)).unwrap_or(rest.len());
+            // A trailing period is punctuation ("thanks @bob."), not part of the name.
+            let name = rest[..end].trim_end_matches('.');
+            if !name.is_empty() && !names.iter().any(|n| n == name) {
+                names.push(name.to_string());
+            }
+        }
+        prev = Some(c);
+    }
+    names
 }
 
 impl ChatCommand{
     pub async fn execute(
         cmd: ChatCommand,
         user_id: Uuid,
+        connection_id: ConnectionId,
         state: &ChatServerState,
-        pool: &PgPool,
+        storage: &Storage,
     ){
         match cmd {
             ChatCommand:: JoinRoom{room_id, username }=>{
-                info!("User {} is joining room {}", user_id, room_id);
+                let room_uuid = match Uuid::parse_str(&room_id) {
+                    Ok(uuid) => uuid,
+                    Err(e) => {
+                        error!("Invalid room_id UUID: {}: {}", room_id, e);
+                        return;
+                    }
+                };
+                match storage.rooms.can_access(room_uuid, user_id).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        warn!("User {} may not join room {}", user_id, room_id);
+                        return;
+                    }
+                    Err(e) => {
+                        error!("Failed to check access to room {}: {}", room_id, e);
+                        return;
+                    }
+                }
 
-                state.room_members.entry(room_id).or_default().insert(user_id);      
+                info!("User {} ({}) is joining room {}", user_id, username, room_id);
+
+                if let Err(e) = storage.rooms.add_member(room_uuid, user_id).await {
+                    error!("Failed to persist membership of {} in {}: {}", user_id, room_id, e);
+                    return;
+                }
+
+                state.room_members.entry(room_id).or_default().insert(user_id);
             }
-            ChatCommand::SendMessage { room_id, content }=> {
+            ChatCommand::LeaveRoom { room_id } => {
+                let room_uuid = match Uuid::parse_str(&room_id) {
+                    Ok(uuid) => uuid,
+                    Err(e) => {
+                        error!("Invalid room_id UUID: {}: {}", room_id, e);
+                        return;
+                    }
+                };
+
+                match storage.rooms.remove_member(room_uuid, user_id).await {
+                    Ok(true) => {
+                        info!("User {} left room {}", user_id, room_id);
+                        typing::update(state, room_id.clone(), user_id, false);
+                        if let Some(members) = state.room_members.get(&room_id) {
+                            members.remove(&user_id);
+                        }
+                    }
+                    Ok(false) => warn!("User {} cannot leave room {}", user_id, room_id),
+                    Err(e) => error!("Failed to remove {} from room {}: {}", user_id, room_id, e),
+                }
+            }
+            ChatCommand::SendMessage { room_id, content, reply_to, attachment_ids, send_at, ttl_seconds, quote }=> {
                 let room_uuid = match Uuid::parse_str(&room_id) {
                     Ok(uuid) => uuid,
                     Err(e) => {
@@ -54,53 +362,408 @@ impl ChatCommand{
                         return;
                     }
                 };
-                let result = sqlx::query!(
-                    "INSERT INTO messages (room_id, user_id, content) VALUES ($1, $2, $3)",
-                    room_uuid,
-                    user_id,
-                    content.clone()
-                )
-                .execute(pool)
-                .await;
 
-            if let Err(e) = result{
-                error!("Failed to insert message: {}", e);
-                return;
+                if ttl_seconds.is_some_and(|secs| secs <= 0) {
+                    warn!("User {} sent a non-positive ttl_seconds to room {}", user_id, room_id);
+                    return;
+                }
+
+                match storage.rooms.can_access(room_uuid, user_id).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        warn!("User {} may not post in room {}", user_id, room_id);
+                        return;
+                    }
+                    Err(e) => {
+                        error!("Failed to check access to room {}: {}", room_id, e);
+                        return;
+                    }
+                }
+
+                // Threads are one level deep: a reply to a reply joins the root's thread.
+                let parent_id = match reply_to {
+                    Some(reply_to) => match storage.messages.thread_root(room_uuid, reply_to).await {
+                        Ok(Some(root_id)) => Some(root_id),
+                        Ok(None) => {
+                            warn!("Message {} is not in room {}; cannot reply", reply_to, room_id);
+                            return;
+                        }
+                        Err(e) => {
+                            error!("Failed to look up reply target {}: {}", reply_to, e);
+                            return;
+                        }
+                    },
+                    None => None,
+                };
+
+                let quoted = match quote {
+                    Some(quote) => match resolve_quote(storage, user_id, quote, false).await {
+                        Some(quoted) => Some(quoted),
+                        None => return,
+                    },
+                    None => None,
+                };
+
+                if let Some(send_at) = send_at.filter(|at| *at > Utc::now()) {
+                    let message = NewMessage {
+                        room_id: room_uuid,
+                        user_id,
+                        content: &content,
+                        parent_id,
+                        attachment_ids: &attachment_ids,
+                        ttl_seconds,
+                        quote: quoted.as_ref(),
+                    };
+                    // The quote is copied again at delivery, in case it changed.
+                    match storage.messages.schedule_message(message, send_at).await {
+                        Ok(Some(id)) => {
+                            info!("User {} scheduled message {} in room {} for {}", user_id, id, room_id, send_at);
+                            drafts::clear(state, storage, room_id.clone(), user_id, connection_id).await;
+                            let scheduled = MessageScheduled {
+                                r#type: "message scheduled",
+                                id,
+                                room_id,
+                                send_at,
+                            };
+                            send_to_user(state, &user_id, &scheduled);
+                        }
+                        Ok(None) => {
+                            warn!("User {} referenced attachments that cannot be used in room {}", user_id, room_id);
+                        }
+                        Err(e) => error!("Failed to schedule message in room {}: {}", room_id, e),
+                    }
+                    return;
+                }
+
+                let message = NewMessage {
+                    room_id: room_uuid,
+                    user_id,
+                    content: &content,
+                    parent_id,
+                    attachment_ids: &attachment_ids,
+                    ttl_seconds,
+                    quote: quoted.as_ref(),
+                };
+                // The message was composed as the draft, so sending it uses the draft up.
+                if deliver_message(state, storage, message).await == Delivery::Sent {
+                    drafts::clear(state, storage, room_id, user_id, connection_id).await;
+                }
             }
+            ChatCommand::ForwardMessage { message_id, room_id, comment } => {
+                let room_uuid = match Uuid::parse_str(&room_id) {
+                    Ok(uuid) => uuid,
+                    Err(e) => {
+                        error!("Invalid room_id UUID: {}: {}", room_id, e);
+                        return;
+                    }
+                };
 
-            //TODO: add broadcast logic here after db write succeeds
+                match storage.rooms.can_access(room_uuid, user_id).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        warn!("User {} may not forward into room {}", user_id, room_id);
+                        return;
+                    }
+                    Err(e) => {
+                        error!("Failed to check access to room {}: {}", room_id, e);
+                        return;
+                    }
+                }
 
-            let sender = match sqlx::query!("SELECT username FROM users WHERE id = $1", user_id)
-                .fetch_one(pool)
-                .await {
-                    Ok(record) => record,
+                let Some(quoted) = resolve_quote(storage, user_id, message_id, true).await else {
+                    return;
+                };
+                info!("User {} is forwarding message {} to room {}", user_id, message_id, room_id);
+                let message = NewMessage {
+                    room_id: room_uuid,
+                    user_id,
+                    content: &comment,
+                    parent_id: None,
+                    attachment_ids: &[],
+                    ttl_seconds: None,
+                    quote: Some(&quoted),
+                };
+                deliver_message(state, storage, message).await;
+            }
+            ChatCommand::EditMessage { message_id, content } => {
+                match storage.messages.edit_message(message_id, user_id, &content).await {
+                    Ok(Some((room_id, edited_at))) => {
+                        let room_id = room_id.to_string();
+                        let edited = MessageEdited {
+                            r#type: "message edited",
+                            id: message_id,
+                            room_id: room_id.clone(),
+                            content,
+                            edited_at,
+                        };
+                        broadcast_to_room(state, &room_id, &edited);
+                    }
+                    Ok(None) => {
+                        warn!("User {} may not edit message {}", user_id, message_id);
+                    }
+                    Err(e) => error!("Failed to edit message {}: {}", message_id, e),
+                }
+            }
+            ChatCommand::DeleteMessage { message_id } => {
+                match storage.messages.delete_message(message_id, user_id).await {
+                    Ok(Some((room_id, deleted_at))) => {
+                        let room_id = room_id.to_string();
+                        let deleted = MessageDeleted {
+                            r#type: "message deleted",
+                            id: message_id,
+                            room_id: room_id.clone(),
+                            deleted_by: user_id,
+                            deleted_at,
+                        };
+                        broadcast_to_room(state, &room_id, &deleted);
+                    }
+                    Ok(None) => {
+                        warn!("User {} may not delete message {}", user_id, message_id);
+                    }
+                    Err(e) => error!("Failed to delete message {}: {}", message_id, e),
+                }
+            }
+            ChatCommand::React { message_id, emoji } => {
+                Self::change_reaction(message_id, emoji, true, user_id, state, storage).await;
+            }
+            ChatCommand::Unreact { message_id, emoji } => {
+                Self::change_reaction(message_id, emoji, false, user_id, state, storage).await;
+            }
+            ChatCommand::Typing { room_id, is_typing } => {
+                // Only members who joined the room may announce typing in it.
+                let is_member = state
+                    .room_members
+                    .get(&room_id)
+                    .is_some_and(|members| members.contains(&user_id));
+                if !is_member {
+                    warn!("User {} is not in room {}; ignoring typing update", user_id, room_id);
+                    return;
+                }
+                typing::update(state, room_id, user_id, is_typing);
+            }
+            ChatCommand::SaveDraft { room_id, content } => {
+                // Checked against the in-memory membership, as drafts arrive per keystroke.
+                let is_member = state
+                    .room_members
+                    .get(&room_id)
+                    .is_some_and(|members| members.contains(&user_id));
+                if !is_member {
+                    warn!("User {} is not in room {}; ignoring draft", user_id, room_id);
+                    return;
+                }
+                drafts::update(state, storage, room_id, user_id, connection_id, content);
+            }
+            ChatCommand::MarkRead { room_id, message_id, send_receipt } => {
+                let room_uuid = match Uuid::parse_str(&room_id) {
+                    Ok(uuid) => uuid,
                     Err(e) => {
-                        error!("Failed to fetch username: {}: {}",user_id, e);
-                        return; // cannot proceed without sender info
+                        error!("Invalid room_id UUID: {}: {}", room_id, e);
+                        return;
                     }
                 };
+                match storage.rooms.mark_read(room_uuid, user_id, message_id).await {
+                    Ok(true) if send_receipt => {
+                        let receipt = ReadReceipt {
+                            r#type: "read receipt",
+                            room_id: room_id.clone(),
+                            user_id,
+                            message_id,
+                        };
+                        broadcast_to_room_except(state, &room_id, &receipt, Some(&user_id));
+                    }
+                    Ok(_) => {}
+                    Err(e) => error!("Failed to mark room {} read for {}: {}", room_id, user_id, e),
+                }
+            }
+            ChatCommand::PinMessage { message_id } => {
+                Self::change_pin(message_id, true, user_id, state, storage).await;
+            }
+            ChatCommand::UnpinMessage { message_id } => {
+                Self::change_pin(message_id, false, user_id, state, storage).await;
+            }
 
-                let outbound_msg = OutboundMessage {
-                    r#type: "new message",
-                    username: sender.username,
+        }
+    }
+
+    async fn change_pin(
+        message_id: i64,
+        pinned: bool,
+        user_id: Uuid,
+        state: &ChatServerState,
+        storage: &Storage,
+    ) {
+        match storage.messages.set_pinned(message_id, user_id, pinned).await {
+            Ok(Some(room_id)) => {
+                let room_id = room_id.to_string();
+                let changed = PinChanged {
+                    r#type: "pin changed",
+                    message_id,
                     room_id: room_id.clone(),
-                    content,
+                    pinned,
+                    changed_by: user_id,
                 };
-                if let Ok(message_json) = serde_json::to_string(&outbound_msg){
-                    if let Some(members) = state.room_members.get(&room_id){
+                broadcast_to_room(state, &room_id, &changed);
+            }
+            Ok(None) => {
+                warn!("User {} could not change pin on message {}", user_id, message_id);
+            }
+            Err(e) => error!("Failed to change pin on message {}: {}", message_id, e),
+        }
+    }
 
-                        for member_id_ref in members.iter() {
-                            let member_id = member_id_ref.key();
+    async fn change_reaction(
+        message_id: i64,
+        emoji: String,
+        added: bool,
+        user_id: Uuid,
+        state: &ChatServerState,
+        storage: &Storage,
+    ) {
+        if emoji.is_empty() || emoji.len() > MAX_EMOJI_LEN {
+            warn!("Rejected reaction {:?} from user {}", emoji, user_id);
+            return;
+        }
 
-                            if let Some(connection) =  state.connections.get(member_id){
+        match storage.messages.set_reaction(message_id, user_id, &emoji, added).await {
+            Ok(Some((room_id, count))) => {
+                let room_id = room_id.to_string();
+                let changed = ReactionChanged {
+                    r#type: "reaction changed",
+                    message_id,
+                    room_id: room_id.clone(),
+                    emoji,
+                    user_id,
+                    added,
+                    count,
+                };
+                broadcast_to_room(state, &room_id, &changed);
+            }
+            // Unknown/deleted message, or the reaction was already in the requested state.
+            Ok(None) => {}
+            Err(e) => error!("Failed to update reaction on message {}: {}", message_id, e),
+        }
+    }
+}
 
-                                let _ = connection.send(message_json.clone());
-                            }
-                        }
-                    }
+/// What became of a message handed to `deliver_message`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Delivery {
+    Sent,
+    Rejected, // Its attachments cannot be used; trying again will not help.
+    Failed,   // Storage failed; the message may be retried.
+}
+
+/// Stores a message and fans it out to the room, notifying mentioned users.
+/// Shared by `SendMessage` and the scheduler, which have already checked that
+/// the author may post in the room and resolved `parent_id` to a thread root.
+pub(crate) async fn deliver_message(
+    state: &ChatServerState,
+    storage: &Storage,
+    message: NewMessage<'_>,
+) -> Delivery {
+    let (room_uuid, user_id, parent_id) = (message.room_id, message.user_id, message.parent_id);
+    let content = message.content.to_string();
+    let quote = message.quote.cloned();
+    let room_id = room_uuid.to_string();
+    let result = storage.messages.insert_message(message).await;
+
+    let inserted = match result {
+        Ok(Some(record)) => record,
+        Ok(None) => {
+            warn!("User {} referenced attachments that cannot be used in room {}", user_id, room_id);
+            return Delivery::Rejected;
+        }
+        Err(e) => {
+            error!("Failed to insert message: {}", e);
+            return Delivery::Failed;
+        }
+    };
+
+    let sender = match state.users.username(storage.users.as_ref(), user_id).await {
+        Ok(Some(username)) => username,
+        Ok(None) => {
+            error!("Sender {} no longer exists", user_id);
+            return Delivery::Sent;
+        }
+        Err(e) => {
+            error!("Failed to fetch username: {}: {}",user_id, e);
+            return Delivery::Sent; // stored, but cannot fan out without sender info
+        }
+    };
+
+    let outbound_msg = OutboundMessage {
+        id: inserted.id,
+        r#type: "new message",
+        username: sender,
+        room_id: room_id.clone(),
+        content,
+        created_at: inserted.created_at,
+        reply_to: parent_id,
+        attachments: inserted.attachments,
+        expires_at: inserted.expires_at,
+        quote,
+    };
+    broadcast_to_room(state, &room_id, &outbound_msg);
+
+    let mentioned = parse_mentions(&outbound_msg.content);
+    if !mentioned.is_empty() {
+        let notification = MentionNotification {
+            r#type: "mention",
+            message_id: outbound_msg.id,
+            room_id: outbound_msg.room_id,
+            content: outbound_msg.content,
+            username: outbound_msg.username,
+            created_at: outbound_msg.created_at,
+        };
+        match storage.messages.record_mentions(notification.message_id, room_uuid, user_id, &mentioned).await {
+            Ok(user_ids) => {
+                for mentioned_id in &user_ids {
+                    send_to_user(state, mentioned_id, &notification);
                 }
             }
+            Err(e) => error!("Failed to record mentions for message {}: {}", notification.message_id, e),
+        }
+    }
+    Delivery::Sent
+}
+
+/// Copies `message_id` for embedding in a message by `user_id`, who must be
+/// able to read its room. Forwarding a plain forward embeds the original
+/// message instead. Returns `None`, after logging why, if it cannot be embedded.
+pub(crate) async fn resolve_quote(
+    storage: &Storage,
+    user_id: Uuid,
+    message_id: i64,
+    forwarded: bool,
+) -> Option<QuotedMessage> {
+    let source = match storage.messages.quote_source(message_id).await {
+        Ok(Some(source)) => source,
+        Ok(None) => {
+            warn!("Message {} does not exist or cannot be quoted", message_id);
+            return None;
+        }
+        Err(e) => {
+            error!("Failed to look up quoted message {}: {}", message_id, e);
+            return None;
+        }
+    };
 
+    match storage.rooms.can_access(source.message.room_id, user_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            warn!("User {} may not read message {} to quote it", user_id, message_id);
+            return None;
+        }
+        Err(e) => {
+            error!("Failed to check access to room {}: {}", source.message.room_id, e);
+            return None;
         }
     }
-}
\ No newline at end of file
+
+    let mut quoted = match source.quote {
+        Some(original) if forwarded && original.forwarded && source.message.content.is_empty() => original,
+        _ => source.message,
+    };
+    quoted.forwarded = forwarded;
+    Some(quoted)
+}
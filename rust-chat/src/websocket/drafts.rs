@@ -0,0 +1,183 @@
+// src/websocket/drafts.rs
+
+// Unsent drafts, shared between a user's devices. Clients send `save_draft`
+// as the user types; the latest content is kept in `ChatServerState::drafts`
+// and only written to storage once the user pauses, after which the user's
+// other connections are told about it. Sending a message clears the draft.
+
+use chrono::{DateTime, Utc};
+use log::error;
+use serde::Serialize;
+use tokio::time::{sleep_until, Duration, Instant};
+use uuid::Uuid;
+
+use crate::models::{ConnectionId, RoomId, UserId};
+use crate::state::ChatServerState;
+use crate::storage::Storage;
+use crate::websocket::commands::send_to_user_except;
+
+/// A draft is saved once it has not changed for this long.
+const DRAFT_DEBOUNCE: Duration = Duration::from_secs(1);
+
+pub struct PendingDraft {
+    content: String,
+    connection_id: ConnectionId, // Where the draft was last edited.
+    save_at: Instant,
+}
+
+/// Sent to a user's other connections when one of their drafts is saved.
+#[derive(Debug, Serialize)]
+pub struct DraftUpdated {
+    r#type: &'static str,
+    pub room_id: String,
+    content: String, // Empty when the draft was cleared.
+    updated_at: DateTime<Utc>,
+}
+
+/// Records the latest draft of `user_id` in `room_id`. An empty `content`
+/// clears the draft.
+pub fn update(
+    state: &ChatServerState,
+    storage: &Storage,
+    room_id: RoomId,
+    user_id: UserId,
+    connection_id: ConnectionId,
+    content: String,
+) {
+    let key = (room_id, user_id);
+    let save_at = Instant::now() + DRAFT_DEBOUNCE;
+
+    let mut newly_pending = false;
+    state
+        .drafts
+        .entry(key.clone())
+        .and_modify(|draft| {
+            draft.content.clone_from(&content);
+            draft.connection_id = connection_id;
+            draft.save_at = save_at;
+        })
+        .or_insert_with(|| {
+            newly_pending = true;
+            PendingDraft { content, connection_id, save_at }
+        });
+
+    if newly_pending {
+        spawn_save(state.clone(), storage.clone(), key);
+    }
+}
+
+/// Drops the draft of `user_id` in `room_id` after they sent the message it
+/// held: a pending save is cancelled, the stored draft is deleted, and the
+/// user's other connections are told it is gone.
+pub async fn clear(
+    state: &ChatServerState,
+    storage: &Storage,
+    room_id: RoomId,
+    user_id: UserId,
+    connection_id: ConnectionId,
+) {
+    // The task waiting to save the draft finds it gone and gives up.
+    state.drafts.remove(&(room_id.clone(), user_id));
+
+    let room_uuid = match Uuid::parse_str(&room_id) {
+        Ok(uuid) => uuid,
+        Err(e) => {
+            error!("Invalid room_id UUID for draft: {}: {}", room_id, e);
+            return;
+        }
+    };
+    let updated_at = Utc::now();
+    if let Err(e) = storage.rooms.save_draft(room_uuid, user_id, "", updated_at).await {
+        error!("Failed to clear draft of {} in room {}: {}", user_id, room_id, e);
+        return;
+    }
+
+    let event = DraftUpdated {
+        r#type: "draft updated",
+        room_id,
+        content: String::new(),
+        updated_at,
+    };
+    send_to_user_except(state, &user_id, &event, Some(connection_id));
+}
+
+/// Waits for a pending draft to settle, then stores it and pushes it to the
+/// user's other connections.
+fn spawn_save(state: ChatServerState, storage: Storage, key: (RoomId, UserId)) {
+    tokio::spawn(async move {
+        let draft = loop {
+            let save_at = match state.drafts.get(&key) {
+                Some(draft) => draft.save_at,
+                None => return,
+            };
+
+            if Instant::now() < save_at {
+                sleep_until(save_at).await;
+                continue;
+            }
+
+            if let Some((_, draft)) = state.drafts.remove_if(&key, |_, draft| draft.save_at <= Instant::now()) {
+                break draft;
+            }
+        };
+
+        let (room_id, user_id) = key;
+        let room_uuid = match Uuid::parse_str(&room_id) {
+            Ok(uuid) => uuid,
+            Err(e) => {
+                error!("Invalid room_id UUID for draft: {}: {}", room_id, e);
+                return;
+            }
+        };
+        let updated_at = Utc::now();
+        if let Err(e) = storage.rooms.save_draft(room_uuid, user_id, &draft.content, updated_at).await {
+            error!("Failed to save draft of {} in room {}: {}", user_id, room_id, e);
+            return;
+        }
+
+        let event = DraftUpdated {
+            r#type: "draft updated",
+            room_id,
+            content: draft.content,
+            updated_at,
+        };
+        send_to_user_except(&state, &user_id, &event, Some(draft.connection_id));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::commands::ChatCommand;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn sending_a_message_clears_the_draft() {
+        let state = ChatServerState::new();
+        let storage = Storage::memory();
+        let user_id = storage.users.create_user("alice", "hash").await.unwrap().unwrap();
+        let room = storage.rooms.create_room("general", None, None, user_id).await.unwrap().unwrap();
+        let room_id = room.id.to_string();
+        state.room_members.entry(room_id.clone()).or_default().insert(user_id);
+        let connection_id = Uuid::new_v4();
+        let command = |value| serde_json::from_value::<ChatCommand>(value).unwrap();
+        let draft = || async {
+            let rooms = storage.rooms.list_rooms(user_id).await.unwrap();
+            rooms[0].draft.clone()
+        };
+
+        storage.rooms.save_draft(room.id, user_id, "hello wor", Utc::now()).await.unwrap();
+        let save = command(json!({ "type": "save_draft", "room_id": room_id, "content": "hello world" }));
+        ChatCommand::execute(save, user_id, connection_id, &state, &storage).await;
+        assert!(state.drafts.contains_key(&(room_id.clone(), user_id)));
+
+        let send = command(json!({ "type": "send_message", "room_id": room_id, "content": "hello world" }));
+        ChatCommand::execute(send, user_id, connection_id, &state, &storage).await;
+        assert!(state.drafts.is_empty());
+        assert_eq!(draft().await, None);
+
+        // The debounced save that was pending must not bring the draft back.
+        tokio::time::sleep(DRAFT_DEBOUNCE + Duration::from_millis(100)).await;
+        assert_eq!(draft().await, None);
+    }
+}
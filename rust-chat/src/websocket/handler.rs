@@ -3,41 +3,52 @@
 use futures_util::StreamExt;
 use rocket::{get, State};
 use rocket_ws as ws;
-use std::sync::Arc;
 use tokio::sync::mpsc::unbounded_channel;
 use log::{error, info};
 use futures_util::SinkExt;
 
 use crate::state::ChatServerState;
 use crate::websocket::commands::ChatCommand;
+use crate::websocket::typing;
 use crate::handlers::guard::AuthenticatedUser;
-use uuid::Uuid;
-use sqlx::PgPool;
+use crate::storage::Storage;
 
 #[get("/")]
 pub fn ws_handler<'a>(
     ws: ws::WebSocket,
     state: &'a State<ChatServerState>,
     user: AuthenticatedUser,
-    db_pool: &'a State<PgPool>,
+    storage: &'a State<Storage>,
 ) -> ws::Channel<'a> {
-    // Clone the application state and database pool to move them into the async block.
-    // Cloning an Arc or a PgPool is cheap as it only increments a reference counter.
+    // Clone the application state and storage to move them into the async block.
+    // Cloning them is cheap as it only increments reference counters.
     let state = state.inner().clone();
-    let pool = db_pool.inner().clone();
+    let storage = storage.inner().clone();
 
     // The `ws.channel` method takes a closure that will be executed for each new connection.
-    ws.channel(move |mut stream| Box::pin(async move {
+    ws.channel(move |stream| Box::pin(async move {
         // The user_id is extracted from the AuthenticatedUser guard.
         // Its presence here guarantees the user has provided a valid JWT.
         let user_id = user.user_id;
         info!("WebSocket opened for user {}", user_id);
 
-        // Create a multi-producer, single-consumer channel for this specific user.
-        // The `tx` (transmitter) end is stored in the global state, allowing other
-        // parts of the application to send messages to this user.
+        // Create a multi-producer, single-consumer channel for this connection.
+        // The `tx` (transmitter) end is stored in the global state, next to the
+        // user's other connections, allowing other parts of the application to
+        // send messages to this user.
         let (tx, mut rx) = unbounded_channel();
-        state.connections.insert(user_id, tx);
+        let connection_id = state.connect(user_id, tx);
+
+        // Warm the user directory, so this user's messages never wait on a
+        // username lookup.
+        match storage.users.get_users(&[user_id]).await {
+            Ok(users) => {
+                for user in users {
+                    state.users.insert(user.id, user.username);
+                }
+            }
+            Err(e) => error!("Failed to load profile of {}: {}", user_id, e),
+        }
 
         // Split the WebSocket stream into a sender and receiver half.
         // This allows for concurrent reading and writing.
@@ -45,7 +56,7 @@ pub fn ws_handler<'a>(
 
         // Spawn a dedicated asynchronous task to handle incoming messages from the client.
         let state_read = state.clone();
-        let pool_read = pool.clone();
+        let storage_read = storage.clone();
         let read_handle = tokio::spawn(async move {
             // Loop until the client disconnects or an error occurs.
             while let Some(msg) = ws_receiver.next().await {
@@ -58,7 +69,7 @@ pub fn ws_handler<'a>(
                                 // If deserialization is successful, delegate the command
                                 // to the central executor, providing verified context.
                                 //TODO: add in websocket/commands.rs
-                                ChatCommand::execute(cmd, user_id, &state_read, &pool_read).await;
+                                ChatCommand::execute(cmd, user_id, connection_id, &state_read, &storage_read).await;
                             }
                             // Log if the client sends a command that doesn't match the expected format.
                             Err(e) => error!("Malformed cmd from {}: {}", user_id, e),
@@ -72,14 +83,15 @@ pub fn ws_handler<'a>(
             }
             // --- Cleanup Logic ---
             // This code runs only after the `while` loop has been broken.
-            // Remove the user's connection sender from the global state.
-            state_read.connections.remove(&user_id);
-            // Iterate through all rooms and remove the user from the member list.
-            // This is a temporary solution; a more efficient approach would be to track
-            // which rooms the user is in.
-            for mut room in state_read.room_members.iter_mut() {
-                room.value_mut().remove(&user_id);
+            // Remove this connection's sender from the global state. Once the
+            // user's last connection is gone, tell the rooms they were typing
+            // in that they stopped, and drop their cached profile.
+            if state_read.disconnect(user_id, connection_id) {
+                typing::stop_all(&state_read, user_id);
+                state_read.users.remove(&user_id);
             }
+            // Room membership is persistent, so the user stays in `room_members`;
+            // without a connection they simply stop receiving broadcasts.
             info!("Cleaned up user {}", user_id);
         });
 
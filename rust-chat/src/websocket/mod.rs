@@ -1,6 +1,8 @@
 pub mod connection;
 pub mod handler;
 pub mod commands;
+pub mod drafts;
+pub mod typing;
 
 // Re-export the main types for easy access
 //pub use connection::ConnectionManager;
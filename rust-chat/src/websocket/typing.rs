@@ -0,0 +1,120 @@
+// src/websocket/typing.rs
+
+// Ephemeral typing indicators. Nothing here touches the database: state lives
+// in `ChatServerState::typing` and disappears with the process.
+
+use log::debug;
+use serde::Serialize;
+use tokio::time::{sleep_until, Duration, Instant};
+use uuid::Uuid;
+
+use crate::models::{RoomId, UserId};
+use crate::state::ChatServerState;
+use crate::websocket::commands::broadcast_to_room_except;
+
+/// Repeated "still typing" updates are forwarded at most this often.
+const TYPING_THROTTLE: Duration = Duration::from_secs(2);
+/// A user who sends no update for this long is considered to have stopped.
+const TYPING_TIMEOUT: Duration = Duration::from_secs(6);
+
+pub struct TypingStatus {
+    last_sent: Instant,
+    expires_at: Instant,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TypingEvent {
+    r#type: &'static str,
+    pub room_id: String,
+    user_id: Uuid,
+    is_typing: bool,
+}
+
+fn announce(state: &ChatServerState, room_id: &str, user_id: UserId, is_typing: bool) {
+    let event = TypingEvent {
+        r#type: "typing",
+        room_id: room_id.to_string(),
+        user_id,
+        is_typing,
+    };
+    broadcast_to_room_except(state, room_id, &event, Some(&user_id));
+}
+
+/// Records a typing update from `user_id` and forwards it to the rest of the room.
+pub fn update(state: &ChatServerState, room_id: RoomId, user_id: UserId, is_typing: bool) {
+    let key = (room_id, user_id);
+
+    if !is_typing {
+        if state.typing.remove(&key).is_some() {
+            announce(state, &key.0, user_id, false);
+        }
+        return;
+    }
+
+    let now = Instant::now();
+    let mut newly_typing = false;
+    let should_send = {
+        let mut status = state.typing.entry(key.clone()).or_insert_with(|| {
+            newly_typing = true;
+            TypingStatus { last_sent: now, expires_at: now }
+        });
+        status.expires_at = now + TYPING_TIMEOUT;
+        if newly_typing || now.duration_since(status.last_sent) >= TYPING_THROTTLE {
+            status.last_sent = now;
+            true
+        } else {
+            false
+        }
+    };
+
+    if should_send {
+        announce(state, &key.0, user_id, true);
+    }
+    if newly_typing {
+        spawn_expiry(state.clone(), key);
+    }
+}
+
+/// Emits "stopped typing" for every room `user_id` was typing in. Used when the
+/// user's connection closes.
+pub fn stop_all(state: &ChatServerState, user_id: UserId) {
+    let rooms: Vec<RoomId> = state
+        .typing
+        .iter()
+        .filter(|entry| entry.key().1 == user_id)
+        .map(|entry| entry.key().0.clone())
+        .collect();
+
+    for room_id in rooms {
+        if state.typing.remove(&(room_id.clone(), user_id)).is_some() {
+            announce(state, &room_id, user_id, false);
+        }
+    }
+}
+
+/// Watches a typing entry and clears it once the client stops refreshing it.
+fn spawn_expiry(state: ChatServerState, key: (RoomId, UserId)) {
+    tokio::spawn(async move {
+        loop {
+            let expires_at = match state.typing.get(&key) {
+                Some(status) => status.expires_at,
+                None => return, // Stopped explicitly or by disconnect.
+            };
+
+            if Instant::now() < expires_at {
+                sleep_until(expires_at).await;
+                continue;
+            }
+
+            if state
+                .typing
+                .remove_if(&key, |_, status| status.expires_at <= Instant::now())
+                .is_some()
+            {
+                debug!("Typing indicator for {} in {} expired", key.1, key.0);
+                announce(&state, &key.0, key.1, false);
+            }
+            return;
+        }
+    });
+}